@@ -1,37 +1,21 @@
 const invoke = window.__TAURI__.tauri.invoke;
+const listen = window.__TAURI__.event.listen;
 
-export async function invokeCompletion(id, messages) {
-    return await invoke("completion", {id, messages});
+// `args` is one of the command structs of `common::ipc`
+export async function invokeCommand(name, args) {
+    return await invoke(name, {args});
 }
 
-export async function invokeStartConversation(hint) {
-    return await invoke("start_conversation", {hint});
+// resolves to a function that stops listening
+export async function listenEvent(name, callback) {
+    return await listen(name, (event) => callback(event.payload));
 }
 
-export async function invokeGetConversations() {
-    return await invoke("get_conversations");
+// text on the clipboard, empty when there is none or reading it was refused
+export async function readClipboard() {
+    try {
+        return await navigator.clipboard.readText();
+    } catch (e) {
+        return "";
+    }
 }
-
-export async function invokeGetConversation(id) {
-    return await invoke("get_conversation", {id});
-}
-
-export async function invokeGetTitle(id) {
-    return await invoke("get_title", {id});
-}
-
-export async function invokeSetTitle(id, title) {
-    return await invoke("set_title", {id, title});
-}
-
-export async function invokeSuggestTitle(id) {
-    return await invoke("suggest_title", {id});
-}
-
-export async function invokeBundledPrompts() {
-    return await invoke("bundled_prompts");
-}
-
-export async function invokeGenerateImage(req) {
-    return await invoke("generate_image", {req});
-} 
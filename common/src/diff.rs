@@ -0,0 +1,165 @@
+//! Word by word differences between two versions of a text, to show what an
+//! edit of a prompt changed. Whitespace sticks to the word after it.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Change {
+    Same(String),
+    Added(String),
+    Removed(String),
+}
+
+impl Change {
+    pub fn text(&self) -> &str {
+        match self {
+            Change::Same(text) | Change::Added(text) | Change::Removed(text) => text,
+        }
+    }
+}
+
+fn words(text: &str) -> Vec<&str> {
+    let mut words = vec![];
+    let mut start = 0;
+    let mut in_word = false;
+    for (i, c) in text.char_indices() {
+        if !c.is_whitespace() {
+            in_word = true;
+        } else if in_word {
+            words.push(&text[start..i]);
+            start = i;
+            in_word = false;
+        }
+    }
+    if start < text.len() {
+        words.push(&text[start..]);
+    }
+    words
+}
+
+// append `change`, merged into the last one when they are of a kind
+fn push(changes: &mut Vec<Change>, change: Change) {
+    match (changes.last_mut(), change) {
+        (Some(Change::Same(last)), Change::Same(text))
+        | (Some(Change::Added(last)), Change::Added(text))
+        | (Some(Change::Removed(last)), Change::Removed(text)) => last.push_str(&text),
+        (_, change) => changes.push(change),
+    }
+}
+
+/// The changes turning `old` into `new`, keeping as many words as possible.
+/// Where both removed and added words, the removed ones come first.
+pub fn diff(old: &str, new: &str) -> Vec<Change> {
+    let old = words(old);
+    let new = words(new);
+
+    // edits usually touch a few words in the middle, leave the rest out of
+    // the quadratic part
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    // common[i * width + j] is the longest common subsequence of a[i..] and b[j..]
+    let width = b.len() + 1;
+    let mut common = vec![0u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            common[i * width + j] = if a[i] == b[j] {
+                common[(i + 1) * width + j + 1] + 1
+            } else {
+                common[(i + 1) * width + j].max(common[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut changes = vec![];
+    push(&mut changes, Change::Same(old[..prefix].concat()));
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            push(&mut changes, Change::Same(a[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || common[(i + 1) * width + j] >= common[i * width + j + 1]) {
+            push(&mut changes, Change::Removed(a[i].to_string()));
+            i += 1;
+        } else {
+            push(&mut changes, Change::Added(b[j].to_string()));
+            j += 1;
+        }
+    }
+    push(&mut changes, Change::Same(old[old.len() - suffix..].concat()));
+    changes.retain(|c| !c.text().is_empty());
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `new` and `old` put back together from the changes
+    fn sides(changes: &[Change]) -> (String, String) {
+        let mut old = String::new();
+        let mut new = String::new();
+        for change in changes {
+            match change {
+                Change::Same(text) => {
+                    old.push_str(text);
+                    new.push_str(text);
+                }
+                Change::Removed(text) => old.push_str(text),
+                Change::Added(text) => new.push_str(text),
+            }
+        }
+        (old, new)
+    }
+
+    #[test]
+    fn equal_texts_are_one_change() {
+        assert_eq!(diff("same words here", "same words here"), [Change::Same("same words here".into())]);
+        assert!(diff("", "").is_empty());
+    }
+
+    #[test]
+    fn replaced_word_is_removed_then_added() {
+        assert_eq!(
+            diff("act as a poet", "act as a critic"),
+            [
+                Change::Same("act as a".into()),
+                Change::Removed(" poet".into()),
+                Change::Added(" critic".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn whitespace_sticks_to_the_next_word() {
+        assert_eq!(
+            diff("one three", "one two three"),
+            [Change::Same("one".into()), Change::Added(" two".into()), Change::Same(" three".into())]
+        );
+    }
+
+    #[test]
+    fn changes_add_up_to_both_texts() {
+        let pairs = [
+            ("", "all new"),
+            ("all gone", ""),
+            ("You are a helpful assistant.\nAnswer briefly.", "You are a terse assistant.\n\nAnswer in one line."),
+            ("a b c d e", "e d c b a"),
+        ];
+        for (old, new) in pairs {
+            let changes = diff(old, new);
+            assert_eq!(sides(&changes), (old.to_string(), new.to_string()));
+            assert!(changes.iter().all(|c| !c.text().is_empty()));
+            // neighbours of a kind are merged
+            assert!(changes.windows(2).all(|w| std::mem::discriminant(&w[0]) != std::mem::discriminant(&w[1])));
+        }
+    }
+}
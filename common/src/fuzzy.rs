@@ -0,0 +1,165 @@
+//! Fuzzy matching for picking prompts by a few typed letters. The letters of
+//! the query have to appear in order, matches at the start of words and runs
+//! of consecutive letters rank higher.
+
+use crate::Prompt;
+
+const MATCH: i64 = 16;
+const CONSECUTIVE: i64 = 8;
+const WORD_START: i64 = 10;
+// per letter skipped between two matches, capped so long texts still match
+const GAP: i64 = 1;
+const MAX_GAP_PENALTY: i64 = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub score: i64,
+    // indices of the matched chars of the text
+    pub positions: Vec<usize>,
+}
+
+fn fold(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn word_start(text: &[char], i: usize) -> bool {
+    match i.checked_sub(1).map(|p| text[p]) {
+        None => true,
+        Some(prev) => !prev.is_alphanumeric() || (prev.is_lowercase() && text[i].is_uppercase()),
+    }
+}
+
+/// how well `query` matches `text`, `None` when some letter of it is missing,
+/// whitespace in the query is ignored
+pub fn fuzzy_match(query: &str, text: &str) -> Option<Match> {
+    let query: Vec<char> = query.chars().filter(|c| !c.is_whitespace()).map(fold).collect();
+    let text: Vec<char> = text.chars().collect();
+    if query.is_empty() {
+        return Some(Match { score: 0, positions: vec![] });
+    }
+
+    // the first place the whole query fits, then walk back from its end to
+    // pull the match together as tight as it goes
+    let mut end = None;
+    let mut q = 0;
+    for (i, &c) in text.iter().enumerate() {
+        if fold(c) == query[q] {
+            q += 1;
+            if q == query.len() {
+                end = Some(i);
+                break;
+            }
+        }
+    }
+    let end = end?;
+
+    let mut positions = vec![0; query.len()];
+    let mut q = query.len();
+    for i in (0..=end).rev() {
+        if fold(text[i]) == query[q - 1] {
+            q -= 1;
+            positions[q] = i;
+            if q == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut score = 0;
+    for (n, &i) in positions.iter().enumerate() {
+        score += MATCH;
+        if word_start(&text, i) {
+            score += WORD_START;
+        }
+        match n.checked_sub(1).map(|p| positions[p]) {
+            Some(prev) if prev + 1 == i => score += CONSECUTIVE,
+            Some(prev) => score -= ((i - prev - 1) as i64 * GAP).min(MAX_GAP_PENALTY),
+            None => score -= (i as i64 * GAP).min(MAX_GAP_PENALTY),
+        }
+    }
+    Some(Match { score, positions })
+}
+
+/// Prompts matching `query`, best first. Those whose name matches come
+/// before those where only the content has every word of the query, ties
+/// keep the order of `prompts`.
+pub fn rank_prompts<'p>(query: &str, prompts: &'p [Prompt]) -> Vec<&'p Prompt> {
+    let words: Vec<String> = query.split_whitespace().map(|w| w.to_lowercase()).collect();
+    if words.is_empty() {
+        return prompts.iter().collect();
+    }
+
+    let mut ranked = prompts
+        .iter()
+        .filter_map(|p| {
+            if let Some(m) = fuzzy_match(query, &p.act) {
+                return Some(((0, -m.score), p));
+            }
+            let content = p.content.to_lowercase();
+            let first = words
+                .iter()
+                .map(|w| content.find(w.as_str()))
+                .collect::<Option<Vec<_>>>()?
+                .into_iter()
+                .min()
+                .unwrap_or(0);
+            Some(((1, first as i64), p))
+        })
+        .collect::<Vec<_>>();
+    ranked.sort_by_key(|(rank, _)| *rank);
+    ranked.into_iter().map(|(_, p)| p).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names<'p>(ranked: &[&'p Prompt]) -> Vec<&'p str> {
+        ranked.iter().map(|p| p.act.as_str()).collect()
+    }
+
+    #[test]
+    fn letters_have_to_come_in_order() {
+        assert!(fuzzy_match("lnx", "Linux Terminal").is_some());
+        assert!(fuzzy_match("tl", "Linux Terminal").is_some());
+        assert!(fuzzy_match("lt x", "Linux Terminal").is_none());
+        assert!(fuzzy_match("linuxz", "Linux Terminal").is_none());
+        assert_eq!(fuzzy_match("", "anything"), Some(Match { score: 0, positions: vec![] }));
+    }
+
+    #[test]
+    fn case_and_query_whitespace_are_ignored() {
+        let m = fuzzy_match("L T", "linux terminal").unwrap();
+        assert_eq!(m.positions, [0, 6]);
+    }
+
+    #[test]
+    fn match_is_pulled_together() {
+        // the first `a` fits too, the `a` right before the `b` is kept
+        let m = fuzzy_match("ab", "a cab").unwrap();
+        assert_eq!(m.positions, [3, 4]);
+    }
+
+    #[test]
+    fn word_starts_and_runs_score_higher() {
+        let start = fuzzy_match("jsc", "JavaScript Console").unwrap();
+        let middle = fuzzy_match("jsc", "adjust scale").unwrap();
+        assert!(start.score > middle.score);
+
+        let run = fuzzy_match("term", "Terminal").unwrap();
+        let spread = fuzzy_match("term", "Teacher of Rome").unwrap();
+        assert!(run.score > spread.score);
+    }
+
+    #[test]
+    fn names_rank_before_content() {
+        let prompts = vec![
+            Prompt::new("Storyteller".to_string(), "tell stories about a linux box".to_string()),
+            Prompt::new("Linux Terminal".to_string(), "act as a shell".to_string()),
+            Prompt::new("Poet".to_string(), "write poems".to_string()),
+        ];
+        assert_eq!(names(&rank_prompts("linux", &prompts)), ["Linux Terminal", "Storyteller"]);
+        assert_eq!(names(&rank_prompts("  ", &prompts)), ["Storyteller", "Linux Terminal", "Poet"]);
+        assert!(rank_prompts("zzz", &prompts).is_empty());
+    }
+}
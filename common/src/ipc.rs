@@ -0,0 +1,121 @@
+//! The commands the frontend invokes on the backend, defined once for both
+//! sides. Every command is a struct of its arguments, named after it, that
+//! knows the name the backend registers it under and what it answers with.
+//! The struct is passed whole as the `args` argument of the command.
+
+use std::collections::HashMap;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::tokens::TokenBudget;
+use crate::*;
+
+pub trait Command: Serialize + DeserializeOwned {
+    const NAME: &'static str;
+    type Output: Serialize + DeserializeOwned;
+}
+
+/// what a backend command returns for `C`
+pub type Reply<C> = Result<<C as Command>::Output, String>;
+
+macro_rules! commands {
+    ($(
+        $(#[$doc:meta])*
+        $name:ident => $command:ident { $($field:ident: $ty:ty),* $(,)? } -> $output:ty;
+    )*) => {
+        $(
+            $(#[$doc])*
+            #[derive(Debug, Clone, Serialize, Deserialize)]
+            pub struct $command {
+                $(pub $field: $ty,)*
+            }
+
+            impl Command for $command {
+                const NAME: &'static str = stringify!($name);
+                type Output = $output;
+            }
+        )*
+    };
+}
+
+/// Expand `$callback!` with the list of commands, in the grammar `commands!`
+/// takes, so the backend generates its handlers from the same list. Types in
+/// the list resolve where they are used, a callback only uses the names.
+#[macro_export]
+macro_rules! with_commands {
+    ($callback:ident) => {
+        $callback! {
+            /// start a conversation, optionally with a system prompt and the library
+            /// prompt it came from
+            start_conversation => StartConversation { hint: Option<String>, prompt: Option<PromptRef> } -> ConversationId;
+            get_conversations => GetConversations {} -> Vec<ConversationId>;
+            /// the active branch with title and meta
+            get_conversation => GetConversation { id: ConversationId } -> Conversation;
+            delete_conversation => DeleteConversation { id: ConversationId } -> ();
+            get_conversation_tree => GetConversationTree { id: ConversationId } -> ConversationTree;
+            /// the active branch, newest first, `limit` messages before `before`
+            get_messages => GetMessages { id: ConversationId, before: Option<MessageId>, limit: usize } -> Page<PathNode, MessageId>;
+            /// append a user turn and answer it, fails with `CONFLICT_ERROR` on concurrent writes
+            append_user_message => AppendUserMessage { id: ConversationId, content: Content } -> Message;
+            switch_branch => SwitchBranch { id: ConversationId, message_id: MessageId } -> Page<PathNode, MessageId>;
+            /// fork the user message `message_id` with new content and answer it
+            edit_message => EditMessage { id: ConversationId, message_id: MessageId, content: Content } -> Page<PathNode, MessageId>;
+            get_meta => GetMeta { id: ConversationId } -> ConversationMeta;
+            set_meta => SetMeta { id: ConversationId, meta: ConversationMeta } -> ();
+            filter_conversations => FilterConversations { filter: ConversationFilter } -> Vec<ConversationEntry>;
+            list_conversations => ListConversations {
+                filter: ConversationFilter,
+                after: Option<ConversationCursor>,
+                limit: usize,
+            } -> Page<ConversationSummary, ConversationCursor>;
+            get_summary => GetSummary { id: ConversationId } -> ConversationSummary;
+            /// tokens the active branch takes up of the context of the configured model
+            token_budget => GetTokenBudget { id: ConversationId } -> TokenBudget;
+            search => Search { query: String } -> Vec<SearchHit>;
+            get_title => GetTitle { id: ConversationId } -> String;
+            set_title => SetTitle { id: ConversationId, title: String } -> ();
+            /// ask the model for a title and store it
+            suggest_title => SuggestTitle { id: ConversationId } -> String;
+            /// the prompt library, sorted with `Prompt::sort`
+            list_prompts => ListPrompts {} -> Vec<Prompt>;
+            /// add `prompt`, or replace the one with its id, a new name or content makes a new version
+            save_prompt => SavePrompt { prompt: Prompt } -> Prompt;
+            delete_prompt => DeletePrompt { id: PromptId } -> ();
+            /// every version of a prompt, oldest first
+            prompt_versions => GetPromptVersions { id: PromptId } -> Vec<PromptVersion>;
+            /// answer `input` once with each prompt version, their variables
+            /// filled with `values`, without storing anything
+            compare_prompts => ComparePrompts {
+                a: PromptRef,
+                b: PromptRef,
+                input: String,
+                values: HashMap<String, String>,
+            } -> (Message, Message);
+            /// add prompts from a `.json` or `.csv` file, skipping ones already there
+            import_prompts => ImportPrompts { path: String } -> ImportSummary;
+            /// write the library to a `.json` or `.csv` file, returns how many were written
+            export_prompts => ExportPrompts { path: String } -> usize;
+            generate_image => GenerateImage { params: GenerateImageParams } -> GenerateImageResult;
+            /// read a `conversations.json` export
+            import_conversations => ImportConversations { path: String } -> ImportSummary;
+            encryption_status => GetEncryptionStatus {} -> EncryptionStatus;
+            unlock => Unlock { passphrase: String } -> ();
+            enable_encryption => EnableEncryption { passphrase: String } -> ();
+            rekey => Rekey { old_passphrase: String, new_passphrase: String } -> ();
+            /// seal a value for the settings file
+            encrypt_secret => EncryptSecret { value: String } -> String;
+            create_backup => CreateBackup { path: String, include_secrets: bool } -> ();
+            restore_backup => RestoreBackup { path: String } -> BackupSummary;
+            list_snapshots => ListSnapshots {} -> Vec<SnapshotInfo>;
+            take_snapshot => TakeSnapshot {} -> SnapshotInfo;
+            retention_preview => GetRetentionPreview {} -> RetentionPreview;
+            apply_retention => ApplyRetention {} -> PurgeSummary;
+            storage_info => GetStorageInfo {} -> StorageInfo;
+            /// copy everything to another backend and switch to it, progress comes as `MIGRATION_EVENT`
+            migrate_storage => MigrateStorage { from: StorageBackend, to: StorageBackend } -> usize;
+        }
+    };
+}
+
+with_commands!(commands);
@@ -1,17 +1,214 @@
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
+use uuid::{NoContext, Timestamp, Uuid};
 
-#[derive(Serialize, Deserialize, Debug)]
+pub mod diff;
+pub mod fuzzy;
+pub mod ipc;
+pub mod template;
+pub mod tokens;
+pub mod tree;
+pub use tree::{ConversationTree, MessageNode};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImageModel {
+    #[serde(rename = "dall-e-2")]
+    DallE2,
+    #[serde(rename = "dall-e-3")]
+    DallE3,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImageSize {
+    #[serde(rename = "256x256")]
+    Small,
+    #[serde(rename = "512x512")]
+    Medium,
+    #[serde(rename = "1024x1024")]
+    Large,
+    #[serde(rename = "1792x1024")]
+    Wide,
+    #[serde(rename = "1024x1792")]
+    Tall,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageQuality {
+    Standard,
+    Hd,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageStyle {
+    Vivid,
+    Natural,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageFormat {
+    // links expire after an hour
+    Url,
+    B64Json,
+}
+
+impl ImageModel {
+    pub const ALL: &'static [ImageModel] = &[ImageModel::DallE2, ImageModel::DallE3];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImageModel::DallE2 => "dall-e-2",
+            ImageModel::DallE3 => "dall-e-3",
+        }
+    }
+
+    pub fn sizes(&self) -> &'static [ImageSize] {
+        match self {
+            ImageModel::DallE2 => &[ImageSize::Small, ImageSize::Medium, ImageSize::Large],
+            ImageModel::DallE3 => &[ImageSize::Large, ImageSize::Wide, ImageSize::Tall],
+        }
+    }
+
+    // images per request
+    pub fn max_n(&self) -> u8 {
+        match self {
+            ImageModel::DallE2 => 10,
+            ImageModel::DallE3 => 1,
+        }
+    }
+
+    // in characters
+    pub fn max_prompt_len(&self) -> usize {
+        match self {
+            ImageModel::DallE2 => 1000,
+            ImageModel::DallE3 => 4000,
+        }
+    }
+
+    // quality and style are only taken by dall-e-3
+    pub fn has_quality_and_style(&self) -> bool {
+        *self == ImageModel::DallE3
+    }
+}
+
+impl ImageSize {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImageSize::Small => "256x256",
+            ImageSize::Medium => "512x512",
+            ImageSize::Large => "1024x1024",
+            ImageSize::Wide => "1792x1024",
+            ImageSize::Tall => "1024x1792",
+        }
+    }
+}
+
+impl ImageQuality {
+    pub const ALL: &'static [ImageQuality] = &[ImageQuality::Standard, ImageQuality::Hd];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImageQuality::Standard => "standard",
+            ImageQuality::Hd => "hd",
+        }
+    }
+}
+
+impl ImageStyle {
+    pub const ALL: &'static [ImageStyle] = &[ImageStyle::Vivid, ImageStyle::Natural];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImageStyle::Vivid => "vivid",
+            ImageStyle::Natural => "natural",
+        }
+    }
+}
+
+impl ImageFormat {
+    pub const ALL: &'static [ImageFormat] = &[ImageFormat::B64Json, ImageFormat::Url];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImageFormat::Url => "url",
+            ImageFormat::B64Json => "b64_json",
+        }
+    }
+}
+
+/// Parameters of the image generation api. Build them with `new` and check
+/// them with `validate` before sending, the api rejects bad combinations.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct GenerateImageParams {
+    pub model: ImageModel,
     pub prompt: String,
-    pub n: usize,
-    pub response_format: String,
-    pub size: String,
+    pub n: u8,
+    pub size: ImageSize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<ImageQuality>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub style: Option<ImageStyle>,
+    pub response_format: ImageFormat,
 }
 
+impl GenerateImageParams {
+    /// one image of the smallest size `model` makes
+    pub fn new(model: ImageModel, prompt: String) -> Self {
+        GenerateImageParams {
+            model,
+            prompt,
+            n: 1,
+            size: model.sizes()[0],
+            quality: None,
+            style: None,
+            response_format: ImageFormat::B64Json,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let model = self.model.as_str();
+        if self.prompt.trim().is_empty() {
+            return Err("the prompt is empty".to_string());
+        }
+        if self.prompt.chars().count() > self.model.max_prompt_len() {
+            return Err(format!("{} takes prompts of up to {} characters", model, self.model.max_prompt_len()));
+        }
+        if self.n == 0 || self.n > self.model.max_n() {
+            return Err(match self.model.max_n() {
+                1 => format!("{} makes one image at a time", model),
+                max => format!("{} makes 1 to {} images at a time", model, max),
+            });
+        }
+        if !self.model.sizes().contains(&self.size) {
+            return Err(format!("{} can't make {} images", model, self.size.as_str()));
+        }
+        if !self.model.has_quality_and_style() && (self.quality.is_some() || self.style.is_some()) {
+            return Err(format!("{} has no quality or style settings", model));
+        }
+        Ok(())
+    }
+}
+
+/// one generated image, `url` or `b64_json` depending on the requested format
 #[derive(Serialize, Debug, Clone, Deserialize)]
 pub struct ImageData {
-    pub b64_json: String,
+    #[serde(default)]
+    pub b64_json: Option<String>,
+    #[serde(default)]
+    pub url: Option<String>,
+    // the prompt dall-e-3 actually used
+    #[serde(default)]
+    pub revised_prompt: Option<String>,
+}
+
+impl ImageData {
+    /// something an `img` element can show
+    pub fn src(&self) -> Option<String> {
+        self.url
+            .clone()
+            .or_else(|| self.b64_json.as_ref().map(|data| format!("data:image/png;base64,{}", data)))
+    }
 }
 
 #[derive(Serialize, Debug, Clone, Deserialize)]
@@ -20,91 +217,709 @@ pub struct GenerateImageResult {
     pub data: Vec<ImageData>,
 }
 
+#[derive(Debug, Clone, Default, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageMeta {
+    pub model: Option<String>,
+    pub finish_reason: Option<String>,
+    pub latency_ms: Option<u64>,
+    pub prompt_tokens: Option<usize>,
+    pub completion_tokens: Option<usize>,
+}
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Message {
-    pub role: String,
-    pub content: String,
+    // messages stored without one get theirs when the storage is opened
+    pub id: MessageId,
+    pub role: Role,
+    pub content: Content,
+    // unix time in milliseconds
+    pub created_at: u64,
+    #[serde(default)]
+    pub meta: MessageMeta,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PromptId(pub Uuid);
+
+impl PromptId {
+    pub fn new() -> Self {
+        PromptId(Uuid::new_v4())
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+impl Default for PromptId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A system prompt of the library. `act` names it, prompts imported without
+/// an id get a new one.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct Prompt {
+    #[serde(default)]
+    pub id: PromptId,
+    pub act: String,
+    pub content: String,
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub favorite: bool,
+    // the latest of its versions, 0 for prompts stored before they had any
+    #[serde(default)]
+    pub version: u32,
+}
+
+/// a version of a library prompt, conversations remember the one they were
+/// started with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PromptRef {
+    pub id: PromptId,
+    pub version: u32,
+}
+
+/// A library prompt as one of the saves that changed its name or content
+/// left it. Every prompt counts its versions up from 1.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PromptVersion {
+    pub prompt: PromptId,
+    pub version: u32,
     pub act: String,
     pub content: String,
+    // unix time in milliseconds
+    pub saved_at: u64,
+}
+
+impl PromptVersion {
+    /// `prompt` as it is now, under its current version
+    pub fn new(prompt: &Prompt, saved_at: u64) -> Self {
+        PromptVersion {
+            prompt: prompt.id,
+            version: prompt.version,
+            act: prompt.act.clone(),
+            content: prompt.content.clone(),
+            saved_at,
+        }
+    }
+
+    pub fn reference(&self) -> PromptRef {
+        PromptRef {
+            id: self.prompt,
+            version: self.version,
+        }
+    }
+}
+
+impl Prompt {
+    pub fn new(act: impl Into<String>, content: impl Into<String>) -> Self {
+        Prompt {
+            id: PromptId::new(),
+            act: act.into(),
+            content: content.into(),
+            category: None,
+            favorite: false,
+            version: 0,
+        }
+    }
+
+    pub fn reference(&self) -> PromptRef {
+        PromptRef {
+            id: self.id,
+            version: self.version,
+        }
+    }
+
+    /// favourites first, then by category and name, uncategorised ones last
+    pub fn sort(prompts: &mut [Prompt]) {
+        prompts.sort_by(|a, b| {
+            b.favorite
+                .cmp(&a.favorite)
+                .then_with(|| a.category.is_none().cmp(&b.category.is_none()))
+                .then_with(|| a.category.cmp(&b.category))
+                .then_with(|| a.act.to_lowercase().cmp(&b.act.to_lowercase()))
+        });
+    }
 }
 
 impl Message {
-    pub fn new_user(content: String) -> Self {
+    pub fn new(role: Role, content: impl Into<Content>) -> Self {
+        let ts = Timestamp::now(NoContext);
+        let (secs, nanos) = ts.to_unix();
         Message {
-            role: <KnownRoles as Into<&str>>::into(KnownRoles::User).into(),
-            content,
+            id: MessageId(Uuid::new_v7(ts)),
+            role,
+            content: content.into(),
+            created_at: secs * 1000 + (nanos / 1_000_000) as u64,
+            meta: MessageMeta::default(),
         }
     }
 
-    pub fn new_system(content: String) -> Self {
-        Message {
-            role: <KnownRoles as Into<&str>>::into(KnownRoles::System).into(),
-            content,
+    pub fn new_user(content: impl Into<Content>) -> Self {
+        Self::new(Role::User, content)
+    }
+
+    pub fn new_system(content: impl Into<Content>) -> Self {
+        Self::new(Role::System, content)
+    }
+
+    pub fn new_assistant(content: impl Into<Content>) -> Self {
+        Self::new(Role::Assistant, content)
+    }
+}
+
+/// The body of a message, sent and stored like the chat api takes it: a
+/// plain string, or a list of parts when images are attached.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Content {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ImageUrl {
+    // a web address or a `data:image/...;base64,` url
+    pub url: String,
+    // `low`, `high` or `auto`, left to the api when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// Whether chat `model` reads images. Unknown names are taken to be older
+/// text-only models, the api rejects images sent to those; the backend
+/// settings can say otherwise.
+pub fn model_accepts_images(model: &str) -> bool {
+    const VISION: [&str; 8] = ["gpt-4o", "gpt-4-turbo", "gpt-4-vision", "gpt-4.1", "gpt-4.5", "gpt-5", "o3", "o4"];
+    (VISION.iter().any(|prefix| model.starts_with(prefix)) || model == "o1" || model.starts_with("o1-2"))
+        && !model.contains("audio")
+        && !model.starts_with("o3-mini")
+}
+
+impl Content {
+    /// `text` followed by `images`, plain text when there are no images
+    pub fn with_images(text: String, images: Vec<String>) -> Self {
+        if images.is_empty() {
+            return Content::Text(text);
         }
+        let mut parts = vec![ContentPart::Text { text }];
+        parts.extend(images.into_iter().map(|url| ContentPart::ImageUrl {
+            image_url: ImageUrl { url, detail: None },
+        }));
+        Content::Parts(parts)
     }
 
-    pub fn new_assistant(content: String) -> Self {
-        Message {
-            role: <KnownRoles as Into<&str>>::into(KnownRoles::Assistant).into(),
-            content,
+    /// all text parts, one per line
+    pub fn text(&self) -> String {
+        match self {
+            Content::Text(text) => text.clone(),
+            Content::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    pub fn images(&self) -> Vec<&ImageUrl> {
+        match self {
+            Content::Text(_) => vec![],
+            Content::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::ImageUrl { image_url } => Some(image_url),
+                    _ => None,
+                })
+                .collect(),
+        }
+    }
+
+    /// the same images with `text` instead of the current text
+    pub fn replace_text(&self, text: String) -> Self {
+        let images = self.images().into_iter().map(|image| image.url.clone()).collect();
+        Content::with_images(text, images)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text().trim().is_empty() && self.images().is_empty()
+    }
+
+    /// apply `f` to every string carried, text and image urls alike
+    pub fn map(&self, mut f: impl FnMut(&str) -> String) -> Self {
+        match self.try_map::<std::convert::Infallible>(|s| Ok(f(s))) {
+            Ok(content) => content,
+            Err(never) => match never {},
         }
     }
+
+    /// `map` with a function that can fail
+    pub fn try_map<E>(&self, mut f: impl FnMut(&str) -> Result<String, E>) -> Result<Self, E> {
+        Ok(match self {
+            Content::Text(text) => Content::Text(f(text)?),
+            Content::Parts(parts) => Content::Parts(
+                parts
+                    .iter()
+                    .map(|part| {
+                        Ok(match part {
+                            ContentPart::Text { text } => ContentPart::Text { text: f(text)? },
+                            ContentPart::ImageUrl { image_url } => ContentPart::ImageUrl {
+                                image_url: ImageUrl {
+                                    url: f(&image_url.url)?,
+                                    detail: image_url.detail.clone(),
+                                },
+                            },
+                        })
+                    })
+                    .collect::<Result<_, E>>()?,
+            ),
+        })
+    }
+}
+
+impl Default for Content {
+    fn default() -> Self {
+        Content::Text(String::new())
+    }
+}
+
+impl From<String> for Content {
+    fn from(text: String) -> Self {
+        Content::Text(text)
+    }
+}
+
+impl From<&str> for Content {
+    fn from(text: &str) -> Self {
+        Content::Text(text.to_string())
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ConversationId(pub Uuid);
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MessageId(pub Uuid);
+
+impl MessageId {
+    pub fn new() -> Self {
+        MessageId(Uuid::now_v7())
+    }
+}
+
+impl Default for MessageId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// user supplied organisation of a conversation in the sidebar, and the
+/// prompt it was started with
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ConversationMeta {
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub folder: Option<String>,
+    #[serde(default)]
+    pub pinned: bool,
+    // the library prompt it was started with
+    #[serde(default)]
+    pub prompt: Option<PromptRef>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ConversationEntry {
+    pub id: ConversationId,
+    pub meta: ConversationMeta,
+    // unix time in milliseconds of the newest message
+    #[serde(default)]
+    pub updated_at: u64,
+}
+
+pub const CONVERSATION_PAGE_SIZE: usize = 50;
+pub const MESSAGE_PAGE_SIZE: usize = 30;
+
+/// A slice of a longer list, `next` is passed back to get the following
+/// slice and is `None` once the end was reached.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Page<T, C> {
+    pub items: Vec<T>,
+    pub next: Option<C>,
+}
+
+/// conversations are listed pinned first, then newest first, ties broken by id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ConversationCursor {
+    #[serde(default)]
+    pub pinned: bool,
+    pub updated_at: u64,
+    pub id: ConversationId,
+}
+
+impl ConversationCursor {
+    /// whether `self` is listed before `other`
+    pub fn before(&self, other: &ConversationCursor) -> bool {
+        (self.pinned, self.updated_at, self.id.0) > (other.pinned, other.updated_at, other.id.0)
+    }
+}
+
+impl ConversationEntry {
+    pub fn cursor(&self) -> ConversationCursor {
+        ConversationCursor {
+            pinned: self.meta.pinned,
+            updated_at: self.updated_at,
+            id: self.id,
+        }
+    }
+}
+
+/// a message of the active branch and all alternatives to it
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PathNode {
+    pub node: MessageNode,
+    pub siblings: Vec<MessageId>,
+}
+
+/// unset fields match everything
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConversationFilter {
+    pub tag: Option<String>,
+    pub folder: Option<String>,
+    pub pinned: Option<bool>,
+}
+
+impl ConversationFilter {
+    pub fn matches(&self, meta: &ConversationMeta) -> bool {
+        self.tag
+            .as_ref()
+            .map(|tag| meta.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+            .unwrap_or(true)
+            && self
+                .folder
+                .as_ref()
+                .map(|folder| meta.folder.as_ref() == Some(folder))
+                .unwrap_or(true)
+            && self.pinned.map(|p| meta.pinned == p).unwrap_or(true)
+    }
+}
+
+/// a conversation with everything shown when opening it, `messages` is the
+/// active branch oldest first
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Conversation {
     pub id: ConversationId,
-    pub title: String,
+    // `None` until the conversation got a title
+    pub title: Option<String>,
+    pub meta: ConversationMeta,
     pub messages: Vec<Message>,
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum KnownRoles {
+pub const PREVIEW_LEN: usize = 80;
+
+/// what the sidebar shows of a conversation
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ConversationSummary {
+    pub id: ConversationId,
+    pub title: Option<String>,
+    pub meta: ConversationMeta,
+    // unix time in milliseconds of the newest message
+    pub updated_at: u64,
+    // first line of the newest message that is not a system prompt, at most
+    // `PREVIEW_LEN` characters
+    pub preview: String,
+}
+
+impl ConversationSummary {
+    pub fn cursor(&self) -> ConversationCursor {
+        ConversationCursor {
+            pinned: self.meta.pinned,
+            updated_at: self.updated_at,
+            id: self.id,
+        }
+    }
+
+    /// the title, or the preview for conversations without one
+    pub fn label(&self) -> &str {
+        match &self.title {
+            Some(title) if !title.is_empty() => title,
+            _ if !self.preview.is_empty() => &self.preview,
+            _ => "new conversation",
+        }
+    }
+}
+
+/// the first line of `text`, shortened to `PREVIEW_LEN` characters
+pub fn preview(text: &str) -> String {
+    let line = text.trim().lines().next().unwrap_or_default().trim();
+    if line.chars().count() <= PREVIEW_LEN {
+        return line.to_string();
+    }
+    let mut short: String = line.chars().take(PREVIEW_LEN - 1).collect();
+    short.truncate(short.trim_end().len());
+    short.push('…');
+    short
+}
+
+/// returned when a conversation changed between reading and writing it,
+/// reloading it and trying again resolves it
+pub const CONFLICT_ERROR: &str = "conversation was changed in the meantime";
+
+/// returned by every storage backend when asked for a conversation it doesn't have
+pub const NOT_FOUND_ERROR: &str = "no conversation found";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub conversation: ConversationId,
+    pub message: MessageId,
+    pub snippet: String,
+    // byte ranges of `snippet` matching the query
+    pub highlights: Vec<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ImportSummary {
+    pub imported: usize,
+    pub skipped: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct BackupSummary {
+    pub conversations: usize,
+    pub files: usize,
+    #[serde(default)]
+    pub prompts: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    pub path: String,
+    // unix time in milliseconds
+    pub created_at: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct EncryptionStatus {
+    pub enabled: bool,
+    // storage can't be used until `unlock` is called with the passphrase
+    pub locked: bool,
+}
+
+/// What happens to conversations the retention policy purges.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RetentionAction {
+    // written to an archive in the config dir first, restorable like a backup,
+    // so it frees no space
+    Archive,
+    #[default]
+    Delete,
+}
+
+/// The `[retention]` table of the settings, everything is kept by default.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    // purge conversations without a new message for this many days, 0 turns it off
+    pub max_age_days: u64,
+    // purge the oldest conversations until the ones not kept anyway take
+    // less, 0 turns it off
+    pub max_total_bytes: u64,
+    pub action: RetentionAction,
+    pub keep_pinned: bool,
+    // conversations having any tag
+    pub keep_tagged: bool,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            max_age_days: 0,
+            max_total_bytes: 0,
+            action: RetentionAction::default(),
+            keep_pinned: true,
+            keep_tagged: true,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.max_age_days > 0 || self.max_total_bytes > 0
+    }
+
+    pub fn keeps(&self, meta: &ConversationMeta) -> bool {
+        (self.keep_pinned && meta.pinned) || (self.keep_tagged && !meta.tags.is_empty())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PurgeReason {
+    Age,
+    Size,
+}
+
+/// a conversation the retention policy would purge now
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PurgeCandidate {
+    pub id: ConversationId,
+    pub title: Option<String>,
+    // unix time in milliseconds of the newest message
+    pub updated_at: u64,
+    pub size: u64,
+    pub reason: PurgeReason,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionPreview {
+    pub policy: RetentionPolicy,
+    // oldest first
+    pub candidates: Vec<PurgeCandidate>,
+    // size of every conversation stored, purged or not
+    pub total_size: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PurgeSummary {
+    pub purged: usize,
+    // where purged conversations were archived to
+    pub archive: Option<String>,
+}
+
+/// Where conversations are kept, picked by `storage_backend` in the settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    // nothing survives a restart
+    Memory,
+    Sled,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageInfo {
+    pub current: StorageBackend,
+    // the backends built into this app
+    pub available: Vec<StorageBackend>,
+}
+
+/// name of the event migration progress is emitted to the frontend with
+pub const MIGRATION_EVENT: &str = "storage-migration";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationProgress {
+    pub from: StorageBackend,
+    pub to: StorageBackend,
+    // conversations copied so far
+    pub done: usize,
+    pub total: usize,
+}
+
+/// name of the event storage changes are emitted to the frontend with
+pub const STORAGE_EVENT: &str = "storage-change";
+
+/// A change made to storage, by this window or any other. Only ids are
+/// carried, views reload what they show when they get one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "id")]
+pub enum StorageEvent {
+    Created(ConversationId),
+    Updated(ConversationId),
+    Deleted(ConversationId),
+    TitleChanged(ConversationId),
+    MetaChanged(ConversationId),
+}
+
+impl StorageEvent {
+    pub fn id(&self) -> ConversationId {
+        match *self {
+            StorageEvent::Created(id)
+            | StorageEvent::Updated(id)
+            | StorageEvent::Deleted(id)
+            | StorageEvent::TitleChanged(id)
+            | StorageEvent::MetaChanged(id) => id,
+        }
+    }
+}
+
+/// Who a message is from. Stored and sent as the plain lowercase name, so
+/// roles added by providers later load as `Other` instead of failing.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Role {
     System,
     User,
     Assistant,
+    // output of a tool call, and its older name
+    Tool,
+    Function,
+    // system instructions of newer models
+    Developer,
+    Other(String),
+}
+
+impl Role {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool => "tool",
+            Role::Function => "function",
+            Role::Developer => "developer",
+            Role::Other(name) => name,
+        }
+    }
 }
 
 use std::fmt::Display;
 
-impl Display for KnownRoles {
+impl Display for Role {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        write!(
-            f,
-            "{}",
-            match self {
-                Self::System => "system",
-                Self::User => "user",
-                Self::Assistant => "assistant",
-            }
-        )
+        f.write_str(self.as_str())
     }
 }
 
-impl From<&str> for KnownRoles {
-    fn from(value: &str) -> Self {
-        match value {
-            "system" => Self::System,
-            "user" => Self::User,
-            "assistant" => Self::Assistant,
-            _ => unreachable!(),
-        }
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        // providers differ in case, "Tool" is the same role as "tool"
+        let name = name.trim().to_lowercase();
+        Ok(match name.as_str() {
+            "system" => Role::System,
+            "user" => Role::User,
+            "assistant" => Role::Assistant,
+            "tool" => Role::Tool,
+            "function" => Role::Function,
+            "developer" => Role::Developer,
+            "" => return Err("empty role".to_string()),
+            _ => Role::Other(name),
+        })
+    }
+}
+
+impl TryFrom<String> for Role {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        name.parse()
     }
 }
 
-impl From<KnownRoles> for &str {
-    fn from(value: KnownRoles) -> Self {
-        match value {
-            KnownRoles::System => "system",
-            KnownRoles::User => "user",
-            KnownRoles::Assistant => "assistant",
+impl From<Role> for String {
+    fn from(role: Role) -> Self {
+        match role {
+            Role::Other(name) => name,
+            known => known.as_str().to_string(),
         }
     }
 }
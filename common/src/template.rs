@@ -0,0 +1,209 @@
+//! Prompts with variables to fill in before they are used, written as
+//! `{{name}}`, `{{name|default}}` or `{{name|default|description}}`.
+//! A variable may appear more than once, the first default and description
+//! given for it count.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// filled in by the app unless the user types something else
+pub const BUILTIN_VARIABLES: &[(&str, &str)] = &[
+    ("date", "today's date"),
+    ("time", "the current time"),
+    ("os", "the operating system"),
+    ("clipboard", "text on the clipboard"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Variable {
+    pub name: String,
+    pub default: Option<String>,
+    pub description: Option<String>,
+}
+
+impl Variable {
+    pub fn is_builtin(&self) -> bool {
+        BUILTIN_VARIABLES.iter().any(|(name, _)| *name == self.name)
+    }
+
+    /// what to show next to the input, built-in ones explain themselves
+    pub fn hint(&self) -> Option<&str> {
+        self.description.as_deref().or_else(|| {
+            BUILTIN_VARIABLES
+                .iter()
+                .find(|(name, _)| *name == self.name)
+                .map(|(_, hint)| *hint)
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Variable(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+    variables: Vec<Variable>,
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == ' ')
+}
+
+impl Template {
+    /// Anything that is not a variable is kept as it is, like `{{.Name}}`
+    /// in a code sample or a `{{` that is never closed.
+    pub fn parse(text: &str) -> Self {
+        let mut parts = vec![];
+        let mut variables: Vec<Variable> = vec![];
+        let mut literal = String::new();
+        let mut rest = text;
+        while let Some(start) = rest.find("{{") {
+            literal.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            let end = match after.find("}}") {
+                Some(end) => end,
+                None => {
+                    literal.push_str("{{");
+                    rest = after;
+                    continue;
+                }
+            };
+
+            let mut fields = after[..end].splitn(3, '|');
+            let name = fields.next().unwrap_or_default().trim().to_string();
+            if !valid_name(&name) {
+                literal.push_str(&rest[start..start + 2 + end + 2]);
+                rest = &after[end + 2..];
+                continue;
+            }
+            let default = fields.next().map(|d| d.trim().to_string()).filter(|d| !d.is_empty());
+            let description = fields.next().map(|d| d.trim().to_string()).filter(|d| !d.is_empty());
+
+            match variables.iter_mut().find(|v| v.name == name) {
+                Some(var) => {
+                    var.default = var.default.take().or(default);
+                    var.description = var.description.take().or(description);
+                }
+                None => variables.push(Variable {
+                    name: name.clone(),
+                    default,
+                    description,
+                }),
+            }
+            if !literal.is_empty() {
+                parts.push(Part::Text(std::mem::take(&mut literal)));
+            }
+            parts.push(Part::Variable(name));
+            rest = &after[end + 2..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Text(literal));
+        }
+        Template { parts, variables }
+    }
+
+    /// the variables in order of first appearance
+    pub fn variables(&self) -> &[Variable] {
+        &self.variables
+    }
+
+    /// the variables `values` has nothing for and that have no default
+    pub fn missing(&self, values: &HashMap<String, String>) -> Vec<&Variable> {
+        self.variables
+            .iter()
+            .filter(|v| v.default.is_none() && values.get(&v.name).filter(|value| !value.trim().is_empty()).is_none())
+            .collect()
+    }
+
+    /// The text with every variable replaced by its value, its default when
+    /// no value was given, or nothing.
+    pub fn render(&self, values: &HashMap<String, String>) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.as_str(),
+                Part::Variable(name) => values
+                    .get(name)
+                    .map(|v| v.as_str())
+                    .or_else(|| {
+                        self.variables
+                            .iter()
+                            .find(|v| &v.name == name)
+                            .and_then(|v| v.default.as_deref())
+                    })
+                    .unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn parse_collects_variables_in_order() {
+        let tpl = Template::parse("Translate {{text}} to {{lang|French|target language}}, {{text}} again");
+        let names = tpl.variables().iter().map(|v| v.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["text", "lang"]);
+        assert_eq!(tpl.variables()[1].default.as_deref(), Some("French"));
+        assert_eq!(tpl.variables()[1].description.as_deref(), Some("target language"));
+    }
+
+    #[test]
+    fn first_default_and_description_count() {
+        let tpl = Template::parse("{{a}} {{a|one}} {{a|two|about a}}");
+        assert_eq!(tpl.variables().len(), 1);
+        assert_eq!(tpl.variables()[0].default.as_deref(), Some("one"));
+        assert_eq!(tpl.variables()[0].description.as_deref(), Some("about a"));
+    }
+
+    #[test]
+    fn anything_else_stays_literal() {
+        for text in ["{{.Name}} in go", "open {{ but never closed", "{{}} and {{ }}", "plain"] {
+            let tpl = Template::parse(text);
+            assert!(tpl.variables().is_empty(), "{}", text);
+            assert_eq!(tpl.render(&HashMap::new()), text);
+        }
+    }
+
+    #[test]
+    fn render_prefers_values_then_defaults() {
+        let tpl = Template::parse("{{greeting|Hello}}, {{name}}!");
+        assert_eq!(tpl.render(&values(&[("name", "Ann")])), "Hello, Ann!");
+        assert_eq!(tpl.render(&values(&[("greeting", "Hi"), ("name", "Bo")])), "Hi, Bo!");
+        assert_eq!(tpl.render(&HashMap::new()), "Hello, !");
+    }
+
+    #[test]
+    fn missing_skips_defaults_and_filled_in_values() {
+        let tpl = Template::parse("{{a}} {{b|x}} {{c}} {{d}}");
+        let missing = tpl.missing(&values(&[("a", "filled"), ("c", "  ")]));
+        assert_eq!(missing.iter().map(|v| v.name.as_str()).collect::<Vec<_>>(), ["c", "d"]);
+        assert!(Template::parse("no variables").missing(&HashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn builtins_explain_themselves() {
+        let tpl = Template::parse("{{date}} {{date2|x|mine}} {{topic}}");
+        let vars = tpl.variables();
+        assert!(vars[0].is_builtin());
+        assert_eq!(vars[0].hint(), Some("today's date"));
+        assert!(!vars[1].is_builtin());
+        assert_eq!(vars[1].hint(), Some("mine"));
+        assert_eq!(vars[2].hint(), None);
+    }
+}
@@ -0,0 +1,168 @@
+//! Token counts as the OpenAI models see them. The vocabularies are bundled
+//! and build for wasm32 as well, so the frontend counts a draft the way the
+//! backend counts the conversation.
+
+use serde::{Deserialize, Serialize};
+use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+use tiktoken_rs::CoreBPE;
+
+use crate::{Content, Message};
+
+// every chat message is wrapped in a few tokens marking its role
+const TOKENS_PER_MESSAGE: usize = 3;
+// the answer is primed with `<|start|>assistant<|message|>`
+const TOKENS_PER_REPLY: usize = 3;
+// what a low detail image costs, high detail ones cost more depending on size
+const TOKENS_PER_IMAGE: usize = 85;
+
+// Context windows by model name prefix, the longest matching prefix wins.
+// Only chat models are listed, anything else is not known.
+const CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("gpt-5", 400_000),
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4.5", 128_000),
+    ("gpt-4o", 128_000),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4-1106", 128_000),
+    ("gpt-4-0125", 128_000),
+    ("gpt-4-vision", 128_000),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4", 8_192),
+    ("gpt-3.5-turbo-instruct", 4_096),
+    ("gpt-3.5-turbo", 16_385),
+    ("o1-mini", 128_000),
+    ("o1-preview", 128_000),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("o4-mini", 200_000),
+];
+
+fn with_bpe<T>(model: &str, f: impl FnOnce(&CoreBPE) -> T) -> T {
+    // models tiktoken does not know yet most likely use the newest vocabulary,
+    // each is only built the first time a model needs it
+    let bpe = match get_tokenizer(model).unwrap_or(Tokenizer::O200kBase) {
+        Tokenizer::O200kBase => tiktoken_rs::o200k_base_singleton(),
+        Tokenizer::Cl100kBase => tiktoken_rs::cl100k_base_singleton(),
+        Tokenizer::P50kBase => tiktoken_rs::p50k_base_singleton(),
+        Tokenizer::P50kEdit => tiktoken_rs::p50k_edit_singleton(),
+        Tokenizer::R50kBase | Tokenizer::Gpt2 => tiktoken_rs::r50k_base_singleton(),
+    };
+    let bpe = bpe.lock();
+    f(&bpe)
+}
+
+fn count_message(bpe: &CoreBPE, msg: &Message) -> usize {
+    TOKENS_PER_MESSAGE
+        + bpe.encode_ordinary(msg.role.as_str()).len()
+        + bpe.encode_ordinary(&msg.content.text()).len()
+        + msg.content.images().len() * TOKENS_PER_IMAGE
+}
+
+/// tokens of `text` for `model`
+pub fn count_tokens(model: &str, text: &str) -> usize {
+    with_bpe(model, |bpe| bpe.encode_ordinary(text).len())
+}
+
+/// tokens a chat request with `messages` takes up before the answer
+pub fn count_message_tokens(model: &str, messages: &[Message]) -> usize {
+    with_bpe(model, |bpe| {
+        messages.iter().map(|msg| count_message(bpe, msg)).sum::<usize>() + TOKENS_PER_REPLY
+    })
+}
+
+/// how many tokens `model` takes in and answers with together, `None` for
+/// models not known here
+pub fn context_window(model: &str) -> Option<usize> {
+    CONTEXT_WINDOWS
+        .iter()
+        .filter(|(prefix, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|&(_, window)| window)
+}
+
+/// how much of the context of `model` a conversation takes up
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenBudget {
+    pub model: String,
+    pub used: usize,
+    // unknown for models not in the list, unless set in the settings
+    pub context_window: Option<usize>,
+}
+
+impl TokenBudget {
+    /// `messages` counted for `model`, with `context_window` in place of the
+    /// known window when set
+    pub fn new(model: &str, messages: &[Message], context_window: Option<usize>) -> Self {
+        Self {
+            model: model.to_string(),
+            used: count_message_tokens(model, messages),
+            context_window: context_window.or_else(|| self::context_window(model)),
+        }
+    }
+
+    /// tokens left after sending `extra` more, negative when over the limit
+    /// and `None` when the window is not known
+    pub fn remaining(&self, extra: usize) -> Option<i64> {
+        self.context_window
+            .map(|window| window as i64 - self.used as i64 - extra as i64)
+    }
+
+    /// what one more user message with `content` adds
+    pub fn cost(&self, content: &Content) -> usize {
+        let tokens = count_tokens(&self.model, &content.text());
+        // `user` is a single token
+        TOKENS_PER_MESSAGE + 1 + tokens + content.images().len() * TOKENS_PER_IMAGE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_go_by_the_longest_prefix() {
+        assert_eq!(context_window("gpt-4"), Some(8_192));
+        assert_eq!(context_window("gpt-4-32k-0613"), Some(32_768));
+        assert_eq!(context_window("gpt-4o-mini"), Some(128_000));
+        assert_eq!(context_window("o1-mini"), Some(128_000));
+        // not the window of `gpt-4` wherever the entries are listed
+        assert_eq!(context_window("gpt-4.5-preview"), Some(128_000));
+        assert_eq!(context_window("gpt-4.1-mini"), Some(1_047_576));
+        assert_eq!(context_window("some-local-model"), None);
+    }
+
+    #[test]
+    fn unknown_windows_leave_nothing_to_compare() {
+        let budget = TokenBudget {
+            model: "some-local-model".to_string(),
+            used: 10,
+            context_window: None,
+        };
+        assert_eq!(budget.remaining(100), None);
+        let budget = TokenBudget { context_window: Some(100), ..budget };
+        assert_eq!(budget.remaining(95), Some(-5));
+    }
+
+    #[test]
+    fn counts_match_the_vocabulary() {
+        assert_eq!(count_tokens("gpt-3.5-turbo", "hello world"), 2);
+        // unknown models are counted like the newest ones
+        assert_eq!(count_tokens("some-local-model", "hello world"), 2);
+
+        let messages = [Message::new_system("be brief"), Message::new_user("hello world")];
+        let budget = TokenBudget::new("gpt-3.5-turbo", &messages, None);
+        assert_eq!(budget.used, count_message_tokens("gpt-3.5-turbo", &messages));
+        assert_eq!(budget.context_window, Some(16_385));
+        assert_eq!(TokenBudget::new("some-local-model", &messages, Some(4_096)).context_window, Some(4_096));
+    }
+
+    #[test]
+    fn draft_cost_is_counted() {
+        let budget = TokenBudget::new("gpt-3.5-turbo", &[], None);
+        let draft = Content::Text("hello world".to_string());
+        assert_eq!(budget.cost(&draft), TOKENS_PER_MESSAGE + 1 + 2);
+        // the message is counted the same way once it is part of the conversation
+        let sent = count_message_tokens("gpt-3.5-turbo", &[Message::new_user("hello world")]);
+        assert_eq!(budget.cost(&draft), sent - TOKENS_PER_REPLY);
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Message, MessageId, Page, PathNode};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MessageNode {
+    pub parent: Option<MessageId>,
+    pub message: Message,
+}
+
+impl MessageNode {
+    pub fn id(&self) -> MessageId {
+        self.message.id
+    }
+}
+
+/// All messages of a conversation with their parent links. Editing an earlier
+/// message forks a sibling branch instead of overwriting what followed it;
+/// `current` is the leaf of the branch being shown and continued.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConversationTree {
+    pub nodes: Vec<MessageNode>,
+    pub current: Option<MessageId>,
+}
+
+impl ConversationTree {
+    pub fn from_messages(msgs: Vec<Message>) -> Self {
+        let mut tree = ConversationTree::default();
+        for msg in msgs {
+            tree.push(msg);
+        }
+        tree
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    // `get`, `children` and `siblings` scan every node, which is fine for a
+    // single lookup; `path` and `page` index the nodes first instead
+    pub fn get(&self, id: MessageId) -> Option<&MessageNode> {
+        self.nodes.iter().find(|n| n.id() == id)
+    }
+
+    pub fn get_mut(&mut self, id: MessageId) -> Option<&mut MessageNode> {
+        self.nodes.iter_mut().find(|n| n.id() == id)
+    }
+
+    pub fn children(&self, parent: Option<MessageId>) -> impl Iterator<Item = &MessageNode> {
+        self.nodes.iter().filter(move |n| n.parent == parent)
+    }
+
+    /// ids of all messages sharing the parent of `id`, `id` included, oldest first
+    pub fn siblings(&self, id: MessageId) -> Vec<MessageId> {
+        match self.get(id) {
+            Some(node) => self.children(node.parent).map(|n| n.id()).collect(),
+            None => vec![],
+        }
+    }
+
+    /// nodes of the active branch, from the root down to `current`
+    pub fn path(&self) -> Vec<&MessageNode> {
+        let by_id = self.nodes.iter().map(|n| (n.id(), n)).collect::<HashMap<_, _>>();
+        let mut path = vec![];
+        let mut seen = HashSet::new();
+        let mut cur = self.current.and_then(|id| by_id.get(&id));
+        while let Some(&node) = cur {
+            // a cycle, stop before a node repeats
+            if !seen.insert(node.id()) {
+                break;
+            }
+            path.push(node);
+            cur = node.parent.and_then(|id| by_id.get(&id));
+        }
+        path.reverse();
+        path
+    }
+
+    /// messages of the active branch, which is what gets sent to the model
+    pub fn messages(&self) -> Vec<Message> {
+        self.path().into_iter().map(|n| n.message.clone()).collect()
+    }
+
+    /// unix time in milliseconds of the newest message in any branch
+    pub fn updated_at(&self) -> u64 {
+        self.nodes.iter().map(|n| n.message.created_at).max().unwrap_or(0)
+    }
+
+    /// Up to `limit` nodes of the active branch ending right before `before`,
+    /// or at `current` when it's `None`. `next` is the oldest node returned
+    /// while older ones remain.
+    pub fn page(&self, before: Option<MessageId>, limit: usize) -> Page<PathNode, MessageId> {
+        let path = self.path();
+        let end = match before {
+            Some(before) => path.iter().position(|n| n.id() == before).unwrap_or(0),
+            None => path.len(),
+        };
+        let start = end.saturating_sub(limit.max(1));
+
+        let mut children = HashMap::<_, Vec<_>>::new();
+        for node in &self.nodes {
+            children.entry(node.parent).or_default().push(node.id());
+        }
+        Page {
+            items: path[start..end]
+                .iter()
+                .map(|n| PathNode {
+                    node: (*n).clone(),
+                    siblings: children[&n.parent].clone(),
+                })
+                .collect(),
+            next: path.get(start).filter(|_| start > 0).map(|n| n.id()),
+        }
+    }
+
+    /// append `msg` to the active branch
+    pub fn push(&mut self, msg: Message) -> MessageId {
+        self.add_child(self.current, msg)
+    }
+
+    /// add `msg` as a new sibling of `id` and make it the active branch
+    pub fn fork(&mut self, id: MessageId, msg: Message) -> Option<MessageId> {
+        let parent = self.get(id)?.parent;
+        Some(self.add_child(parent, msg))
+    }
+
+    /// show the branch containing `id`, following the most recent child down to a leaf
+    pub fn switch_to(&mut self, id: MessageId) -> bool {
+        if self.get(id).is_none() {
+            return false;
+        }
+
+        let mut leaf = id;
+        let mut seen = HashSet::from([id]);
+        while let Some(child) = self.children(Some(leaf)).last() {
+            // a cycle, stop before a node repeats
+            if !seen.insert(child.id()) {
+                break;
+            }
+            leaf = child.id();
+        }
+        self.current = Some(leaf);
+        true
+    }
+
+    /// Make `msgs` the active branch. The longest prefix already on the active
+    /// branch is reused and the rest is appended from there, so other branches
+    /// survive callers that only know about the linear list. Messages already
+    /// in the tree are followed when they hang off the branch built so far and
+    /// skipped otherwise, ids stay unique.
+    pub fn replace_path(&mut self, msgs: Vec<Message>) {
+        let path = self.path().into_iter().map(|n| n.id()).collect::<Vec<_>>();
+
+        let mut parent = None;
+        let mut rest = msgs.into_iter().peekable();
+        for id in path {
+            match rest.peek() {
+                Some(msg) if msg.id == id => {
+                    parent = Some(id);
+                    rest.next();
+                }
+                _ => break,
+            }
+        }
+
+        self.current = parent;
+        for msg in rest {
+            match self.get(msg.id) {
+                Some(node) if node.parent == self.current => self.current = Some(msg.id),
+                Some(_) => {}
+                None => {
+                    self.push(msg);
+                }
+            }
+        }
+    }
+
+    /// add `msg` under `parent` and make it the active branch
+    pub fn add_child(&mut self, parent: Option<MessageId>, msg: Message) -> MessageId {
+        let id = msg.id;
+        self.nodes.push(MessageNode {
+            parent,
+            message: msg,
+        });
+        self.current = Some(id);
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(tree: &ConversationTree) -> Vec<String> {
+        tree.messages().iter().map(|m| m.content.text()).collect()
+    }
+
+    // system, question, answer
+    fn dialogue() -> (ConversationTree, Vec<MessageId>) {
+        let mut tree = ConversationTree::default();
+        let ids = vec![
+            tree.push(Message::new_system("be brief")),
+            tree.push(Message::new_user("hi")),
+            tree.push(Message::new_assistant("hello")),
+        ];
+        (tree, ids)
+    }
+
+    #[test]
+    fn push_extends_the_active_branch() {
+        let (tree, ids) = dialogue();
+        assert_eq!(texts(&tree), ["be brief", "hi", "hello"]);
+        assert_eq!(tree.current, Some(ids[2]));
+        assert_eq!(tree.get(ids[2]).unwrap().parent, Some(ids[1]));
+        assert_eq!(tree.siblings(ids[1]), vec![ids[1]]);
+    }
+
+    #[test]
+    fn fork_keeps_the_old_branch() {
+        let (mut tree, ids) = dialogue();
+        let edited = tree.fork(ids[1], Message::new_user("hey")).unwrap();
+        assert_eq!(texts(&tree), ["be brief", "hey"]);
+        assert_eq!(tree.siblings(edited), vec![ids[1], edited]);
+        assert_eq!(tree.nodes.len(), 4);
+
+        assert!(tree.fork(MessageId::new(), Message::new_user("x")).is_none());
+    }
+
+    #[test]
+    fn switch_to_follows_the_newest_child() {
+        let (mut tree, ids) = dialogue();
+        let edited = tree.fork(ids[1], Message::new_user("hey")).unwrap();
+        let answer = tree.push(Message::new_assistant("hey there"));
+
+        assert!(tree.switch_to(ids[1]));
+        assert_eq!(tree.current, Some(ids[2]));
+        assert!(tree.switch_to(ids[0]));
+        // the root's newest child is the edit
+        assert_eq!(tree.current, Some(answer));
+        assert!(tree.switch_to(edited));
+        assert_eq!(texts(&tree), ["be brief", "hey", "hey there"]);
+
+        assert!(!tree.switch_to(MessageId::new()));
+        assert_eq!(tree.current, Some(answer));
+    }
+
+    #[test]
+    fn replace_path_reuses_the_common_prefix() {
+        let (mut tree, ids) = dialogue();
+        let mut msgs = tree.messages();
+        msgs.truncate(2);
+        msgs.push(Message::new_assistant("hello again"));
+        tree.replace_path(msgs);
+
+        assert_eq!(texts(&tree), ["be brief", "hi", "hello again"]);
+        // the old answer is still there as a sibling
+        assert_eq!(tree.siblings(ids[2]).len(), 2);
+        assert_eq!(tree.nodes.len(), 4);
+    }
+
+    #[test]
+    fn page_walks_back_from_current() {
+        let (tree, ids) = dialogue();
+        let newest = tree.page(None, 2);
+        assert_eq!(newest.items.iter().map(|n| n.node.id()).collect::<Vec<_>>(), ids[1..]);
+        assert_eq!(newest.next, Some(ids[1]));
+
+        let older = tree.page(newest.next, 2);
+        assert_eq!(older.items.iter().map(|n| n.node.id()).collect::<Vec<_>>(), ids[..1]);
+        assert_eq!(older.next, None);
+    }
+
+    #[test]
+    fn path_survives_a_cycle() {
+        let (mut tree, ids) = dialogue();
+        // a node outside the cycle, so the cycle is shorter than the tree
+        tree.fork(ids[1], Message::new_user("hey")).unwrap();
+        tree.get_mut(ids[0]).unwrap().parent = Some(ids[2]);
+        tree.current = Some(ids[2]);
+        let path = tree.path();
+        assert_eq!(path.len(), 3);
+        assert!(path.len() <= tree.nodes.len());
+        let mut seen = path.iter().map(|n| n.id()).collect::<Vec<_>>();
+        seen.sort_by_key(|id| id.0);
+        seen.dedup();
+        assert_eq!(seen.len(), path.len());
+    }
+
+    #[test]
+    fn switch_to_survives_a_cycle() {
+        let (mut tree, ids) = dialogue();
+        tree.get_mut(ids[0]).unwrap().parent = Some(ids[2]);
+        assert!(tree.switch_to(ids[0]));
+        assert_eq!(tree.current, Some(ids[2]));
+    }
+
+    #[test]
+    fn replace_path_keeps_ids_unique() {
+        let (mut tree, ids) = dialogue();
+        let old_answer = tree.get(ids[2]).unwrap().message.clone();
+        let edited = tree.fork(ids[1], Message::new_user("hey")).unwrap();
+
+        // the old answer is on the other branch, it can't follow the edit
+        let mut msgs = tree.messages();
+        msgs.push(old_answer.clone());
+        tree.replace_path(msgs);
+        assert_eq!(tree.nodes.iter().filter(|n| n.id() == ids[2]).count(), 1);
+        assert_eq!(tree.current, Some(edited));
+
+        // back on its own branch it is followed instead of stored again
+        tree.replace_path(vec![tree.nodes[0].message.clone(), tree.nodes[1].message.clone(), old_answer]);
+        assert_eq!(tree.current, Some(ids[2]));
+        assert_eq!(tree.nodes.len(), 4);
+    }
+}
@@ -0,0 +1,70 @@
+//! Time appending a turn to conversations of growing length on the disk
+//! backend, the way a chat does it: checked against the version read before.
+//! A write should cost the same however long the conversation is.
+//!
+//!     cargo run --release --example storage_bench
+use std::time::Instant;
+
+use chatgpt_backend::storage::{disk::KVStorage, Storage};
+use common::Message;
+
+const LENGTHS: [usize; 5] = [10, 100, 250, 500, 1000];
+const SAMPLES: u32 = 50;
+const WORDS: [&str; 24] = [
+    "fn", "main", "println", "review", "change", "please", "lifetime", "borrow", "trait",
+    "impl", "struct", "enum", "match", "result", "error", "vector", "iterator", "closure",
+    "async", "await", "thread", "mutex", "channel", "serde",
+];
+
+// about 4KB of text like a long answer with code in it, different every time
+// so that every message brings terms of its own to the search index
+fn body(seed: &mut u64) -> String {
+    let mut text = String::new();
+    while text.len() < 4096 {
+        // xorshift, good enough to shuffle words
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 7;
+        *seed ^= *seed << 17;
+        text.push_str(WORDS[(*seed % WORDS.len() as u64) as usize]);
+        text.push_str(if *seed % 5 == 0 { "_" } else { " " });
+        if *seed % 11 == 0 {
+            text.push_str(&format!("{} ", *seed % 100_000));
+        }
+    }
+    text
+}
+
+fn main() {
+    let dir = std::env::temp_dir().join(format!("storage-bench-{}", std::process::id()));
+    let store = KVStorage::new(&dir).expect("open storage");
+
+    let mut seed = 0x2545_f491_4f6c_dd1d;
+    let id = store.start_conversation(None).expect("start conversation");
+    let (mut version, _) = store.get_versioned_tree(id).expect("read conversation");
+    let mut len = 1;
+    for target in LENGTHS {
+        while len < target {
+            version = store
+                .compare_and_append(id, version, Message::new_user(body(&mut seed)))
+                .expect("append");
+            len += 1;
+        }
+
+        let bodies = (0..SAMPLES).map(|_| body(&mut seed)).collect::<Vec<_>>();
+        let started = Instant::now();
+        for body in bodies {
+            version = store
+                .compare_and_append(id, version, Message::new_user(body))
+                .expect("append");
+        }
+        len += SAMPLES as usize;
+        println!(
+            "{:>5} messages: {:>8.1?} per append",
+            target,
+            started.elapsed() / SAMPLES
+        );
+    }
+
+    drop(store);
+    std::fs::remove_dir_all(&dir).ok();
+}
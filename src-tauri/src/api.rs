@@ -1,8 +1,11 @@
 #![allow(unused)]
 
-use std::{sync::{Mutex, Arc}, path::{Path, PathBuf}};
+use std::{collections::HashMap, sync::{atomic::{AtomicBool, Ordering}, mpsc::Receiver, Mutex, Arc, RwLock}, path::{Path, PathBuf}, time::{Duration, Instant, UNIX_EPOCH}};
 
-use common::{GenerateImageResult, GenerateImageParams};
+use common::tokens::TokenBudget;
+use common::{BackupSummary, ConversationCursor, StorageEvent, StorageBackend, StorageInfo, MigrationProgress, PurgeSummary, RetentionPolicy, RetentionPreview, EncryptionStatus, Page, PathNode, SnapshotInfo, MESSAGE_PAGE_SIZE, GenerateImageResult, GenerateImageParams, ImportSummary, Role, Content, MessageMeta, SearchHit, ConversationMeta, ConversationFilter, ConversationEntry, Conversation, ConversationSummary, Prompt, PromptId, PromptRef, PromptVersion, CONFLICT_ERROR};
+use common::template::Template;
+use itertools::Itertools;
 use lazy_static::lazy_static;
 use config::{Config, ConfigError, File, Environment};
 use rand::{Rng, SeedableRng, rngs::StdRng, distributions::{Uniform, Distribution}};
@@ -11,11 +14,21 @@ static COMPLETION_MODEL: &str = "gpt-3.5-turbo";
 static CODING_MODEL: &str = "code-davinci-002";
 static CHAT_API_PATH: &str = "https://api.openai.com/v1/chat/completions";
 static GEN_IMAGE_API_PATH: &str = "https://api.openai.com/v1/images/generations";
+// times an answer is stored again when other turns came in while it was generated
+const ANSWER_RETRIES: usize = 3;
+// how often the retention policy is enforced
+const RETENTION_INTERVAL: Duration = Duration::from_secs(3600);
+// messages read per sidebar row to find one to preview
+const PREVIEW_MESSAGES: usize = 2;
 
 use serde::{Deserialize, Serialize};
-pub use common::{Message, ConversationId};
+pub use common::{Message, ConversationId, ConversationTree, MessageId};
 
-use crate::storage::Storage;
+use crate::backup::{self, KEY_CHECK_FILE, NEXT_KEY_CHECK_FILE, SETTINGS_NAME};
+use crate::retention;
+use crate::storage::{self, Storage};
+use crate::storage::encrypted::{Cipher, EncryptedStorage, KeyCheck};
+use crate::storage::switch::SwitchableStorage;
 #[cfg(feature = "local-storage")]
 use crate::storage::local::KVStorage as LocalStorage;
 #[cfg(feature = "persist-storage")]
@@ -28,7 +41,23 @@ pub struct Params<'a> {
     model: &'a str,
     temperature: f32,
     stream: bool,
-    messages: Vec<Message>,
+    messages: Vec<WireMessage>,
+}
+
+// the api only accepts `role` and `content`, the rest of `Message` stays local
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct WireMessage {
+    role: Role,
+    content: Content,
+}
+
+impl From<Message> for WireMessage {
+    fn from(msg: Message) -> Self {
+        WireMessage {
+            role: msg.role,
+            content: msg.content,
+        }
+    }
 }
 
 #[derive(Debug, Default, Deserialize)]
@@ -43,8 +72,8 @@ struct Usage {
 #[allow(unused)]
 struct Choice {
     index: usize,
-    message: Message,
-    finish_reason: String,
+    message: WireMessage,
+    finish_reason: Option<String>,
 }
 
 #[derive(Debug, Default, Deserialize)]
@@ -52,11 +81,13 @@ struct Choice {
 struct Answer {
     id: String,
     object: String,
+    #[serde(default)]
+    model: String,
     choices: Vec<Choice>,
     usage: Usage
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[allow(unused)]
 pub struct Settings {
     model: String,
@@ -64,47 +95,420 @@ pub struct Settings {
     stream: bool,
     api_key: String,
     api_keys: Vec<String>,
+    // 0 turns rolling snapshots off
+    snapshot_interval_hours: u64,
+    snapshot_keep: usize,
+    // zstd level for stored message bodies, 0 turns compression off
+    storage_compression: i32,
+    #[serde(default)]
+    retention: RetentionPolicy,
+    // unset picks the persistent backend when it is built in
+    #[serde(default)]
+    storage_backend: Option<StorageBackend>,
+    // the bundled prompts were added to the library once, deleting them is for good
+    #[serde(default)]
+    prompts_seeded: bool,
+    // tokens the model takes, for models not known or known wrong; only a
+    // window set here keeps requests that do not fit from being sent
+    #[serde(default)]
+    context_window: Option<usize>,
+    // whether the model reads images, for models not known or known wrong
+    #[serde(default)]
+    vision: Option<bool>,
 }
 
 pub struct ChatGPT {
-    settings: Settings,
+    // reloaded whenever the settings file is rewritten
+    settings: RwLock<Settings>,
+    cfg_path: PathBuf,
+    // plaintext keys, the sealed ones from settings are opened on unlock
+    api_keys: RwLock<Vec<String>>,
     rng: Arc<Mutex<StdRng>>,
     pub cli: reqwest::Client,
 
-    store: Box<dyn Storage + Send + Sync>,
+    // the backend picked in settings, only accessed directly to wrap it
+    base: Arc<SwitchableStorage>,
+    backend: RwLock<StorageBackend>,
+    // `None` while encrypted storage is locked
+    store: RwLock<Option<Arc<dyn Storage + Send + Sync>>>,
+    vault: RwLock<Option<Arc<EncryptedStorage<dyn Storage + Send + Sync>>>>,
+    prompts_seeded: AtomicBool,
 }
 
 impl ChatGPT {
     pub fn new<P: AsRef<Path>>(cfg_path: P) -> Self {
         let settings = ChatGPT::load_settings(cfg_path.as_ref()).unwrap();
-
-        ChatGPT {
-            settings,
+        let backend = settings.storage_backend.unwrap_or_else(Self::default_backend);
+        let base = Arc::new(SwitchableStorage::new(
+            Self::open_store(backend, cfg_path.as_ref(), settings.storage_compression).unwrap(),
+        ));
+        let gpt = ChatGPT {
+            // memory starts out empty on every run, so does its library
+            prompts_seeded: AtomicBool::new(settings.prompts_seeded && backend != StorageBackend::Memory),
+            settings: RwLock::new(settings),
+            cfg_path: cfg_path.as_ref().to_path_buf(),
+            api_keys: RwLock::new(vec![]),
             rng: Arc::new(Mutex::new(StdRng::from_entropy())),
             cli: reqwest::Client::new(),
 
-            store: Self::get_store(cfg_path), 
+            base: base.clone(),
+            backend: RwLock::new(backend),
+            store: RwLock::new(None),
+            vault: RwLock::new(None),
+        };
+
+        if gpt.key_check().is_none() {
+            *gpt.store.write().unwrap() = Some(base);
+            gpt.open_api_keys(None);
+        }
+        gpt
+    }
+
+    fn default_backend() -> StorageBackend {
+        if cfg!(feature = "persist-storage") {
+            StorageBackend::Sled
+        } else {
+            StorageBackend::Memory
+        }
+    }
+
+    fn available_backends() -> Vec<StorageBackend> {
+        let mut available = vec![];
+        if cfg!(feature = "local-storage") {
+            available.push(StorageBackend::Memory);
+        }
+        if cfg!(feature = "persist-storage") {
+            available.push(StorageBackend::Sled);
+        }
+        available
+    }
+
+    fn open_store(backend: StorageBackend, cfg_path: &Path, compression: i32) -> Result<Arc<dyn Storage + Send + Sync>, String> {
+        match backend {
+            #[cfg(feature = "local-storage")]
+            StorageBackend::Memory => Ok(Arc::new(LocalStorage::new())),
+            #[cfg(feature = "persist-storage")]
+            StorageBackend::Sled => Ok(Arc::new(DiskStorage::new(cfg_path).map_err(|e| e.to_string())?.compression(compression))),
+            #[allow(unreachable_patterns)]
+            _ => Err(format!("{:?} storage is not built into this app", backend)),
+        }
+    }
+
+    fn settings(&self) -> Settings {
+        self.settings.read().unwrap().clone()
+    }
+
+    fn base(&self) -> Arc<dyn Storage + Send + Sync> {
+        self.base.clone()
+    }
+
+    fn store(&self) -> Result<Arc<dyn Storage + Send + Sync>, String> {
+        self.store.read().unwrap().clone().ok_or("storage is locked".to_string())
+    }
+
+    fn key_check(&self) -> Option<KeyCheck> {
+        Self::read_key_check(&self.cfg_path.join(KEY_CHECK_FILE))
+    }
+
+    fn read_key_check(path: &Path) -> Option<KeyCheck> {
+        let data = std::fs::read(path).ok()?;
+        serde_json::from_slice(&data).ok()
+    }
+
+    // The new key is created once and its check is kept next to the old one
+    // until everything is sealed with it. Asking for the same passphrase again
+    // picks up where an interrupted change stopped.
+    fn next_key(&self, passphrase: &str) -> Result<Cipher, String> {
+        let path = self.cfg_path.join(NEXT_KEY_CHECK_FILE);
+        if let Some(check) = Self::read_key_check(&path) {
+            return Cipher::unlock(passphrase, &check)
+                .map_err(|_| "a passphrase change was interrupted, repeat it with the same new passphrase".to_string());
+        }
+
+        let (cipher, check) = Cipher::create(passphrase)?;
+        let data = serde_json::to_vec_pretty(&check).map_err(|e| e.to_string())?;
+        backup::write_atomic(&path, &data)?;
+        Ok(cipher)
+    }
+
+    // seal everything with the key from `next_key`, `old` still opens what isn't yet
+    fn finish_rekey(&self, vault: &EncryptedStorage<dyn Storage + Send + Sync>, old: Cipher, cipher: Cipher) -> Result<(), String> {
+        vault.rekey(cipher.clone())?;
+
+        let either = cipher.clone().falling_back_to(old);
+        backup::reseal_secrets(&self.cfg_path, |key| {
+            if key.starts_with("enc:") {
+                cipher.encrypt(&either.decrypt(key)?)
+            } else {
+                Ok(key.to_string())
+            }
+        })?;
+        std::fs::rename(self.cfg_path.join(NEXT_KEY_CHECK_FILE), self.cfg_path.join(KEY_CHECK_FILE))
+            .map_err(|e| e.to_string())?;
+
+        self.reload_settings()
+    }
+
+    // the settings file changed, take it over along with its api keys
+    fn reload_settings(&self) -> Result<(), String> {
+        *self.settings.write().unwrap() = Self::load_settings(&self.cfg_path).map_err(|e| e.to_string())?;
+        let vault = self.vault.read().unwrap().clone();
+        match vault {
+            Some(vault) => self.open_api_keys(Some(&vault.cipher())),
+            None if self.store.read().unwrap().is_some() => self.open_api_keys(None),
+            // opened on unlock
+            None => {}
         }
+        Ok(())
+    }
+
+    // keys sealed with `encrypt_secret` can only be used once unlocked
+    fn open_api_keys(&self, cipher: Option<&Cipher>) {
+        let settings = self.settings();
+        let keys = if settings.api_key.is_empty() {
+            settings.api_keys
+        } else {
+            vec![settings.api_key]
+        };
+
+        *self.api_keys.write().unwrap() = keys
+            .into_iter()
+            .filter_map(|key| match cipher {
+                Some(cipher) => cipher.decrypt(&key).map_err(|e| eprintln!("api key: {}", e)).ok(),
+                None if key.starts_with("enc:") => None,
+                None => Some(key),
+            })
+            .collect();
     }
 
-    #[cfg(feature = "persist-storage")]
-    fn get_store<P: AsRef<Path>>(cfg_path: P) -> Box<dyn Storage + Send + Sync> {
-        Box::new(DiskStorage::new(cfg_path).unwrap())
+    fn install_vault(&self, cipher: Cipher) -> Arc<EncryptedStorage<dyn Storage + Send + Sync>> {
+        self.open_api_keys(Some(&cipher));
+        let vault = Arc::new(EncryptedStorage::new(self.base(), cipher));
+        *self.vault.write().unwrap() = Some(vault.clone());
+        *self.store.write().unwrap() = Some(vault.clone() as Arc<dyn Storage + Send + Sync>);
+        vault
     }
 
-    #[cfg(all(feature = "local-storage", not(feature = "persist-storage")))]
-    fn get_store<P: AsRef<Path>>(cfg_path: P) -> Box<dyn Storage + Send + Sync> {
-        Box::new(LocalStorage::new())
+    pub fn encryption_status(&self) -> EncryptionStatus {
+        EncryptionStatus {
+            enabled: self.key_check().is_some(),
+            locked: self.store.read().unwrap().is_none(),
+        }
+    }
+
+    pub fn unlock(&self, passphrase: String) -> Result<(), String> {
+        let check = self.key_check().ok_or("encryption is not enabled".to_string())?;
+        let cipher = Cipher::unlock(&passphrase, &check)?;
+        if self.cfg_path.join(NEXT_KEY_CHECK_FILE).exists() {
+            eprintln!("unlock: a passphrase change was interrupted, some values stay sealed until it is repeated");
+        }
+        self.install_vault(cipher);
+        Ok(())
+    }
+
+    /// seal everything stored so far and from now on ask for `passphrase` at startup
+    pub fn enable_encryption(&self, passphrase: String) -> Result<(), String> {
+        if self.key_check().is_some() {
+            return Err("encryption is already enabled".to_string());
+        }
+        if passphrase.is_empty() {
+            return Err("passphrase is empty".to_string());
+        }
+
+        let cipher = self.next_key(&passphrase)?;
+        // old and new cipher are the same, existing plaintext passes through decrypt
+        let vault = self.install_vault(cipher.clone());
+        self.finish_rekey(&vault, cipher.clone(), cipher)
+    }
+
+    pub fn rekey(&self, old_passphrase: String, new_passphrase: String) -> Result<(), String> {
+        let check = self.key_check().ok_or("encryption is not enabled".to_string())?;
+        let old = Cipher::unlock(&old_passphrase, &check)?;
+        if new_passphrase.is_empty() {
+            return Err("passphrase is empty".to_string());
+        }
+
+        let vault = self.vault.read().unwrap().clone().ok_or("storage is locked".to_string())?;
+        let cipher = self.next_key(&new_passphrase)?;
+        self.finish_rekey(&vault, old, cipher)
+    }
+
+    /// seal `value` with the current key, for putting api keys into the config file
+    pub fn encrypt_secret(&self, value: String) -> Result<String, String> {
+        let vault = self.vault.read().unwrap().clone().ok_or("storage is locked".to_string())?;
+        vault.encrypt(&value)
+    }
+
+    /// Archive everything into `path`. The store is read below encryption, so
+    /// encrypted conversations stay encrypted in the archive.
+    pub fn create_backup<P: AsRef<Path>>(&self, path: P, include_secrets: bool) -> Result<(), String> {
+        let backup = backup::collect(self.base().as_ref(), &self.cfg_path, include_secrets)?;
+        backup::write_archive(path.as_ref(), &backup)
+    }
+
+    pub fn restore_backup<P: AsRef<Path>>(&self, path: P) -> Result<BackupSummary, String> {
+        let backup = backup::read_archive(path.as_ref())?;
+        let archived = backup
+            .files
+            .get(KEY_CHECK_FILE)
+            .and_then(|text| serde_json::from_str::<KeyCheck>(text).ok());
+
+        // values sealed with two different keys can't live in one store
+        let key_changes = archived != self.key_check();
+        if key_changes && !self.base().get_conversations()?.is_empty() {
+            return Err("the backup is encrypted differently, restore it into an empty data directory".to_string());
+        }
+
+        // whatever is overwritten can still be brought back
+        backup::take_snapshot(self.base().as_ref(), &self.cfg_path, self.settings().snapshot_keep + 1)?;
+        let summary = backup::restore(self.base().as_ref(), &self.cfg_path, backup)?;
+
+        if key_changes {
+            *self.vault.write().unwrap() = None;
+            if archived.is_some() {
+                *self.store.write().unwrap() = None;
+            } else {
+                std::fs::remove_file(self.cfg_path.join(KEY_CHECK_FILE)).map_err(|e| e.to_string())?;
+                *self.store.write().unwrap() = Some(self.base());
+            }
+        }
+        self.reload_settings()?;
+
+        eprintln!("restore_backup: {:?}", summary);
+        Ok(summary)
+    }
+
+    pub fn list_snapshots(&self) -> Vec<SnapshotInfo> {
+        backup::list_snapshots(&self.cfg_path)
+    }
+
+    pub fn take_snapshot(&self) -> Result<SnapshotInfo, String> {
+        backup::take_snapshot(self.base().as_ref(), &self.cfg_path, self.settings().snapshot_keep)
+    }
+
+    /// Take a snapshot whenever the newest one is older than the configured
+    /// interval. Blocks forever, run it on its own thread.
+    pub fn run_snapshots(&self) {
+        if self.settings().snapshot_interval_hours == 0 {
+            return;
+        }
+        let interval = Duration::from_secs(self.settings().snapshot_interval_hours * 3600);
+
+        loop {
+            let due = self
+                .list_snapshots()
+                .first()
+                .map(|s| UNIX_EPOCH + Duration::from_millis(s.created_at) + interval);
+            if let Some(wait) = due.and_then(|due| due.duration_since(std::time::SystemTime::now()).ok()) {
+                std::thread::sleep(wait);
+            }
+
+            match self.take_snapshot() {
+                Ok(snapshot) => eprintln!("snapshot: {}", snapshot.path),
+                Err(e) => {
+                    eprintln!("snapshot failed: {}", e);
+                    std::thread::sleep(interval);
+                }
+            }
+        }
+    }
+
+    /// What the retention policy would purge now. Titles can only be read
+    /// while storage is unlocked.
+    pub fn retention_preview(&self) -> Result<RetentionPreview, String> {
+        let policy = self.settings().retention;
+        let (mut candidates, total_size) = retention::plan(self.base().as_ref(), &policy, backup::now_ms())?;
+        let store = self.store().ok();
+        for candidate in candidates.iter_mut() {
+            candidate.title = store.as_ref().and_then(|store| store.get_title(candidate.id));
+        }
+
+        Ok(RetentionPreview {
+            policy,
+            candidates,
+            total_size,
+        })
+    }
+
+    /// Purge what the retention policy says has to go. This works on the store
+    /// below encryption, so it runs while locked and archives stay sealed.
+    pub fn apply_retention(&self) -> Result<PurgeSummary, String> {
+        let policy = self.settings().retention;
+        let (candidates, _) = retention::plan(self.base().as_ref(), &policy, backup::now_ms())?;
+        let ids = candidates.into_iter().map(|c| c.id).collect::<Vec<_>>();
+        retention::purge(self.base().as_ref(), &self.cfg_path, policy.action, &ids)
+    }
+
+    /// Enforce the retention policy periodically, blocks forever like `run_snapshots`.
+    pub fn run_retention(&self) {
+        if !self.settings().retention.is_enabled() {
+            return;
+        }
+
+        loop {
+            match self.apply_retention() {
+                Ok(summary) if summary.purged > 0 => eprintln!("retention: {:?}", summary),
+                Ok(_) => {}
+                Err(e) => eprintln!("retention failed: {}", e),
+            }
+            std::thread::sleep(RETENTION_INTERVAL);
+        }
+    }
+
+    pub fn storage_info(&self) -> StorageInfo {
+        StorageInfo {
+            current: *self.backend.read().unwrap(),
+            available: Self::available_backends(),
+        }
+    }
+
+    /// Copy every conversation from `from` into `to` and keep using `to` from
+    /// now on, also after a restart. `from` is left as it was. The stores
+    /// below encryption are copied, so nothing needs to be unlocked.
+    pub fn migrate_storage(&self, from: StorageBackend, to: StorageBackend, mut progress: impl FnMut(MigrationProgress)) -> Result<usize, String> {
+        if from == to {
+            return Err("storage is already there".to_string());
+        }
+        let current = *self.backend.read().unwrap();
+        let open = |backend| {
+            if backend == current {
+                Ok(self.base.backend())
+            } else {
+                Self::open_store(backend, &self.cfg_path, self.settings().storage_compression)
+            }
+        };
+        let source = open(from)?;
+        let target = open(to)?;
+
+        let progress = |done, total| progress(MigrationProgress { from, to, done, total });
+        let copied = if to != current {
+            // writes wait until the copy is done and go to `target` after it
+            self.base.switch(source.as_ref(), target, progress)?
+        } else {
+            storage::migrate(source.as_ref(), target.as_ref(), progress)?
+        };
+
+        if to != current {
+            *self.backend.write().unwrap() = to;
+            let name = serde_json::to_value(to).map_err(|e| e.to_string())?;
+            backup::write_setting(&self.cfg_path, "storage_backend", name.as_str().unwrap_or_default())?;
+        }
+
+        eprintln!("migrate_storage: {} conversations from {:?} to {:?}", copied, from, to);
+        Ok(copied)
     }
 
     fn load_settings<P: AsRef<Path>>(cfg_path: P) -> Result<Settings, ConfigError> {
         let mut fpath = PathBuf::from(cfg_path.as_ref());
-        fpath.push("chatgpt");
+        fpath.push(SETTINGS_NAME);
 
         let cfg = Config::builder()
             .set_default("model", COMPLETION_MODEL)?
             .set_default("stream", false)?
             .set_default("api_key", "")?
+            .set_default("snapshot_interval_hours", 24)?
+            .set_default("snapshot_keep", 7)?
+            .set_default("storage_compression", 3)?
             .add_source(File::with_name(fpath.as_path().to_str().unwrap()))
             .add_source(Environment::with_prefix("openai"))
             .build()?;
@@ -112,41 +516,320 @@ impl ChatGPT {
         cfg.try_deserialize()
     }
 
-    fn pick_api_key(&self) -> Option<&str> {
-        if self.settings.api_key.is_empty() {
-            let range = Uniform::from(0..self.settings.api_keys.len());
-            let i = self.rng.lock().unwrap().sample(range);
-            self.settings.api_keys.get(i).map(|s| s.as_str())
-        } else {
-            Some(self.settings.api_key.as_str())
+    fn pick_api_key(&self) -> Option<String> {
+        let keys = self.api_keys.read().unwrap();
+        if keys.is_empty() {
+            return None;
         }
+        let range = Uniform::from(0..keys.len());
+        let i = self.rng.lock().unwrap().sample(range);
+        keys.get(i).cloned()
     }
 
 
-    pub fn start_conversation(&self, hint: Option<String>) -> Result<ConversationId, String> {
-        Ok(self.store.start_conversation(hint))
+    pub fn start_conversation(&self, hint: Option<String>, prompt: Option<PromptRef>) -> Result<ConversationId, String> {
+        let store = self.store()?;
+        let id = store.start_conversation(hint)?;
+        if prompt.is_some() {
+            store.store_meta(id, ConversationMeta { prompt, ..Default::default() })?;
+        }
+        Ok(id)
     }
 
     pub fn get_conversations(&self) -> Result<Vec<ConversationId>, String> {
-        self.store.get_conversations()
+        self.store()?.get_conversations()
     }
     
-    pub fn get_conversation(&self, id: ConversationId) -> Result<Vec<Message>, String> {
-        self.store.get_conversation(id)
+    pub fn delete_conversation(&self, id: ConversationId) -> Result<(), String> {
+        self.store()?.delete_conversation(id)
+    }
+
+    // changes of the underlying store, events carry ids only so this works while locked
+    pub fn subscribe(&self) -> Result<Receiver<StorageEvent>, String> {
+        self.base().subscribe()
+    }
+    
+    pub fn get_conversation(&self, id: ConversationId) -> Result<Conversation, String> {
+        let store = self.store()?;
+        Ok(Conversation {
+            id,
+            messages: store.get_conversation(id)?,
+            title: store.get_title(id),
+            meta: store.get_meta(id)?,
+        })
+    }
+
+    pub fn get_conversation_tree(&self, id: ConversationId) -> Result<ConversationTree, String> {
+        self.store()?.get_tree(id)
+    }
+
+    pub fn get_messages(&self, id: ConversationId, before: Option<MessageId>, limit: usize) -> Result<Page<PathNode, MessageId>, String> {
+        self.store()?.get_messages(id, before, limit)
+    }
+
+    pub fn switch_branch(&self, id: ConversationId, message_id: MessageId) -> Result<Page<PathNode, MessageId>, String> {
+        let (version, mut tree) = self.store()?.get_versioned_tree(id)?;
+        if !tree.switch_to(message_id) {
+            return Err("no such message".to_string());
+        }
+        let page = tree.page(None, MESSAGE_PAGE_SIZE);
+        self.store()?.compare_and_set_current(id, version, tree.current)?;
+        Ok(page)
+    }
+
+    /// fork a sibling of the user message `message_id` with new content and
+    /// answer it, the original branch stays reachable through `switch_branch`
+    pub async fn edit_message(&self, id: ConversationId, message_id: MessageId, content: Content) -> Result<Page<PathNode, MessageId>, String> {
+        let (version, mut tree) = self.store()?.get_versioned_tree(id)?;
+        match tree.get(message_id) {
+            Some(node) if node.message.role == Role::User => {},
+            Some(_) => return Err("only user messages can be edited".to_string()),
+            None => return Err("no such message".to_string()),
+        }
+
+        refuse_images(&self.settings(), [&content])?;
+        // the edit is kept even when no answer comes
+        let question = tree.fork(message_id, Message::new_user(content)).ok_or("no such message".to_string())?;
+        let version = self.store()?.compare_and_store_tree(id, version, tree.clone())?;
+        let answer = self.generate_completion(tree.messages()).await?;
+        self.store_answer(id, version, question, answer)?;
+        self.store()?.get_messages(id, None, MESSAGE_PAGE_SIZE)
+    }
+
+    // Put `answer` below `question`, which was stored at `version`. Turns
+    // stored while the model was answering are kept, the answer then starts a
+    // branch of its own.
+    fn store_answer(&self, id: ConversationId, version: u64, question: MessageId, answer: Message) -> Result<(), String> {
+        match self.store()?.compare_and_append(id, version, answer.clone()) {
+            Err(e) if e == CONFLICT_ERROR => {}
+            result => return result.map(|_| ()),
+        }
+
+        for _ in 0..ANSWER_RETRIES {
+            let (version, mut tree) = self.store()?.get_versioned_tree(id)?;
+            if tree.get(question).is_none() {
+                return Err("the question was deleted before it was answered".to_string());
+            }
+            tree.add_child(Some(question), answer.clone());
+            match self.store()?.compare_and_store_tree(id, version, tree) {
+                Err(e) if e == CONFLICT_ERROR => continue,
+                result => return result.map(|_| ()),
+            }
+        }
+        Err(CONFLICT_ERROR.to_string())
+    }
+
+    pub fn get_meta(&self, id: ConversationId) -> Result<ConversationMeta, String> {
+        self.store()?.get_meta(id)
+    }
+
+    pub fn set_meta(&self, id: ConversationId, mut meta: ConversationMeta) -> Result<(), String> {
+        meta.tags = meta
+            .tags
+            .into_iter()
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .unique()
+            .collect();
+        meta.folder = meta.folder.map(|f| f.trim().to_string()).filter(|f| !f.is_empty());
+        self.store()?.store_meta(id, meta)
+    }
+
+    pub fn filter_conversations(&self, filter: ConversationFilter) -> Result<Vec<ConversationEntry>, String> {
+        self.store()?.filter_conversations(&filter)
+    }
+
+    pub fn list_conversations(&self, filter: ConversationFilter, after: Option<ConversationCursor>, limit: usize) -> Result<Page<ConversationSummary, ConversationCursor>, String> {
+        let store = self.store()?;
+        let page = store.list_conversations(&filter, after, limit)?;
+        let items = page
+            .items
+            .into_iter()
+            .map(|entry| summarize(store.as_ref(), entry))
+            .collect::<Result<_, _>>()?;
+        Ok(Page { items, next: page.next })
+    }
+
+    pub fn token_budget(&self, id: ConversationId) -> Result<TokenBudget, String> {
+        let messages = self.store()?.get_conversation(id)?;
+        let settings = self.settings();
+        Ok(TokenBudget::new(&settings.model, &messages, settings.context_window))
+    }
+
+    pub fn get_summary(&self, id: ConversationId) -> Result<ConversationSummary, String> {
+        let store = self.store()?;
+        let entry = ConversationEntry {
+            id,
+            meta: store.get_meta(id)?,
+            updated_at: store.get_updated(id)?,
+        };
+        summarize(store.as_ref(), entry)
+    }
+
+    pub fn search(&self, query: String) -> Result<Vec<SearchHit>, String> {
+        self.store()?.search(&query)
     }
 
     pub fn get_title(&self, id: ConversationId) -> Result<String, String> {
-        self.store.get_title(id).ok_or("no title".to_string())
+        self.store()?.get_title(id).ok_or("no title".to_string())
     }
 
     pub fn set_title(&self, id: ConversationId, title: String) -> Result<(), String> {
-        self.store.store_title(id, title)
+        self.store()?.store_title(id, title)
+    }
+
+    pub fn import_conversations<P: AsRef<Path>>(&self, path: P) -> Result<ImportSummary, String> {
+        let data = std::fs::read(path.as_ref()).map_err(|e| e.to_string())?;
+        let convs = crate::import::parse_export(&data)?;
+        let existing = self.store()?.get_conversations()?;
+
+        let mut summary = ImportSummary::default();
+        for conv in convs {
+            if existing.contains(&conv.id) || conv.tree.nodes.len() <= 1 {
+                summary.skipped += 1;
+                continue;
+            }
+
+            self.store()?.store_tree(conv.id, conv.tree)?;
+            if !conv.title.is_empty() {
+                self.store()?.store_title(conv.id, conv.title)?;
+            }
+            summary.imported += 1;
+        }
+
+        eprintln!("import_conversations: {:?}", summary);
+        Ok(summary)
+    }
+
+    // Fill an empty library with the bundled prompts, only ever once. Callers
+    // racing past the flag store the same ids, their versions included.
+    fn seed_prompts(&self) -> Result<(), String> {
+        if self.prompts_seeded.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let store = self.store()?;
+        let bundled = crate::prompts::bundled()?;
+        let stored = store.get_prompts()?;
+        // a library with only some of them is one being seeded right now
+        if stored.iter().all(|p| bundled.iter().any(|b| b.id == p.id)) {
+            for prompt in bundled {
+                if !stored.iter().any(|p| p.id == prompt.id) {
+                    record_prompt(store.as_ref(), None, prompt)?;
+                }
+            }
+        }
+        if self.prompts_seeded.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        if let Err(e) = crate::backup::write_setting(&self.cfg_path, "prompts_seeded", "true") {
+            eprintln!("seed prompts: {}", e);
+        }
+        Ok(())
+    }
+
+    pub fn list_prompts(&self) -> Result<Vec<Prompt>, String> {
+        self.seed_prompts()?;
+        let mut prompts = self.store()?.get_prompts()?;
+        Prompt::sort(&mut prompts);
+        Ok(prompts)
+    }
+
+    /// store `prompt` as a new version when its name or content changed
+    pub fn save_prompt(&self, prompt: Prompt) -> Result<Prompt, String> {
+        let prompt = crate::prompts::normalize(prompt)?;
+        let store = self.store()?;
+        let old = store.get_prompts()?.into_iter().find(|p| p.id == prompt.id);
+        record_prompt(store.as_ref(), old.as_ref(), prompt)
+    }
+
+    pub fn delete_prompt(&self, id: PromptId) -> Result<(), String> {
+        self.store()?.delete_prompt(id)
+    }
+
+    pub fn prompt_versions(&self, id: PromptId) -> Result<Vec<PromptVersion>, String> {
+        self.store()?.get_prompt_versions(id)
+    }
+
+    fn prompt_version(&self, prompt: PromptRef) -> Result<PromptVersion, String> {
+        self.prompt_versions(prompt.id)?
+            .into_iter()
+            .find(|v| v.version == prompt.version)
+            .ok_or_else(|| format!("prompt version {} not found", prompt.version))
+    }
+
+    /// Answer `input` once with each of two prompt versions as the system
+    /// prompt, their variables filled with `values` or their defaults.
+    /// Refused while a variable has neither. Nothing is stored.
+    pub async fn compare_prompts(
+        &self,
+        a: PromptRef,
+        b: PromptRef,
+        input: String,
+        values: HashMap<String, String>,
+    ) -> Result<(Message, Message), String> {
+        let dialogue = |prompt: PromptRef| -> Result<Vec<Message>, String> {
+            let version = self.prompt_version(prompt)?;
+            let template = Template::parse(&version.content);
+            let missing = template.missing(&values);
+            if !missing.is_empty() {
+                let names = missing.iter().map(|v| v.name.as_str()).collect::<Vec<_>>();
+                return Err(format!("fill in {} first", names.join(", ")));
+            }
+            Ok(vec![
+                Message::new_system(template.render(&values)),
+                Message::new_user(input.clone()),
+            ])
+        };
+        let (a, b) = (dialogue(a)?, dialogue(b)?);
+        futures::try_join!(self.generate_completion(a), self.generate_completion(b))
+    }
+
+    /// add the prompts of a `.json` or `.csv` file, skipping those with an id
+    /// or a name and content the library has already
+    pub fn import_prompts<P: AsRef<Path>>(&self, path: P) -> Result<ImportSummary, String> {
+        let imported = crate::prompts::read_file(path.as_ref())?;
+        self.seed_prompts()?;
+        let mut existing = self.store()?.get_prompts()?;
+
+        let mut summary = ImportSummary::default();
+        for prompt in imported {
+            let prompt = match crate::prompts::normalize(prompt) {
+                Ok(prompt) => prompt,
+                Err(_) => {
+                    summary.skipped += 1;
+                    continue;
+                }
+            };
+            if existing.iter().any(|p| p.id == prompt.id || (p.act == prompt.act && p.content == prompt.content)) {
+                summary.skipped += 1;
+                continue;
+            }
+
+            // numbered on from whatever history a deleted prompt with its id left
+            let prompt = record_prompt(self.store()?.as_ref(), None, prompt)?;
+            existing.push(prompt);
+            summary.imported += 1;
+        }
+
+        eprintln!("import_prompts: {:?}", summary);
+        Ok(summary)
     }
 
+    pub fn export_prompts<P: AsRef<Path>>(&self, path: P) -> Result<usize, String> {
+        let prompts = self.list_prompts()?;
+        crate::prompts::write_file(path.as_ref(), &prompts)?;
+        Ok(prompts.len())
+    }
+
+    /// Ask the model for a title of `id`. A title given meanwhile, by the
+    /// user or an earlier request, is kept and returned instead.
     pub async fn suggest_title(&self, id: ConversationId) -> Result<String, String> {
         eprintln!("suggest_title({:?})", id);
-        let msgs = self.store.get_conversation(id).and_then(|dialogue| {
-            let dialogue = dialogue.into_iter().take(6).map(|msg| msg.content).collect::<Vec<_>>().join("\n");
+        if let Some(title) = self.store()?.get_title(id) {
+            return Ok(title);
+        }
+        let msgs = self.store()?.get_conversation(id).and_then(|dialogue| {
+            let dialogue = dialogue.into_iter().take(6).map(|msg| msg.content.text()).collect::<Vec<_>>().join("\n");
 
             Ok(vec! {
                 Message::new_system("Act as a summarizer and summarize this conversation in ten words.".to_string()),
@@ -156,21 +839,37 @@ impl ChatGPT {
 
         match msgs {
             Ok(msgs) => {
-                self.generate_completion(msgs).await.map(|msg| {
-                    msg.content.chars().take(64).collect::<String>()
-                })
+                let title = self.generate_completion(msgs).await.map(|msg| {
+                    msg.content.text().chars().take(64).collect::<String>()
+                })?;
+                // stored right away, the sidebar picks it up from the change event
+                self.store()?.store_title_if_missing(id, title)
             },
             Err(e) => Err(e),
         }
     }
 
-    //FIXME: what if tokens in `messages` exceed 4096
     pub async fn generate_completion(&self, mut messages: Vec<Message>) -> Result<Message, String> {
+        let settings = self.settings();
+        refuse_images(&settings, messages.iter().map(|m| &m.content))?;
+        // the count is an estimate and the known windows can be out of date,
+        // the api gets the last word unless a window was configured
+        let budget = TokenBudget::new(&settings.model, &messages, settings.context_window);
+        if let (Some(window), Some(left)) = (budget.context_window, budget.remaining(0)) {
+            if left <= 0 {
+                let over = format!("the conversation takes {} tokens, {} reads at most {}", budget.used, budget.model, window);
+                if settings.context_window.is_some() {
+                    return Err(over);
+                }
+                eprintln!("generate_completion: {}", over);
+            }
+        }
+
         let data = Params {
-            model: &self.settings.model,
-            temperature: self.settings.temperature,
-            messages,
-            stream: self.settings.stream,
+            model: &settings.model,
+            temperature: settings.temperature,
+            messages: messages.into_iter().map(WireMessage::from).collect(),
+            stream: settings.stream,
         };
 
         let api_key = match self.pick_api_key() {
@@ -178,9 +877,11 @@ impl ChatGPT {
             None => return Err("api key is not set".to_string()),
         };
 
-        eprintln!("completion({})", serde_json::to_string(&data).unwrap());
-        eprintln!("api_key({}...)", api_key.get(..8).unwrap_or(""));
+        // the bodies carry the decrypted conversation, whole images and the
+        // answer, never log them
+        eprintln!("completion({}, {} messages)", data.model, data.messages.len());
 
+        let started = Instant::now();
         let mut retried = false;
         let resp = loop {
             match self.cli
@@ -206,11 +907,21 @@ impl ChatGPT {
                 }
         };
 
-        let data = resp.bytes().await.unwrap();
-        eprintln!("data: {}", String::from_utf8_lossy(&data));
+        let status = resp.status();
+        let data = resp.bytes().await.map_err(|e| format!("response error: {}", e))?;
+        eprintln!("answer({}, {} bytes)", status, data.len());
         match serde_json::from_slice::<Answer>(&data) {
             Ok(result) => {
-                Ok(result.choices[0].message.clone())
+                let choice = result.choices.into_iter().next().ok_or("no answer".to_string())?;
+                let mut msg = Message::new_assistant(choice.message.content);
+                msg.meta = MessageMeta {
+                    model: Some(result.model).filter(|m| !m.is_empty()),
+                    finish_reason: choice.finish_reason,
+                    latency_ms: Some(started.elapsed().as_millis() as u64),
+                    prompt_tokens: Some(result.usage.prompt_tokens),
+                    completion_tokens: Some(result.usage.completion_tokens),
+                };
+                Ok(msg)
             },
             Err(err) => {
                 eprintln!("answer: {:?}", err);
@@ -220,16 +931,25 @@ impl ChatGPT {
     }
 
 
-    pub async fn chat_completion(&self, id: ConversationId, mut messages: Vec<Message>) -> Result<Message, String> {
-        self.generate_completion(messages.clone()).await.and_then(|msg| {
-            messages.push(msg.clone());
-            self.store.store_conversation(id, messages).unwrap();
-            Ok(msg)
-        })
+    /// Append a user turn to the active branch of the stored conversation and
+    /// answer it. The turn is stored before the model is asked, so it survives
+    /// a failed request. Fails with `CONFLICT_ERROR` when the conversation was
+    /// written to between reading and storing the turn.
+    pub async fn append_user_message(&self, id: ConversationId, content: Content) -> Result<Message, String> {
+        refuse_images(&self.settings(), [&content])?;
+        let (version, mut tree) = self.store()?.get_versioned_tree(id)?;
+        let question = Message::new_user(content);
+        // only the new message is written, however long the conversation is
+        let version = self.store()?.compare_and_append(id, version, question.clone())?;
+        let question = tree.push(question);
+        let answer = self.generate_completion(tree.messages()).await?;
+        self.store_answer(id, version, question, answer.clone())?;
+        Ok(answer)
     }
 
     pub async fn generate_image(&self, params: GenerateImageParams) -> Result<GenerateImageResult, String> {
         eprintln!("params: {:?}", params);
+        params.validate()?;
         let api_key = match self.pick_api_key() {
             Some(key) => key,
             None => return Err("api key is not set".to_string()),
@@ -277,3 +997,88 @@ impl ChatGPT {
 }
 
 
+
+// images sent to a model that does not read them get the request refused,
+// `vision` in the settings overrides what is known about the model
+fn refuse_images<'a>(settings: &Settings, contents: impl IntoIterator<Item = &'a Content>) -> Result<(), String> {
+    let model = &settings.model;
+    let accepts = settings.vision.unwrap_or_else(|| common::model_accepts_images(model));
+    if !accepts && contents.into_iter().any(|c| !c.images().is_empty()) {
+        return Err(format!("{} does not read images, pick another model or set vision in settings", model));
+    }
+    Ok(())
+}
+
+// Store `prompt`, recording it as a new version unless its name and content
+// are those of `old`, the stored prompt with its id. Prompts stored before
+// there were versions get their old state recorded first. A number another
+// save took meanwhile keeps its version, this one goes on to the next free.
+fn record_prompt(store: &(dyn Storage + Send + Sync), old: Option<&Prompt>, mut prompt: Prompt) -> Result<Prompt, String> {
+    let now = backup::now_ms();
+    if let Some(old) = old.filter(|old| old.version == 0) {
+        let first = Prompt { version: 1, ..old.clone() };
+        store.store_prompt_version_if_missing(PromptVersion::new(&first, now))?;
+    }
+    match old {
+        Some(old) if old.act == prompt.act && old.content == prompt.content => prompt.version = old.version.max(1),
+        _ => {
+            prompt.version = old.map(|old| old.version.max(1) + 1).unwrap_or(1);
+            while !store.store_prompt_version_if_missing(PromptVersion::new(&prompt, now))? {
+                prompt.version += 1;
+            }
+        }
+    }
+    store.store_prompt(prompt.clone())?;
+    Ok(prompt)
+}
+
+// the sidebar line of `entry`, previewing the newest message of the active
+// branch that is not a system prompt. Only the newest couple are read, a
+// system prompt is followed by an answer or nothing at all.
+fn summarize(store: &(dyn Storage + Send + Sync), entry: ConversationEntry) -> Result<ConversationSummary, String> {
+    let newest = store.get_messages(entry.id, None, PREVIEW_MESSAGES)?;
+    let preview = newest
+        .items
+        .iter()
+        .rev()
+        .map(|node| &node.node.message)
+        .find(|msg| !matches!(msg.role, Role::System | Role::Developer))
+        .map(|msg| common::preview(&msg.content.text()))
+        .unwrap_or_default();
+    Ok(ConversationSummary {
+        id: entry.id,
+        title: store.get_title(entry.id),
+        meta: entry.meta,
+        updated_at: entry.updated_at,
+        preview,
+    })
+}
+
+#[cfg(all(test, feature = "local-storage"))]
+mod tests {
+    use super::*;
+    use common::Message;
+
+    fn summary(msgs: Vec<Message>) -> ConversationSummary {
+        let store = LocalStorage::new();
+        let id = store.start_conversation(None).unwrap();
+        store.store_conversation(id, msgs).unwrap();
+        let entry = store.filter_conversations(&ConversationFilter::default()).unwrap().remove(0);
+        summarize(&store, entry).unwrap()
+    }
+
+    #[test]
+    fn preview_is_the_newest_message() {
+        let answered = summary(vec![
+            Message::new_system("be brief"),
+            Message::new_user("what is a borrow?"),
+            Message::new_assistant("a reference"),
+        ]);
+        assert_eq!(answered.preview, "a reference");
+
+        // only a system prompt and a question, the question is newest
+        let asked = summary(vec![Message::new_system("be brief"), Message::new_user("hello")]);
+        assert_eq!(asked.preview, "hello");
+        assert_eq!(summary(vec![Message::new_system("be brief")]).preview, "");
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use common::{BackupSummary, ConversationId, ConversationMeta, ConversationTree, Prompt, PromptVersion, SnapshotInfo};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+
+use crate::storage::Storage;
+
+/// bumped whenever the layout of `Backup` changes incompatibly
+pub const BACKUP_VERSION: u32 = 1;
+
+pub static SETTINGS_NAME: &str = "chatgpt";
+pub static KEY_CHECK_FILE: &str = "encryption.json";
+// the check of a new key, until everything is sealed with it
+pub static NEXT_KEY_CHECK_FILE: &str = "encryption.next.json";
+static SNAPSHOT_DIR: &str = "snapshots";
+static SECRET_KEYS: [&str; 2] = ["api_key", "api_keys"];
+
+/// Everything needed to rebuild the data directory. Values are taken as the
+/// backend stores them, so an encrypted store stays encrypted in the archive
+/// and `encryption.json` travels along to unlock it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Backup {
+    pub version: u32,
+    // unix time in milliseconds
+    pub created_at: u64,
+    // whether api keys were kept in the settings file
+    pub secrets: bool,
+    pub conversations: Vec<BackupConversation>,
+    // missing in archives written before there was a prompt library
+    #[serde(default)]
+    pub prompts: Vec<Prompt>,
+    // missing in archives written before prompts had versions
+    #[serde(default)]
+    pub prompt_versions: Vec<PromptVersion>,
+    // file name in the config dir -> content
+    pub files: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupConversation {
+    pub id: ConversationId,
+    pub title: Option<String>,
+    pub meta: ConversationMeta,
+    pub tree: ConversationTree,
+}
+
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// the settings file is `chatgpt.<ext>` in any format the config crate reads
+fn settings_file(cfg_path: &Path) -> Option<PathBuf> {
+    std::fs::read_dir(cfg_path)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .find(|p| p.is_file() && p.file_stem().map(|s| s == SETTINGS_NAME).unwrap_or(false))
+}
+
+// drop the api keys of a settings file, or take them over from `from` when given
+fn edit_secrets(name: &str, text: &str, from: Option<&str>) -> Result<String, String> {
+    match Path::new(name).extension().and_then(|e| e.to_str()) {
+        Some("toml") => {
+            let mut value: toml::Value = toml::from_str(text).map_err(|e| e.to_string())?;
+            let from: Option<toml::Value> = from.and_then(|f| toml::from_str(f).ok());
+            if let Some(table) = value.as_table_mut() {
+                for key in SECRET_KEYS {
+                    table.remove(key);
+                    if let Some(v) = from.as_ref().and_then(|f| f.get(key)) {
+                        table.insert(key.to_string(), v.clone());
+                    }
+                }
+            }
+            toml::to_string_pretty(&value).map_err(|e| e.to_string())
+        }
+        Some("json") => {
+            let mut value: serde_json::Value =
+                serde_json::from_str(text).map_err(|e| e.to_string())?;
+            let from: Option<serde_json::Value> = from.and_then(|f| serde_json::from_str(f).ok());
+            if let Some(map) = value.as_object_mut() {
+                for key in SECRET_KEYS {
+                    map.remove(key);
+                    if let Some(v) = from.as_ref().and_then(|f| f.get(key)) {
+                        map.insert(key.to_string(), v.clone());
+                    }
+                }
+            }
+            serde_json::to_string_pretty(&value).map_err(|e| e.to_string())
+        }
+        _ => Err(format!("can't strip api keys from {}", name)),
+    }
+}
+
+/// Replace the content of `path` so that it is either the old or the new
+/// content, even if the app dies halfway.
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<(), String> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, data).map_err(|e| e.to_string())?;
+    std::fs::rename(&tmp, path).map_err(|e| e.to_string())
+}
+
+/// Pass the api keys of the settings file through `reseal`, keeping everything else.
+pub fn reseal_secrets(cfg_path: &Path, reseal: impl Fn(&str) -> Result<String, String>) -> Result<(), String> {
+    let path = match settings_file(cfg_path) {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let text = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    let text = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => {
+            let mut settings: toml::Value = toml::from_str(&text).map_err(|e| e.to_string())?;
+            for key in SECRET_KEYS {
+                match settings.get_mut(key) {
+                    Some(toml::Value::String(s)) => *s = reseal(s)?,
+                    Some(toml::Value::Array(keys)) => {
+                        for value in keys.iter_mut() {
+                            if let toml::Value::String(s) = value {
+                                *s = reseal(s)?;
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            toml::to_string_pretty(&settings).map_err(|e| e.to_string())?
+        }
+        Some("json") => {
+            let mut settings: serde_json::Value = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+            for key in SECRET_KEYS {
+                match settings.get_mut(key) {
+                    Some(serde_json::Value::String(s)) => *s = reseal(s)?,
+                    Some(serde_json::Value::Array(keys)) => {
+                        for value in keys.iter_mut() {
+                            if let serde_json::Value::String(s) = value {
+                                *s = reseal(s)?;
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            serde_json::to_string_pretty(&settings).map_err(|e| e.to_string())?
+        }
+        _ => return Err(format!("can't change {}", path.display())),
+    };
+    write_atomic(&path, text.as_bytes())
+}
+
+/// Set `key` of the settings file to the string `value`, keeping everything else.
+pub fn write_setting(cfg_path: &Path, key: &str, value: &str) -> Result<(), String> {
+    let path = settings_file(cfg_path).ok_or("no settings file".to_string())?;
+    let text = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    let text = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => {
+            let mut settings: toml::Value = toml::from_str(&text).map_err(|e| e.to_string())?;
+            if let Some(table) = settings.as_table_mut() {
+                table.insert(key.to_string(), toml::Value::String(value.to_string()));
+            }
+            toml::to_string_pretty(&settings).map_err(|e| e.to_string())?
+        }
+        Some("json") => {
+            let mut settings: serde_json::Value = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+            if let Some(map) = settings.as_object_mut() {
+                map.insert(key.to_string(), serde_json::Value::String(value.to_string()));
+            }
+            serde_json::to_string_pretty(&settings).map_err(|e| e.to_string())?
+        }
+        _ => return Err(format!("can't change {}", path.display())),
+    };
+    write_atomic(&path, text.as_bytes())
+}
+
+/// Collect conversations and prompts from `store` and the files of `cfg_path` into a backup.
+/// Api keys are removed from the settings unless `include_secrets` is set.
+pub fn collect<S: Storage + ?Sized>(
+    store: &S,
+    cfg_path: &Path,
+    include_secrets: bool,
+) -> Result<Backup, String> {
+    let mut conversations = vec![];
+    for id in store.get_conversations()? {
+        conversations.push(BackupConversation {
+            id,
+            title: store.get_title(id),
+            meta: store.get_meta(id)?,
+            tree: store.get_tree(id)?,
+        });
+    }
+
+    let mut files = BTreeMap::new();
+    if let Some(path) = settings_file(cfg_path) {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let text = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        if include_secrets {
+            files.insert(name, text);
+        } else {
+            match edit_secrets(&name, &text, None) {
+                Ok(text) => {
+                    files.insert(name, text);
+                }
+                Err(e) => eprintln!("backup: settings skipped, {}", e),
+            }
+        }
+    }
+    if let Ok(text) = std::fs::read_to_string(cfg_path.join(KEY_CHECK_FILE)) {
+        files.insert(KEY_CHECK_FILE.to_string(), text);
+    }
+
+    let prompts = store.get_prompts()?;
+    // deleted prompts keep the versions conversations were started with
+    let mut started_with = conversations
+        .iter()
+        .filter_map(|c| c.meta.prompt.map(|p| p.id))
+        .chain(prompts.iter().map(|p| p.id))
+        .collect::<Vec<_>>();
+    started_with.sort_by_key(|id| id.0);
+    started_with.dedup();
+    let mut prompt_versions = vec![];
+    for id in started_with {
+        prompt_versions.extend(store.get_prompt_versions(id)?);
+    }
+
+    Ok(Backup {
+        version: BACKUP_VERSION,
+        created_at: now_ms(),
+        secrets: include_secrets,
+        conversations,
+        prompts,
+        prompt_versions,
+        files,
+    })
+}
+
+pub fn write_archive(path: &Path, backup: &Backup) -> Result<(), String> {
+    let data = serde_json::to_vec(backup).map_err(|e| e.to_string())?;
+    // write aside and rename, a crash midway must not leave a truncated archive
+    let tmp = path.with_extension("part");
+    let file = std::fs::File::create(&tmp).map_err(|e| e.to_string())?;
+    let mut enc = GzEncoder::new(file, Compression::default());
+    enc.write_all(&data).map_err(|e| e.to_string())?;
+    enc.finish().map_err(|e| e.to_string())?;
+    std::fs::rename(&tmp, path).map_err(|e| e.to_string())
+}
+
+pub fn read_archive(path: &Path) -> Result<Backup, String> {
+    let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+    let mut data = vec![];
+    GzDecoder::new(file)
+        .read_to_end(&mut data)
+        .map_err(|e| format!("not a backup archive: {}", e))?;
+
+    let backup: Backup =
+        serde_json::from_slice(&data).map_err(|e| format!("invalid backup: {}", e))?;
+    if backup.version > BACKUP_VERSION {
+        return Err(format!(
+            "backup version {} is newer than supported {}",
+            backup.version, BACKUP_VERSION
+        ));
+    }
+    Ok(backup)
+}
+
+/// Write the content of `backup` into `store` and `cfg_path`. Conversations
+/// and prompts already stored under the same id are replaced, others are left
+/// alone.
+/// A settings file without secrets keeps the api keys configured now.
+pub fn restore<S: Storage + ?Sized>(
+    store: &S,
+    cfg_path: &Path,
+    backup: Backup,
+) -> Result<BackupSummary, String> {
+    let mut summary = BackupSummary::default();
+    for conv in backup.conversations {
+        store.store_tree(conv.id, conv.tree)?;
+        if let Some(title) = conv.title {
+            store.store_title(conv.id, title)?;
+        }
+        store.store_meta(conv.id, conv.meta)?;
+        summary.conversations += 1;
+    }
+    for version in backup.prompt_versions {
+        store.store_prompt_version(version)?;
+    }
+    for prompt in backup.prompts {
+        store.store_prompt(prompt)?;
+        summary.prompts += 1;
+    }
+
+    for (name, mut text) in backup.files {
+        // names come from the archive, only take the files `collect` puts there
+        let is_settings = Path::new(&name).file_stem().map(|s| s == SETTINGS_NAME).unwrap_or(false)
+            && Path::new(&name).extension().is_some();
+        if !is_settings && name != KEY_CHECK_FILE {
+            eprintln!("restore: skip file {:?}", name);
+            continue;
+        }
+
+        if is_settings {
+            let current = settings_file(cfg_path);
+            if !backup.secrets {
+                let old = current.as_ref().and_then(|p| std::fs::read_to_string(p).ok());
+                text = edit_secrets(&name, &text, old.as_deref())?;
+            }
+            // the config crate would pick one of several settings files at random
+            if let Some(current) = current.filter(|p| !p.ends_with(&name)) {
+                std::fs::remove_file(current).map_err(|e| e.to_string())?;
+            }
+        }
+
+        std::fs::write(cfg_path.join(&name), text).map_err(|e| e.to_string())?;
+        summary.files += 1;
+    }
+
+    Ok(summary)
+}
+
+pub fn snapshot_dir(cfg_path: &Path) -> PathBuf {
+    cfg_path.join(SNAPSHOT_DIR)
+}
+
+/// snapshots in `cfg_path`, newest first
+pub fn list_snapshots(cfg_path: &Path) -> Vec<SnapshotInfo> {
+    let entries = match std::fs::read_dir(snapshot_dir(cfg_path)) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    let mut snapshots = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().into_owned();
+            let created_at = name
+                .strip_prefix("snapshot-")?
+                .strip_suffix(".json.gz")?
+                .parse()
+                .ok()?;
+            Some(SnapshotInfo {
+                path: e.path().to_string_lossy().into_owned(),
+                created_at,
+                size: e.metadata().map(|m| m.len()).unwrap_or(0),
+            })
+        })
+        .collect::<Vec<_>>();
+    snapshots.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    snapshots
+}
+
+/// Write a snapshot of `store` without secrets and drop all but the `keep` newest.
+pub fn take_snapshot<S: Storage + ?Sized>(
+    store: &S,
+    cfg_path: &Path,
+    keep: usize,
+) -> Result<SnapshotInfo, String> {
+    let dir = snapshot_dir(cfg_path);
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+
+    let backup = collect(store, cfg_path, false)?;
+    let path = dir.join(format!("snapshot-{}.json.gz", backup.created_at));
+    write_archive(&path, &backup)?;
+
+    for old in list_snapshots(cfg_path).into_iter().skip(keep.max(1)) {
+        if let Err(e) = std::fs::remove_file(&old.path) {
+            eprintln!("snapshot: remove {}: {}", old.path, e);
+        }
+    }
+
+    list_snapshots(cfg_path)
+        .into_iter()
+        .find(|s| s.created_at == backup.created_at)
+        .ok_or("snapshot was not written".to_string())
+}
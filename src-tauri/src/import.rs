@@ -0,0 +1,315 @@
+use std::collections::{HashMap, VecDeque};
+
+use common::{ConversationId, ConversationTree, Message, MessageId, MessageNode, Role};
+use serde::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+/// a conversation from the official `conversations.json` export, with all of
+/// its branches and the one that was active (`current_node`) selected. When
+/// it was created and last updated is kept in the times of its messages.
+#[derive(Debug, Clone)]
+pub struct ImportedConversation {
+    pub id: ConversationId,
+    pub title: String,
+    pub tree: ConversationTree,
+}
+
+fn unix_ms(secs: f64) -> u64 {
+    (secs * 1000.0) as u64
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportConversation {
+    id: Option<String>,
+    conversation_id: Option<String>,
+    #[serde(default)]
+    title: Option<String>,
+    create_time: Option<f64>,
+    update_time: Option<f64>,
+    #[serde(default)]
+    mapping: HashMap<String, ExportNode>,
+    current_node: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportNode {
+    message: Option<ExportMessage>,
+    parent: Option<String>,
+    #[serde(default)]
+    children: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportMessage {
+    author: ExportAuthor,
+    create_time: Option<f64>,
+    content: Option<ExportContent>,
+    #[serde(default)]
+    metadata: ExportMetadata,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ExportMetadata {
+    model_slug: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportAuthor {
+    role: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportContent {
+    content_type: String,
+    #[serde(default)]
+    parts: Vec<Value>,
+    #[serde(default)]
+    text: Option<String>,
+}
+
+impl ExportContent {
+    // only textual content is kept, images and other attachments are dropped
+    fn text(&self) -> Option<String> {
+        match self.content_type.as_str() {
+            "text" | "multimodal_text" => {
+                let text = self
+                    .parts
+                    .iter()
+                    .filter_map(|p| p.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                Some(text)
+            }
+            "code" => self.text.clone(),
+            _ => None,
+        }
+    }
+}
+
+impl ExportMessage {
+    // messages without a time of their own get `fallback`, unix ms
+    fn to_message(&self, fallback: u64) -> Option<Message> {
+        let content = self.content.as_ref().and_then(|c| c.text())?;
+        if content.trim().is_empty() {
+            return None;
+        }
+
+        let role = match self.author.role.parse() {
+            Ok(role @ (Role::System | Role::User | Role::Assistant)) => role,
+            // tool and plugin outputs have no counterpart in the app
+            _ => return None,
+        };
+        let mut msg = Message::new(role, content);
+        msg.created_at = self.create_time.map(unix_ms).unwrap_or(fallback);
+        msg.meta.model = self.metadata.model_slug.clone();
+        Some(msg)
+    }
+}
+
+impl ExportConversation {
+    // the id of the export, or one derived from the content so importing the
+    // same file again finds the conversations it already has
+    fn id(&self) -> ConversationId {
+        let exported = self
+            .id
+            .as_deref()
+            .or(self.conversation_id.as_deref())
+            .and_then(|id| Uuid::parse_str(id).ok());
+        let id = exported.unwrap_or_else(|| {
+            let mut nodes = self.mapping.keys().map(|k| k.as_str()).collect::<Vec<_>>();
+            nodes.sort_unstable();
+            let key = format!(
+                "chatgpt-export\0{}\0{:?}\0{}",
+                self.title.as_deref().unwrap_or_default(),
+                self.create_time,
+                nodes.join("\0")
+            );
+            Uuid::new_v5(&Uuid::NAMESPACE_OID, key.as_bytes())
+        });
+        ConversationId(id)
+    }
+
+    fn into_imported(self) -> ImportedConversation {
+        let id = self.id();
+        let created = self
+            .create_time
+            .or(self.update_time)
+            .map(unix_ms)
+            .unwrap_or_else(crate::backup::now_ms);
+        let mut tree = ConversationTree::default();
+        // export node id -> the node it was imported as, or its nearest imported ancestor
+        let mut imported: HashMap<&str, Option<MessageId>> = HashMap::new();
+
+        let mut roots = self
+            .mapping
+            .iter()
+            .filter(|(_, n)| n.parent.as_deref().map(|p| !self.mapping.contains_key(p)).unwrap_or(true))
+            .map(|(id, _)| id.as_str())
+            .collect::<Vec<_>>();
+        roots.sort();
+
+        // breadth first, so parents are always added before their children and
+        // siblings keep the order the export lists them in
+        let mut queue = roots.into_iter().map(|r| (r, None)).collect::<VecDeque<_>>();
+        while let Some((node_id, parent)) = queue.pop_front() {
+            if imported.contains_key(node_id) {
+                continue;
+            }
+            let node = match self.mapping.get(node_id) {
+                Some(node) => node,
+                None => continue,
+            };
+
+            // untimed messages are as old as the message before them
+            let fallback = parent
+                .and_then(|p| tree.get(p))
+                .map(|n| n.message.created_at)
+                .unwrap_or(created);
+            let this = match node.message.as_ref().and_then(|m| m.to_message(fallback)) {
+                Some(msg) => Some(tree.add_child(parent, msg)),
+                None => parent,
+            };
+            imported.insert(node_id, this);
+            queue.extend(node.children.iter().map(|c| (c.as_str(), this)));
+        }
+
+        if !tree.children(None).any(|n| n.message.role == Role::System) && !tree.is_empty() {
+            let mut message = Message::new_system("act as a general chat.");
+            message.created_at = created;
+            let root = MessageNode {
+                parent: None,
+                message,
+            };
+            for node in tree.nodes.iter_mut().filter(|n| n.parent.is_none()) {
+                node.parent = Some(root.id());
+            }
+            tree.nodes.insert(0, root);
+        }
+
+        let current = self
+            .current_node
+            .as_deref()
+            .and_then(|leaf| imported.get(leaf).copied().flatten());
+        match current {
+            Some(leaf) => tree.current = Some(leaf),
+            None => {
+                if let Some(root) = tree.nodes.first().map(|n| n.id()) {
+                    tree.switch_to(root);
+                }
+            }
+        }
+
+        ImportedConversation {
+            id,
+            title: self.title.unwrap_or_default(),
+            tree,
+        }
+    }
+}
+
+/// parse the content of an exported `conversations.json`
+pub fn parse_export(data: &[u8]) -> Result<Vec<ImportedConversation>, String> {
+    let convs: Vec<ExportConversation> =
+        serde_json::from_slice(data).map_err(|e| format!("invalid export: {}", e))?;
+
+    Ok(convs.into_iter().map(|c| c.into_imported()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a question answered twice, the second answer being the active branch,
+    // plus a tool call that is dropped
+    const EXPORT: &str = r#"[{
+        "title": "Rust help",
+        "create_time": 1700000000.5,
+        "update_time": 1700000100.0,
+        "current_node": "a2",
+        "mapping": {
+            "root": { "message": null, "parent": null, "children": ["q"] },
+            "q": {
+                "message": { "author": { "role": "user" }, "create_time": 1700000001.0,
+                    "content": { "content_type": "text", "parts": ["what is a borrow?"] } },
+                "parent": "root", "children": ["a1", "t"]
+            },
+            "a1": {
+                "message": { "author": { "role": "assistant" }, "create_time": null,
+                    "content": { "content_type": "text", "parts": ["a reference"] },
+                    "metadata": { "model_slug": "gpt-4" } },
+                "parent": "q", "children": []
+            },
+            "t": {
+                "message": { "author": { "role": "tool" }, "create_time": 1700000002.0,
+                    "content": { "content_type": "text", "parts": ["search results"] } },
+                "parent": "q", "children": ["a2"]
+            },
+            "a2": {
+                "message": { "author": { "role": "assistant" }, "create_time": 1700000003.0,
+                    "content": { "content_type": "code", "text": "let r = &x;" } },
+                "parent": "t", "children": []
+            }
+        }
+    }]"#;
+
+    fn texts(tree: &ConversationTree) -> Vec<String> {
+        tree.messages().iter().map(|m| m.content.text()).collect()
+    }
+
+    #[test]
+    fn keeps_branches_and_the_active_one() {
+        let conv = parse_export(EXPORT.as_bytes()).unwrap().remove(0);
+        assert_eq!(conv.title, "Rust help");
+        assert_eq!(texts(&conv.tree), ["act as a general chat.", "what is a borrow?", "let r = &x;"]);
+
+        // the tool message is skipped, its answer hangs off the question
+        let path = conv.tree.path();
+        let answers = conv.tree.siblings(path[2].id());
+        assert_eq!(answers.len(), 2);
+        let other = conv.tree.get(answers[0]).unwrap();
+        assert_eq!(other.message.content.text(), "a reference");
+        assert_eq!(other.message.meta.model.as_deref(), Some("gpt-4"));
+    }
+
+    #[test]
+    fn keeps_timestamps() {
+        let conv = parse_export(EXPORT.as_bytes()).unwrap().remove(0);
+        let path = conv.tree.path();
+        assert_eq!(path[0].message.created_at, 1_700_000_000_500);
+        assert_eq!(path[1].message.created_at, 1_700_000_001_000);
+        // an answer without a time of its own is as old as its question
+        let other = conv.tree.get(conv.tree.siblings(path[2].id())[0]).unwrap();
+        assert_eq!(other.message.created_at, 1_700_000_001_000);
+        assert_eq!(conv.tree.updated_at(), 1_700_000_003_000);
+    }
+
+    #[test]
+    fn ids_are_stable() {
+        let first = parse_export(EXPORT.as_bytes()).unwrap().remove(0);
+        let again = parse_export(EXPORT.as_bytes()).unwrap().remove(0);
+        assert_eq!(first.id, again.id);
+
+        let renamed = EXPORT.replace("Rust help", "Borrowing");
+        assert_ne!(parse_export(renamed.as_bytes()).unwrap()[0].id, first.id);
+
+        let given = EXPORT.replacen("[{", r#"[{"id": "6b1f3c2e-0c1a-4a53-9d55-3f8a4c1e2b7d","#, 1);
+        assert_eq!(
+            parse_export(given.as_bytes()).unwrap()[0].id.0.to_string(),
+            "6b1f3c2e-0c1a-4a53-9d55-3f8a4c1e2b7d"
+        );
+    }
+
+    #[test]
+    fn active_branch_falls_back_to_the_first() {
+        let export = EXPORT.replace(r#""current_node": "a2","#, "");
+        let conv = parse_export(export.as_bytes()).unwrap().remove(0);
+        assert_eq!(texts(&conv.tree).len(), 3);
+    }
+
+    #[test]
+    fn rejects_invalid_json() {
+        assert!(parse_export(b"{}").is_err());
+        assert!(parse_export(b"[]").unwrap().is_empty());
+    }
+}
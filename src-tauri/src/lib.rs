@@ -1,2 +1,6 @@
 pub mod api;
+pub mod backup;
+pub mod import;
+pub mod prompts;
+pub mod retention;
 pub mod storage;
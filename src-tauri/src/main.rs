@@ -4,86 +4,105 @@
 )]
 
 use chatgpt_backend::api;
-use common::{ConversationId, Prompt, GenerateImageResult, GenerateImageParams};
-use tauri::{CustomMenuItem, Manager, Menu, Submenu, WindowMenuEvent};
-
-#[tauri::command]
-async fn completion<'r>(
-    id: ConversationId,
-    messages: Vec<api::Message>,
-    state: tauri::State<'r, api::ChatGPT>,
-) -> Result<api::Message, String> {
-    state.chat_completion(id, messages).await
+use common::ipc::{self, Command, Reply};
+use futures::future::BoxFuture;
+use common::{MIGRATION_EVENT, STORAGE_EVENT};
+use tauri::{AppHandle, CustomMenuItem, Manager, Menu, Submenu, WindowMenuEvent};
+
+// every command takes its arguments as one `args` struct from `common::ipc`,
+// the frontend calls them through the same types. The commands and their
+// registration are generated from the list there, a command without a
+// `Handler` does not compile.
+
+trait Handler: Command {
+    fn handle<'a>(self, state: &'a api::ChatGPT, app: &'a AppHandle) -> BoxFuture<'a, Reply<Self>>;
 }
 
-
-#[tauri::command]
-async fn generate_image<'r>(
-    req: GenerateImageParams,
-    state: tauri::State<'r, api::ChatGPT>
-) -> Result<GenerateImageResult, String> {
-    state.generate_image(req).await
+macro_rules! handlers {
+    ($(
+        $(#[$doc:meta])*
+        $name:ident => $command:ident { $($field:ident: $ty:ty),* $(,)? } -> $output:ty;
+    )*) => {
+        $(
+            #[tauri::command]
+            async fn $name<'r>(
+                args: ipc::$command,
+                app: AppHandle,
+                state: tauri::State<'r, api::ChatGPT>,
+            ) -> Reply<ipc::$command> {
+                args.handle(&state, &app).await
+            }
+        )*
+
+        fn invoke_handler() -> impl Fn(tauri::Invoke) + Send + Sync + 'static {
+            tauri::generate_handler![$($name),*]
+        }
+    };
 }
 
-#[tauri::command]
-fn start_conversation<'r>(hint: Option<String>, state: tauri::State<'r, api::ChatGPT>) -> Result<ConversationId, String> {
-    state.start_conversation(hint)
+common::with_commands!(handlers);
+
+// `Handler` for `ipc::$command`, `$body` is run in an async block
+macro_rules! handle {
+    ($command:ident($args:pat, $state:ident $(, $app:ident)?) => $body:expr) => {
+        impl Handler for ipc::$command {
+            fn handle<'a>(self, state: &'a api::ChatGPT, _app: &'a AppHandle) -> BoxFuture<'a, Reply<Self>> {
+                let ($args, $state) = (self, state);
+                $(let $app = _app;)?
+                Box::pin(async move { $body })
+            }
+        }
+    };
 }
 
-#[tauri::command]
-fn get_conversations<'r>(
-    state: tauri::State<'r, api::ChatGPT>,
-) -> Result<Vec<ConversationId>, String> {
-    state.get_conversations()
-}
-
-#[tauri::command]
-fn get_conversation<'r>(
-    id: ConversationId,
-    state: tauri::State<'r, api::ChatGPT>,
-) -> Result<Vec<api::Message>, String> {
-    state.get_conversation(id)
-}
-
-#[tauri::command]
-fn get_title<'r>(
-    id: ConversationId,
-    state: tauri::State<'r, api::ChatGPT>,
-) -> Result<String, String> {
-    state.get_title(id)
-}
-
-#[tauri::command]
-fn set_title<'r>(
-    id: ConversationId,
-    title: String,
-    state: tauri::State<'r, api::ChatGPT>,
-) -> Result<(), String> {
-    state.set_title(id, title)
-}
-
-#[tauri::command]
-async fn suggest_title<'r>(
-    id: ConversationId,
-    state: tauri::State<'r, api::ChatGPT>,
-) -> Result<String, String> {
-    state.suggest_title(id).await
-}
-
-#[tauri::command]
-fn bundled_prompts() -> Result<Vec<Prompt>, String> {
-    use itertools::Itertools;
-
-    let file = include_bytes!("../prompts.csv");
-    let mut rdr = csv::ReaderBuilder::new().from_reader(&file[..]);
-    rdr.records()
-        .map_ok(|r| Prompt {
-            act: r.get(0).unwrap().to_owned(),
-            content: r.get(1).unwrap().to_owned(),
-        })
-        .try_collect::<_, Vec<_>, _>()
-        .map_err(|e| e.to_string())
-}
+handle!(AppendUserMessage(args, state) => state.append_user_message(args.id, args.content).await);
+handle!(GenerateImage(args, state) => state.generate_image(args.params).await);
+handle!(StartConversation(args, state) => state.start_conversation(args.hint, args.prompt));
+handle!(GetConversations(_, state) => state.get_conversations());
+handle!(DeleteConversation(args, state) => state.delete_conversation(args.id));
+handle!(GetConversation(args, state) => state.get_conversation(args.id));
+handle!(GetConversationTree(args, state) => state.get_conversation_tree(args.id));
+handle!(GetMessages(args, state) => state.get_messages(args.id, args.before, args.limit));
+handle!(SwitchBranch(args, state) => state.switch_branch(args.id, args.message_id));
+handle!(EditMessage(args, state) => state.edit_message(args.id, args.message_id, args.content).await);
+handle!(GetMeta(args, state) => state.get_meta(args.id));
+handle!(SetMeta(args, state) => state.set_meta(args.id, args.meta));
+handle!(FilterConversations(args, state) => state.filter_conversations(args.filter));
+handle!(ListConversations(args, state) => state.list_conversations(args.filter, args.after, args.limit));
+handle!(GetTokenBudget(args, state) => state.token_budget(args.id));
+handle!(GetSummary(args, state) => state.get_summary(args.id));
+handle!(Search(args, state) => state.search(args.query));
+handle!(GetTitle(args, state) => state.get_title(args.id));
+handle!(SetTitle(args, state) => state.set_title(args.id, args.title));
+handle!(SuggestTitle(args, state) => state.suggest_title(args.id).await);
+handle!(ImportConversations(args, state) => state.import_conversations(args.path));
+handle!(GetEncryptionStatus(_, state) => Ok(state.encryption_status()));
+handle!(Unlock(args, state) => state.unlock(args.passphrase));
+handle!(EnableEncryption(args, state) => state.enable_encryption(args.passphrase));
+handle!(Rekey(args, state) => state.rekey(args.old_passphrase, args.new_passphrase));
+handle!(EncryptSecret(args, state) => state.encrypt_secret(args.value));
+handle!(CreateBackup(args, state) => state.create_backup(args.path, args.include_secrets));
+handle!(RestoreBackup(args, state) => state.restore_backup(args.path));
+handle!(ListSnapshots(_, state) => Ok(state.list_snapshots()));
+handle!(TakeSnapshot(_, state) => state.take_snapshot());
+handle!(GetRetentionPreview(_, state) => state.retention_preview());
+handle!(ApplyRetention(_, state) => state.apply_retention());
+handle!(GetStorageInfo(_, state) => Ok(state.storage_info()));
+// progress goes out as events
+handle!(MigrateStorage(args, state, app) => {
+    state.migrate_storage(args.from, args.to, |progress| {
+        if let Err(e) = app.emit_all(MIGRATION_EVENT, progress) {
+            eprintln!("emit {:?}: {}", progress, e);
+        }
+    })
+});
+handle!(ListPrompts(_, state) => state.list_prompts());
+handle!(SavePrompt(args, state) => state.save_prompt(args.prompt));
+handle!(DeletePrompt(args, state) => state.delete_prompt(args.id));
+handle!(GetPromptVersions(args, state) => state.prompt_versions(args.id));
+handle!(ComparePrompts(args, state) => state.compare_prompts(args.a, args.b, args.input, args.values).await);
+handle!(ImportPrompts(args, state) => state.import_prompts(args.path));
+handle!(ExportPrompts(args, state) => state.export_prompts(args.path));
 
 fn build_menu() -> Menu {
     let chats = CustomMenuItem::new("id_chats", "Chat");
@@ -115,19 +134,27 @@ fn main() {
 
             app.handle().manage(api::ChatGPT::new(cfg));
 
+            let handle = app.handle();
+            std::thread::spawn(move || handle.state::<api::ChatGPT>().run_snapshots());
+
+            let handle = app.handle();
+            std::thread::spawn(move || handle.state::<api::ChatGPT>().run_retention());
+
+            // every window refreshes what it shows from these, they keep
+            // coming from the new backend when storage moves to another one
+            let events = app.state::<api::ChatGPT>().subscribe()?;
+            let handle = app.handle();
+            std::thread::spawn(move || {
+                for event in events {
+                    if let Err(e) = handle.emit_all(STORAGE_EVENT, event) {
+                        eprintln!("emit {:?}: {}", event, e);
+                    }
+                }
+            });
+
             Ok(())
         })
-        .invoke_handler(tauri::generate_handler![
-            completion,
-            start_conversation,
-            get_conversations,
-            get_conversation,
-            get_title,
-            set_title,
-            suggest_title,
-            bundled_prompts,
-            generate_image,
-        ])
+        .invoke_handler(invoke_handler())
         .menu(build_menu())
         .on_menu_event(handle_menu_event)
         .run(tauri::generate_context!())
@@ -0,0 +1,98 @@
+use std::path::Path;
+
+use common::{Prompt, PromptId};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// the library a fresh install starts with
+static BUNDLED: &[u8] = include_bytes!("../prompts.csv");
+
+/// a line of a prompt csv, `act,prompt` like the bundled file with the
+/// other columns optional
+#[derive(Debug, Serialize, Deserialize)]
+struct CsvPrompt {
+    act: String,
+    prompt: String,
+    #[serde(default)]
+    category: Option<String>,
+    #[serde(default)]
+    favorite: Option<bool>,
+}
+
+/// The bundled prompts, each with an id derived from its name, so seeding
+/// the library twice at once stores every prompt once.
+pub fn bundled() -> Result<Vec<Prompt>, String> {
+    let mut prompts = parse_csv(BUNDLED)?;
+    for prompt in prompts.iter_mut() {
+        let key = format!("bundled-prompt\0{}", prompt.act);
+        prompt.id = PromptId(Uuid::new_v5(&Uuid::NAMESPACE_OID, key.as_bytes()));
+    }
+    Ok(prompts)
+}
+
+pub fn parse_csv(data: &[u8]) -> Result<Vec<Prompt>, String> {
+    let mut rdr = csv::ReaderBuilder::new().flexible(true).from_reader(data);
+    rdr.deserialize::<CsvPrompt>()
+        .map(|r| {
+            let r = r.map_err(|e| e.to_string())?;
+            let mut prompt = Prompt::new(r.act, r.prompt);
+            prompt.category = r.category.filter(|c| !c.is_empty());
+            prompt.favorite = r.favorite.unwrap_or(false);
+            Ok(prompt)
+        })
+        .collect()
+}
+
+fn write_csv(prompts: &[Prompt]) -> Result<Vec<u8>, String> {
+    let mut wtr = csv::Writer::from_writer(vec![]);
+    for p in prompts {
+        wtr.serialize(CsvPrompt {
+            act: p.act.clone(),
+            prompt: p.content.clone(),
+            category: p.category.clone(),
+            favorite: Some(p.favorite),
+        })
+        .map_err(|e| e.to_string())?;
+    }
+    wtr.into_inner().map_err(|e| e.to_string())
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().map(|e| e.eq_ignore_ascii_case("json")).unwrap_or(false)
+}
+
+/// prompts of a `.json` list or, for any other extension, a csv file
+pub fn read_file(path: &Path) -> Result<Vec<Prompt>, String> {
+    let data = std::fs::read(path).map_err(|e| e.to_string())?;
+    if is_json(path) {
+        serde_json::from_slice(&data).map_err(|e| format!("invalid prompts: {}", e))
+    } else {
+        parse_csv(&data)
+    }
+}
+
+/// write `prompts` as a `.json` list or, for any other extension, as csv
+pub fn write_file(path: &Path, prompts: &[Prompt]) -> Result<(), String> {
+    let data = if is_json(path) {
+        serde_json::to_vec_pretty(prompts).map_err(|e| e.to_string())?
+    } else {
+        write_csv(prompts)?
+    };
+    std::fs::write(path, data).map_err(|e| e.to_string())
+}
+
+/// trim what was typed in and refuse prompts without a name or content
+pub fn normalize(mut prompt: Prompt) -> Result<Prompt, String> {
+    prompt.act = prompt.act.trim().to_string();
+    prompt.category = prompt
+        .category
+        .map(|c| c.trim().to_string())
+        .filter(|c| !c.is_empty());
+    if prompt.act.is_empty() {
+        return Err("a prompt needs a name".to_string());
+    }
+    if prompt.content.trim().is_empty() {
+        return Err("a prompt needs some content".to_string());
+    }
+    Ok(prompt)
+}
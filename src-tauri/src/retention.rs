@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use common::{
+    ConversationId, PurgeCandidate, PurgeReason, PurgeSummary, RetentionAction, RetentionPolicy,
+};
+
+use crate::backup::{self, Backup, BackupConversation, BACKUP_VERSION, KEY_CHECK_FILE};
+use crate::storage::Storage;
+
+static ARCHIVE_DIR: &str = "archives";
+const DAY_MS: u64 = 24 * 3600 * 1000;
+
+pub fn archive_dir(cfg_path: &Path) -> PathBuf {
+    cfg_path.join(ARCHIVE_DIR)
+}
+
+/// Conversations of `store` that `policy` purges at `now`, oldest first,
+/// along with the size of everything stored. Sizes are those the backend
+/// reports with `get_size`. Kept ones are left out of the cap, purging can't
+/// make room for them, so only the others have to fit in it.
+pub fn plan<S: Storage + ?Sized>(
+    store: &S,
+    policy: &RetentionPolicy,
+    now: u64,
+) -> Result<(Vec<PurgeCandidate>, u64), String> {
+    let mut entries = vec![];
+    for id in store.get_conversations()? {
+        let size = store.get_size(id)?;
+        let kept = policy.keeps(&store.get_meta(id)?);
+        entries.push((store.get_updated(id)?, id, size, kept));
+    }
+    entries.sort_by_key(|(updated_at, id, _, _)| (*updated_at, id.0));
+    let total_size = entries.iter().map(|(_, _, size, _)| size).sum::<u64>();
+
+    // what is kept anyway can't make room, the limit is for the rest
+    let mut left = entries
+        .iter()
+        .filter(|(_, _, _, kept)| !kept)
+        .map(|(_, _, size, _)| size)
+        .sum::<u64>();
+    let mut candidates = vec![];
+    for (updated_at, id, size, kept) in entries {
+        if kept {
+            continue;
+        }
+
+        let expired = policy.max_age_days > 0
+            && now.saturating_sub(updated_at) > policy.max_age_days * DAY_MS;
+        let reason = if expired {
+            PurgeReason::Age
+        } else if policy.max_total_bytes > 0 && left > policy.max_total_bytes {
+            PurgeReason::Size
+        } else {
+            continue;
+        };
+
+        left -= size;
+        candidates.push(PurgeCandidate {
+            id,
+            title: store.get_title(id),
+            updated_at,
+            size,
+            reason,
+        });
+    }
+    Ok((candidates, total_size))
+}
+
+/// Remove `ids` from `store`, archiving them first when asked to. The archive
+/// is a backup holding just these conversations, taken as the backend stores
+/// them, so pass the store below encryption to keep them sealed.
+pub fn purge<S: Storage + ?Sized>(
+    store: &S,
+    cfg_path: &Path,
+    action: RetentionAction,
+    ids: &[ConversationId],
+) -> Result<PurgeSummary, String> {
+    if ids.is_empty() {
+        return Ok(PurgeSummary::default());
+    }
+
+    let mut archive = None;
+    if action == RetentionAction::Archive {
+        let mut conversations = vec![];
+        for &id in ids {
+            conversations.push(BackupConversation {
+                id,
+                title: store.get_title(id),
+                meta: store.get_meta(id)?,
+                tree: store.get_tree(id)?,
+            });
+        }
+
+        let mut files = BTreeMap::new();
+        if let Ok(text) = std::fs::read_to_string(cfg_path.join(KEY_CHECK_FILE)) {
+            files.insert(KEY_CHECK_FILE.to_string(), text);
+        }
+        let backup = Backup {
+            version: BACKUP_VERSION,
+            created_at: backup::now_ms(),
+            secrets: false,
+            conversations,
+            // only conversations are purged
+            prompts: vec![],
+            prompt_versions: vec![],
+            files,
+        };
+
+        let dir = archive_dir(cfg_path);
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        let path = dir.join(format!("archive-{}.json.gz", backup.created_at));
+        backup::write_archive(&path, &backup)?;
+        archive = Some(path.to_string_lossy().into_owned());
+    }
+
+    for &id in ids {
+        store.delete_conversation(id)?;
+    }
+
+    Ok(PurgeSummary {
+        purged: ids.len(),
+        archive,
+    })
+}
@@ -0,0 +1,487 @@
+//! The contract of `Storage`, written as checks any backend can be run
+//! against. Each check gets a store of its own that starts out empty:
+//!
+//! ```ignore
+//! storage::conformance::run(|| MyStorage::new())?;
+//! ```
+
+use std::fmt::Debug;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use super::*;
+use common::{Content, PromptRef, Role};
+
+type Check = fn(&dyn Storage) -> Result<(), String>;
+
+pub static CHECKS: &[(&str, Check)] = &[
+    ("unknown conversation", unknown_conversation),
+    ("start conversation", start_conversation),
+    ("append messages", append_messages),
+    ("titles", titles),
+    ("meta", meta),
+    ("prompts", prompts),
+    ("prompt versions", prompt_versions),
+    ("versions", versions),
+    ("branches", branches),
+    ("list conversations", list_conversations),
+    ("search", search),
+    ("delete", delete),
+    ("events", events),
+];
+
+/// Run every check against a fresh store made by `fresh`, stopping at the
+/// first one that fails.
+pub fn run<S, F>(mut fresh: F) -> Result<(), String>
+where
+    S: Storage,
+    F: FnMut() -> S,
+{
+    for (name, check) in CHECKS {
+        let store = fresh();
+        check(&store).map_err(|e| format!("{}: {}", name, e))?;
+    }
+    Ok(())
+}
+
+fn expect_eq<T: PartialEq + Debug>(what: &str, got: T, want: T) -> Result<(), String> {
+    if got == want {
+        Ok(())
+    } else {
+        Err(format!("{}: got {:?}, want {:?}", what, got, want))
+    }
+}
+
+fn expect_err<T: Debug>(what: &str, got: Result<T, String>, want: &str) -> Result<(), String> {
+    match got {
+        Err(e) if e == want => Ok(()),
+        got => Err(format!("{}: got {:?}, want error {:?}", what, got, want)),
+    }
+}
+
+fn contents(msgs: &[Message]) -> Vec<String> {
+    msgs.iter().map(|m| m.content.text()).collect()
+}
+
+fn strings(texts: &[&str]) -> Vec<String> {
+    texts.iter().map(|t| t.to_string()).collect()
+}
+
+fn unknown_conversation(store: &dyn Storage) -> Result<(), String> {
+    let id = ConversationId(Uuid::new_v4());
+    expect_err("get_tree", store.get_tree(id), NOT_FOUND_ERROR)?;
+    expect_err("get_versioned_tree", store.get_versioned_tree(id), NOT_FOUND_ERROR)?;
+    expect_err("get_conversation", store.get_conversation(id), NOT_FOUND_ERROR)?;
+    expect_err("get_messages", store.get_messages(id, None, 10), NOT_FOUND_ERROR)?;
+    expect_err("get_updated", store.get_updated(id), NOT_FOUND_ERROR)?;
+    expect_err("get_size", store.get_size(id), NOT_FOUND_ERROR)?;
+    expect_eq("get_title", store.get_title(id), None)?;
+    expect_eq("get_meta", store.get_meta(id)?, ConversationMeta::default())?;
+    expect_eq("get_conversations", store.get_conversations()?, vec![])?;
+    store.delete_conversation(id)
+}
+
+fn start_conversation(store: &dyn Storage) -> Result<(), String> {
+    let id = store.start_conversation(Some("be brief".into()))?;
+    expect_eq("get_conversations", store.get_conversations()?, vec![id])?;
+
+    let msgs = store.get_conversation(id)?;
+    expect_eq("messages", contents(&msgs), strings(&["be brief"]))?;
+    expect_eq("role", &msgs[0].role, &Role::System)?;
+    expect_eq("get_updated", store.get_updated(id)?, msgs[0].created_at)?;
+    expect_eq("get_title", store.get_title(id), None)
+}
+
+fn append_messages(store: &dyn Storage) -> Result<(), String> {
+    // appending is enough to create a conversation
+    let id = ConversationId(Uuid::new_v4());
+    let first = Message::new_user("first");
+    store.store_message(id, first.clone())?;
+    store.store_message(id, Message::new_assistant("second"))?;
+
+    let msgs = store.get_conversation(id)?;
+    expect_eq("messages", contents(&msgs), strings(&["first", "second"]))?;
+    expect_eq("stored as given", &msgs[0], &first)?;
+
+    let tree = store.get_tree(id)?;
+    expect_eq("parent", tree.get(msgs[1].id).and_then(|n| n.parent), Some(first.id))?;
+    expect_eq("get_updated", store.get_updated(id)?, msgs[1].created_at)?;
+
+    let page = store.get_messages(id, None, 1)?;
+    expect_eq("newest page", page.items.iter().map(|i| i.node.id()).collect(), vec![msgs[1].id])?;
+    expect_eq("next", page.next, Some(msgs[1].id))?;
+    let page = store.get_messages(id, page.next, 1)?;
+    expect_eq("older page", page.items.iter().map(|i| i.node.id()).collect(), vec![first.id])?;
+    expect_eq("last page", page.next, None)?;
+
+    // images are kept next to the text
+    let size = store.get_size(id)?;
+    let image = "data:image/png;base64,iVBORw0KGgo=".to_string();
+    let with_image = Message::new_user(Content::with_images("what is this?".into(), vec![image]));
+    store.store_message(id, with_image.clone())?;
+    if store.get_size(id)? <= size {
+        return Err(format!("get_size kept {} after a message was added", size));
+    }
+    expect_eq("parts", store.get_conversation(id)?.pop(), Some(with_image.clone()))?;
+    let page = store.get_messages(id, None, 1)?;
+    expect_eq("parts in page", page.items.into_iter().map(|i| i.node.message).collect(), vec![with_image])
+}
+
+fn titles(store: &dyn Storage) -> Result<(), String> {
+    let id = store.start_conversation(None)?;
+    let (version, _) = store.get_versioned_tree(id)?;
+
+    expect_eq("title if missing", store.store_title_if_missing(id, "first".into())?, "first".to_string())?;
+    expect_eq("get_title", store.get_title(id), Some("first".into()))?;
+    expect_eq("title kept", store.store_title_if_missing(id, "second".into())?, "first".to_string())?;
+    store.store_title(id, "renamed".into())?;
+    expect_eq("overwritten", store.get_title(id), Some("renamed".into()))?;
+
+    // titles are kept apart from the conversation
+    expect_eq("get_conversations", store.get_conversations()?, vec![id])?;
+    expect_eq("version", store.get_versioned_tree(id)?.0, version)
+}
+
+fn meta(store: &dyn Storage) -> Result<(), String> {
+    let id = store.start_conversation(None)?;
+    let (version, _) = store.get_versioned_tree(id)?;
+    expect_eq("default", store.get_meta(id)?, ConversationMeta::default())?;
+
+    let meta = ConversationMeta {
+        tags: vec!["rust".into(), "work".into()],
+        folder: Some("projects".into()),
+        pinned: true,
+        prompt: Some(PromptRef { id: PromptId::new(), version: 3 }),
+    };
+    store.store_meta(id, meta.clone())?;
+    expect_eq("get_meta", store.get_meta(id)?, meta)?;
+    store.store_meta(id, ConversationMeta::default())?;
+    expect_eq("overwritten", store.get_meta(id)?, ConversationMeta::default())?;
+    expect_eq("version", store.get_versioned_tree(id)?.0, version)
+}
+
+fn prompts(store: &dyn Storage) -> Result<(), String> {
+    expect_eq("empty", store.get_prompts()?, vec![])?;
+
+    let mut first = Prompt::new("Translator", "translate everything to english");
+    first.category = Some("language".into());
+    let second = Prompt::new("Terminal", "act as a linux terminal");
+    store.store_prompt(first.clone())?;
+    store.store_prompt(second.clone())?;
+    let mut got = store.get_prompts()?;
+    Prompt::sort(&mut got);
+    expect_eq("get_prompts", got, vec![first.clone(), second.clone()])?;
+
+    first.favorite = true;
+    first.content = "translate everything to german".into();
+    store.store_prompt(first.clone())?;
+    store.delete_prompt(second.id)?;
+    expect_eq("replaced", store.get_prompts()?, vec![first.clone()])?;
+    store.delete_prompt(second.id)?;
+
+    // prompts are kept apart from conversations
+    expect_eq("get_conversations", store.get_conversations()?, vec![])
+}
+
+fn prompt_versions(store: &dyn Storage) -> Result<(), String> {
+    let mut prompt = Prompt::new("Translator", "translate everything to english");
+    let other = Prompt::new("Terminal", "act as a linux terminal");
+    expect_eq("none", store.get_prompt_versions(prompt.id)?, vec![])?;
+
+    prompt.version = 2;
+    let second = PromptVersion::new(&prompt, 20);
+    prompt.version = 10;
+    prompt.content = "translate everything to german".into();
+    let mut tenth = PromptVersion::new(&prompt, 100);
+    // stored out of order, listed by number
+    store.store_prompt_version(tenth.clone())?;
+    store.store_prompt_version(second.clone())?;
+    store.store_prompt_version(PromptVersion::new(&other, 5))?;
+    store.store_prompt(prompt.clone())?;
+    store.store_prompt(other.clone())?;
+    expect_eq("get_prompt_versions", store.get_prompt_versions(prompt.id)?, vec![second.clone(), tenth.clone()])?;
+
+    tenth.content = "translate everything to french".into();
+    store.store_prompt_version(tenth.clone())?;
+    expect_eq("replaced", store.get_prompt_versions(prompt.id)?, vec![second.clone(), tenth.clone()])?;
+
+    // a number taken already is kept
+    let mut taken = tenth.clone();
+    taken.content = "translate everything to dutch".into();
+    expect_eq("taken", store.store_prompt_version_if_missing(taken.clone())?, false)?;
+    taken.version = 11;
+    expect_eq("free", store.store_prompt_version_if_missing(taken.clone())?, true)?;
+    expect_eq("kept", store.get_prompt_versions(prompt.id)?, vec![second.clone(), tenth.clone(), taken.clone()])?;
+
+    // conversations started with a deleted prompt still find their version
+    store.delete_prompt(prompt.id)?;
+    expect_eq("deleted", store.get_prompts()?, vec![other.clone()])?;
+    expect_eq("versions kept", store.get_prompt_versions(prompt.id)?, vec![second, tenth, taken])?;
+    expect_eq("others kept", store.get_prompt_versions(other.id)?.len(), 1)
+}
+
+fn versions(store: &dyn Storage) -> Result<(), String> {
+    let id = store.start_conversation(None)?;
+    let (v1, mut tree) = store.get_versioned_tree(id)?;
+    if v1 == 0 {
+        return Err("a stored conversation is at version 0".into());
+    }
+
+    store.store_message(id, Message::new_user("a"))?;
+    let (v2, _) = store.get_versioned_tree(id)?;
+    if v2 <= v1 {
+        return Err(format!("store_message kept version {} at {}", v1, v2));
+    }
+
+    tree.push(Message::new_user("b"));
+    expect_err("stale version", store.compare_and_store_tree(id, v1, tree.clone()), CONFLICT_ERROR)?;
+    let v3 = store.compare_and_store_tree(id, v2, tree.clone())?;
+    expect_eq("returned version", store.get_versioned_tree(id)?.0, v3)?;
+    expect_eq("stored tree", contents(&store.get_conversation(id)?), contents(&tree.messages()))?;
+    expect_err("replayed version", store.compare_and_store_tree(id, v2, tree.clone()), CONFLICT_ERROR)?;
+
+    store.store_tree(id, tree.clone())?;
+    if store.get_versioned_tree(id)?.0 <= v3 {
+        return Err("store_tree kept the version".into());
+    }
+
+    // version 0 stands for a conversation not stored yet
+    let other = ConversationId(Uuid::new_v4());
+    expect_err("created at version 1", store.compare_and_store_tree(other, 1, tree.clone()), CONFLICT_ERROR)?;
+    expect_err("left behind by a conflict", store.get_tree(other), NOT_FOUND_ERROR)?;
+    let v = store.compare_and_store_tree(other, 0, tree.clone())?;
+    expect_eq("new version", store.get_versioned_tree(other)?.0, v)?;
+    expect_err("created twice", store.compare_and_store_tree(id, 0, tree), CONFLICT_ERROR)?;
+
+    // appending checks the version the same way
+    let (v, before) = store.get_versioned_tree(id)?;
+    let appended = Message::new_user("appended");
+    expect_err("stale append", store.compare_and_append(id, v - 1, appended.clone()), CONFLICT_ERROR)?;
+    let next = store.compare_and_append(id, v, appended.clone())?;
+    expect_eq("appended version", store.get_versioned_tree(id)?.0, next)?;
+    let mut want = before.messages();
+    want.push(appended.clone());
+    expect_eq("appended", contents(&store.get_conversation(id)?), contents(&want))?;
+    let hits = store.search("appended")?.into_iter().map(|h| h.message).collect::<Vec<_>>();
+    expect_eq("appended found", hits, vec![appended.id])?;
+    let fresh = ConversationId(Uuid::new_v4());
+    expect_err("appended at version 1", store.compare_and_append(fresh, 1, appended.clone()), CONFLICT_ERROR)?;
+    let v = store.compare_and_append(fresh, 0, appended)?;
+    expect_eq("created by append", store.get_versioned_tree(fresh)?.0, v)
+}
+
+fn branches(store: &dyn Storage) -> Result<(), String> {
+    let id = store.start_conversation(None)?;
+    let question = Message::new_user("question");
+    store.store_message(id, question.clone())?;
+    store.store_message(id, Message::new_assistant("answer"))?;
+
+    let mut tree = store.get_tree(id)?;
+    tree.fork(question.id, Message::new_user("edited"));
+    store.store_tree(id, tree.clone())?;
+
+    let stored = store.get_tree(id)?;
+    expect_eq("nodes", stored.nodes.len(), tree.nodes.len())?;
+    expect_eq("active branch", contents(&stored.messages()), contents(&tree.messages()))?;
+
+    // replacing the active branch keeps the others
+    let mut msgs = stored.messages();
+    msgs.push(Message::new_assistant("another answer"));
+    store.store_conversation(id, msgs.clone())?;
+    let stored = store.get_tree(id)?;
+    expect_eq("replaced branch", contents(&stored.messages()), contents(&msgs))?;
+    expect_eq("kept branch", stored.get(question.id).is_some(), true)?;
+
+    // moving the active branch keeps every message
+    let (v, mut tree) = store.get_versioned_tree(id)?;
+    tree.switch_to(question.id);
+    expect_err("stale switch", store.compare_and_set_current(id, v - 1, tree.current), CONFLICT_ERROR)?;
+    let next = store.compare_and_set_current(id, v, tree.current)?;
+    let (found, stored) = store.get_versioned_tree(id)?;
+    expect_eq("switched version", found, next)?;
+    expect_eq("switched branch", contents(&stored.messages()), contents(&tree.messages()))?;
+    expect_eq("nodes after switch", stored.nodes.len(), tree.nodes.len())
+}
+
+fn list_conversations(store: &dyn Storage) -> Result<(), String> {
+    let mut ids = vec![];
+    for n in 0..7 {
+        let id = store.start_conversation(None)?;
+        if n % 2 == 0 {
+            let meta = ConversationMeta {
+                tags: vec!["even".into()],
+                ..Default::default()
+            };
+            store.store_meta(id, meta)?;
+        }
+        ids.push(id);
+    }
+    // an old conversation getting a new message comes first again, after the pinned one
+    let pinned = ConversationMeta {
+        pinned: true,
+        ..Default::default()
+    };
+    store.store_meta(ids[1], pinned)?;
+    std::thread::sleep(Duration::from_millis(2));
+    store.store_message(ids[0], Message::new_user("bump"))?;
+
+    let mut want = vec![];
+    for id in &ids {
+        want.push((*id == ids[1], store.get_updated(*id)?, id.0));
+    }
+    want.sort_by(|a, b| b.cmp(a));
+    let want = want.into_iter().map(|(_, _, id)| ConversationId(id)).collect::<Vec<_>>();
+    expect_eq("pinned first", want[0], ids[1])?;
+    expect_eq("bumped next", want[1], ids[0])?;
+
+    let filter = ConversationFilter::default();
+    let mut got = vec![];
+    let mut after = None;
+    loop {
+        let page = store.list_conversations(&filter, after, 3)?;
+        if page.items.len() > 3 {
+            return Err(format!("page of {} for a limit of 3", page.items.len()));
+        }
+        for entry in &page.items {
+            expect_eq("updated_at", entry.updated_at, store.get_updated(entry.id)?)?;
+            expect_eq("meta", &entry.meta, &store.get_meta(entry.id)?)?;
+        }
+        got.extend(page.items.iter().map(|e| e.id));
+        after = match page.next {
+            Some(next) => Some(next),
+            None => break,
+        };
+    }
+    expect_eq("pages", got, want.clone())?;
+
+    let even = ConversationFilter {
+        tag: Some("even".into()),
+        ..Default::default()
+    };
+    let got = store
+        .list_conversations(&even, None, 10)?
+        .items
+        .into_iter()
+        .map(|e| e.id)
+        .collect::<Vec<_>>();
+    let want = want
+        .into_iter()
+        .filter(|id| ids.iter().position(|i| i == id).unwrap() % 2 == 0)
+        .collect::<Vec<_>>();
+    expect_eq("filtered", got, want)
+}
+
+fn search(store: &dyn Storage) -> Result<(), String> {
+    let id = store.start_conversation(None)?;
+    let question = Message::new_user("How do lifetimes work in Rust?");
+    store.store_message(id, question.clone())?;
+    let answer = Message::new_assistant("Lifetimes describe how long references live.");
+    store.store_message(id, answer.clone())?;
+
+    // ids made within the same millisecond are in no particular order
+    let hits = |query: &str| -> Result<Vec<MessageId>, String> {
+        let mut hits = store.search(query)?.into_iter().map(|h| h.message).collect::<Vec<_>>();
+        hits.sort_by_key(|m| m.0);
+        Ok(hits)
+    };
+    let mut both = vec![question.id, answer.id];
+    both.sort_by_key(|m| m.0);
+    // words match by prefix regardless of case
+    expect_eq("prefix", hits("lifetime")?, both)?;
+    expect_eq("every word", hits("lifetimes rust")?, vec![question.id])?;
+    expect_eq("nothing", hits("python")?, vec![])?;
+    expect_eq("empty query", hits("")?, vec![])?;
+
+    // edited away messages stay searchable in their branch
+    let mut tree = store.get_tree(id)?;
+    let edited = Message::new_user("How do traits work?");
+    tree.fork(question.id, edited.clone());
+    store.store_tree(id, tree)?;
+    expect_eq("other branch", hits("rust")?, vec![question.id])?;
+    expect_eq("new branch", hits("traits")?, vec![edited.id])?;
+
+    // replaced content is gone from the results
+    let tree = ConversationTree::from_messages(vec![Message::new_system("empty")]);
+    store.store_tree(id, tree)?;
+    expect_eq("replaced", hits("lifetimes")?, vec![])?;
+
+    // newest first by when a message was written, an imported one has a
+    // newer id than its time says
+    let recent = Message::new_user("ordering probe");
+    let mut imported = Message::new_user("ordering probe");
+    imported.created_at = 1;
+    store.store_message(id, recent.clone())?;
+    store.store_message(store.start_conversation(None)?, imported.clone())?;
+    let ordered = store.search("probe")?.into_iter().map(|h| h.message).collect::<Vec<_>>();
+    expect_eq("newest first", ordered, vec![recent.id, imported.id])
+}
+
+fn delete(store: &dyn Storage) -> Result<(), String> {
+    let id = store.start_conversation(None)?;
+    store.store_message(id, Message::new_user("forget me"))?;
+    store.store_title(id, "secret".into())?;
+    store.store_meta(id, ConversationMeta { pinned: true, ..Default::default() })?;
+    let kept = store.start_conversation(None)?;
+    store.store_title(kept, "kept".into())?;
+
+    store.delete_conversation(id)?;
+    expect_err("get_tree", store.get_tree(id), NOT_FOUND_ERROR)?;
+    expect_eq("get_title", store.get_title(id), None)?;
+    expect_eq("get_meta", store.get_meta(id)?, ConversationMeta::default())?;
+    expect_eq("get_conversations", store.get_conversations()?, vec![kept])?;
+    let listed = store.list_conversations(&ConversationFilter::default(), None, 10)?;
+    expect_eq("listed", listed.items.into_iter().map(|e| e.id).collect(), vec![kept])?;
+    expect_eq("search", store.search("forget")?, vec![])?;
+    expect_eq("other title", store.get_title(kept), Some("kept".into()))?;
+
+    // deleting again is not an error, and the id can be used anew
+    store.delete_conversation(id)?;
+    store.store_message(id, Message::new_user("again"))?;
+    expect_eq("reused", contents(&store.get_conversation(id)?), strings(&["again"]))
+}
+
+// events may come from other threads, wait a little for all of them
+fn receive(rx: &Receiver<StorageEvent>, count: usize) -> Vec<StorageEvent> {
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let mut events = vec![];
+    while events.len() < count {
+        let left = deadline.saturating_duration_since(Instant::now());
+        match rx.recv_timeout(left) {
+            Ok(event) => events.push(event),
+            Err(_) => break,
+        }
+    }
+    events
+}
+
+fn events(store: &dyn Storage) -> Result<(), String> {
+    let before = store.start_conversation(None)?;
+    let rx = store.subscribe()?;
+
+    let id = store.start_conversation(None)?;
+    store.store_message(id, Message::new_user("hi"))?;
+    store.store_title(id, "greeting".into())?;
+    store.store_meta(id, ConversationMeta { pinned: true, ..Default::default() })?;
+    store.store_message(before, Message::new_user("still here"))?;
+    store.delete_conversation(id)?;
+
+    let want = vec![
+        StorageEvent::Created(id),
+        StorageEvent::Updated(id),
+        StorageEvent::TitleChanged(id),
+        StorageEvent::MetaChanged(id),
+        StorageEvent::Updated(before),
+        StorageEvent::Deleted(id),
+    ];
+    // every change is reported, though not necessarily in order
+    let mut got = receive(&rx, want.len());
+    for event in &want {
+        match got.iter().position(|e| e == event) {
+            Some(n) => {
+                got.remove(n);
+            }
+            None => return Err(format!("missing {:?}, got {:?}", event, got)),
+        }
+    }
+    Ok(())
+}
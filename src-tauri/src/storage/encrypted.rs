@@ -0,0 +1,417 @@
+use std::sync::{Arc, RwLock};
+
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+// marks sealed values, anything else was written before encryption was enabled
+const PREFIX: &str = "enc:";
+const CHECK: &str = "chatgpt-service";
+const NONCE_LEN: usize = 24;
+
+#[derive(Clone)]
+pub struct Cipher {
+    aead: XChaCha20Poly1305,
+    // tried when `aead` can't open a value, while a rekey is under way
+    previous: Option<Box<Cipher>>,
+}
+
+/// What is kept on disk to tell a right passphrase from a wrong one:
+/// the salt of the key derivation and a known value sealed with the key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyCheck {
+    salt: String,
+    check: String,
+}
+
+impl Cipher {
+    fn derive(passphrase: &str, salt: &[u8]) -> Result<Self, String> {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| e.to_string())?;
+
+        Ok(Cipher {
+            aead: XChaCha20Poly1305::new(&key.into()),
+            previous: None,
+        })
+    }
+
+    /// seals with this key, opens with it or with `previous`
+    pub fn falling_back_to(mut self, previous: Cipher) -> Self {
+        self.previous = Some(Box::new(previous));
+        self
+    }
+
+    /// a cipher for a new passphrase, with the check needed to unlock it later
+    pub fn create(passphrase: &str) -> Result<(Self, KeyCheck), String> {
+        let salt: [u8; 16] = rand::random();
+        let cipher = Self::derive(passphrase, &salt)?;
+        let check = KeyCheck {
+            salt: STANDARD.encode(salt),
+            check: cipher.encrypt(CHECK)?,
+        };
+        Ok((cipher, check))
+    }
+
+    pub fn unlock(passphrase: &str, check: &KeyCheck) -> Result<Self, String> {
+        let salt = STANDARD.decode(&check.salt).map_err(|e| e.to_string())?;
+        let cipher = Self::derive(passphrase, &salt)?;
+        match cipher.decrypt(&check.check) {
+            Ok(v) if v == CHECK => Ok(cipher),
+            _ => Err("wrong passphrase".to_string()),
+        }
+    }
+
+    pub fn encrypt(&self, plain: &str) -> Result<String, String> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut sealed = nonce.to_vec();
+        sealed.extend(
+            self.aead
+                .encrypt(&nonce, plain.as_bytes())
+                .map_err(|_| "value too large to encrypt".to_string())?,
+        );
+        Ok(format!("{}{}", PREFIX, STANDARD.encode(sealed)))
+    }
+
+    // plaintext values are passed through so existing data stays readable
+    pub fn decrypt(&self, value: &str) -> Result<String, String> {
+        let sealed = match value.strip_prefix(PREFIX) {
+            Some(sealed) => STANDARD.decode(sealed).map_err(|e| e.to_string())?,
+            None => return Ok(value.to_string()),
+        };
+        if sealed.len() < NONCE_LEN {
+            return Err("corrupted value".to_string());
+        }
+
+        let (nonce, data) = sealed.split_at(NONCE_LEN);
+        let plain = match self.aead.decrypt(XNonce::from_slice(nonce), data) {
+            Ok(plain) => plain,
+            Err(_) => match &self.previous {
+                Some(previous) => return previous.decrypt(value),
+                None => return Err("cannot decrypt value, wrong passphrase?".to_string()),
+            },
+        };
+        String::from_utf8(plain).map_err(|e| e.to_string())
+    }
+
+    fn seal_tree(&self, mut tree: ConversationTree) -> Result<ConversationTree, String> {
+        for node in tree.nodes.iter_mut() {
+            node.message.content = node.message.content.try_map(|s| self.encrypt(s))?;
+        }
+        Ok(tree)
+    }
+
+    fn seal_message(&self, mut msg: Message) -> Result<Message, String> {
+        msg.content = msg.content.try_map(|s| self.encrypt(s))?;
+        Ok(msg)
+    }
+
+    fn open_tree(&self, mut tree: ConversationTree) -> Result<ConversationTree, String> {
+        for node in tree.nodes.iter_mut() {
+            node.message.content = node.message.content.try_map(|s| self.decrypt(s))?;
+        }
+        Ok(tree)
+    }
+
+    fn seal_meta(&self, mut meta: ConversationMeta) -> Result<ConversationMeta, String> {
+        meta.tags = meta
+            .tags
+            .iter()
+            .map(|t| self.encrypt(t))
+            .collect::<Result<_, _>>()?;
+        meta.folder = meta.folder.map(|f| self.encrypt(&f)).transpose()?;
+        Ok(meta)
+    }
+
+    fn open_meta(&self, mut meta: ConversationMeta) -> Result<ConversationMeta, String> {
+        meta.tags = meta
+            .tags
+            .iter()
+            .map(|t| self.decrypt(t))
+            .collect::<Result<_, _>>()?;
+        meta.folder = meta.folder.map(|f| self.decrypt(&f)).transpose()?;
+        Ok(meta)
+    }
+
+    fn seal_prompt(&self, mut prompt: Prompt) -> Result<Prompt, String> {
+        prompt.act = self.encrypt(&prompt.act)?;
+        prompt.content = self.encrypt(&prompt.content)?;
+        prompt.category = prompt.category.map(|c| self.encrypt(&c)).transpose()?;
+        Ok(prompt)
+    }
+
+    fn open_prompt(&self, mut prompt: Prompt) -> Result<Prompt, String> {
+        prompt.act = self.decrypt(&prompt.act)?;
+        prompt.content = self.decrypt(&prompt.content)?;
+        prompt.category = prompt.category.map(|c| self.decrypt(&c)).transpose()?;
+        Ok(prompt)
+    }
+
+    fn seal_version(&self, mut version: PromptVersion) -> Result<PromptVersion, String> {
+        version.act = self.encrypt(&version.act)?;
+        version.content = self.encrypt(&version.content)?;
+        Ok(version)
+    }
+
+    fn open_version(&self, mut version: PromptVersion) -> Result<PromptVersion, String> {
+        version.act = self.decrypt(&version.act)?;
+        version.content = self.decrypt(&version.content)?;
+        Ok(version)
+    }
+}
+
+/// Seals message contents, titles, meta and prompts before they reach `inner`.
+/// Searching and filtering fall back to the trait defaults, which scan
+/// decrypted values, since an index kept by `inner` only sees ciphertext.
+pub struct EncryptedStorage<S: ?Sized> {
+    cipher: RwLock<Cipher>,
+    // held for reading by writes of titles, meta and prompts, for writing
+    // while a rekey reseals those; trees are resealed with compare-and-swap
+    writes: RwLock<()>,
+    inner: Arc<S>,
+}
+
+impl<S: Storage + ?Sized> EncryptedStorage<S> {
+    pub fn new(inner: Arc<S>, cipher: Cipher) -> Self {
+        EncryptedStorage {
+            cipher: RwLock::new(cipher),
+            writes: RwLock::new(()),
+            inner,
+        }
+    }
+
+    /// the key values are sealed with now
+    pub fn cipher(&self) -> Cipher {
+        self.cipher.read().unwrap().clone()
+    }
+
+    /// seal a value kept outside of storage, like api keys in the config file
+    pub fn encrypt(&self, value: &str) -> Result<String, String> {
+        self.cipher().encrypt(value)
+    }
+
+    /// Re-encrypt everything in `inner` with `cipher`. This is also how
+    /// plaintext written before encryption was enabled gets sealed.
+    /// Values are opened with either key, so running it again with the same
+    /// `cipher` finishes a rekey that was interrupted.
+    pub fn rekey(&self, cipher: Cipher) -> Result<(), String> {
+        let either = cipher.clone().falling_back_to(self.cipher());
+        // whatever is written meanwhile is sealed with the new key already
+        *self.cipher.write().unwrap() = either.clone();
+        let ids = self.inner.get_conversations()?;
+        for &id in &ids {
+            self.reseal_tree(id, &either, &cipher)?;
+        }
+
+        // the rest is small, a rename, pin or prompt edit made meanwhile
+        // waits instead of being overwritten with what was read before it
+        let _paused = self.writes.write().unwrap();
+        // deleted prompts keep the versions conversations were started with
+        let mut started_with = vec![];
+        for id in ids {
+            if let Some(title) = self.inner.get_title(id) {
+                self.inner.store_title(id, cipher.encrypt(&either.decrypt(&title)?)?)?;
+            }
+
+            let meta = either.open_meta(self.inner.get_meta(id)?)?;
+            started_with.extend(meta.prompt.map(|p| p.id));
+            self.inner.store_meta(id, cipher.seal_meta(meta)?)?;
+        }
+        let prompts = self.inner.get_prompts()?;
+        started_with.extend(prompts.iter().map(|p| p.id));
+        started_with.sort_by_key(|id| id.0);
+        started_with.dedup();
+        for id in started_with {
+            for version in self.inner.get_prompt_versions(id)? {
+                self.inner.store_prompt_version(cipher.seal_version(either.open_version(version)?)?)?;
+            }
+        }
+        for prompt in prompts {
+            self.inner.store_prompt(cipher.seal_prompt(either.open_prompt(prompt)?)?)?;
+        }
+
+        *self.cipher.write().unwrap() = cipher;
+        Ok(())
+    }
+
+    // Seal the tree of `id` with `cipher`, read again when it changes in
+    // between so a message appended meanwhile is not lost. Every conflict
+    // means a write went through, so this ends once writers pause.
+    fn reseal_tree(&self, id: ConversationId, either: &Cipher, cipher: &Cipher) -> Result<(), String> {
+        loop {
+            let (version, tree) = match self.inner.get_versioned_tree(id) {
+                Ok(found) => found,
+                // deleted since it was listed
+                Err(e) if e == NOT_FOUND_ERROR => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let tree = cipher.seal_tree(either.open_tree(tree)?)?;
+            match self.inner.compare_and_store_tree(id, version, tree) {
+                Err(e) if e == CONFLICT_ERROR => continue,
+                result => return result.map(|_| ()),
+            }
+        }
+    }
+}
+
+impl<S: Storage + ?Sized> Storage for EncryptedStorage<S> {
+    fn store_title(&self, id: ConversationId, msg: String) -> Result<(), String> {
+        let _writing = self.writes.read().unwrap();
+        self.inner.store_title(id, self.cipher().encrypt(&msg)?)
+    }
+
+    fn store_title_if_missing(&self, id: ConversationId, title: String) -> Result<String, String> {
+        let _writing = self.writes.read().unwrap();
+        let cipher = self.cipher();
+        let stored = self.inner.store_title_if_missing(id, cipher.encrypt(&title)?)?;
+        cipher.decrypt(&stored)
+    }
+
+    fn get_title(&self, id: ConversationId) -> Option<String> {
+        self.inner
+            .get_title(id)
+            .and_then(|title| self.cipher().decrypt(&title).ok())
+    }
+
+    fn store_message(&self, id: ConversationId, msg: Message) -> Result<(), String> {
+        self.inner.store_message(id, self.cipher().seal_message(msg)?)
+    }
+
+    fn store_tree(&self, id: ConversationId, tree: ConversationTree) -> Result<(), String> {
+        self.inner.store_tree(id, self.cipher().seal_tree(tree)?)
+    }
+
+    fn get_tree(&self, id: ConversationId) -> Result<ConversationTree, String> {
+        self.cipher().open_tree(self.inner.get_tree(id)?)
+    }
+
+    fn get_versioned_tree(&self, id: ConversationId) -> Result<(u64, ConversationTree), String> {
+        let (version, tree) = self.inner.get_versioned_tree(id)?;
+        Ok((version, self.cipher().open_tree(tree)?))
+    }
+
+    fn compare_and_store_tree(&self, id: ConversationId, version: u64, tree: ConversationTree) -> Result<u64, String> {
+        self.inner
+            .compare_and_store_tree(id, version, self.cipher().seal_tree(tree)?)
+    }
+
+    fn compare_and_append(&self, id: ConversationId, version: u64, msg: Message) -> Result<u64, String> {
+        self.inner
+            .compare_and_append(id, version, self.cipher().seal_message(msg)?)
+    }
+
+    fn compare_and_set_current(&self, id: ConversationId, version: u64, current: Option<MessageId>) -> Result<u64, String> {
+        self.inner.compare_and_set_current(id, version, current)
+    }
+
+    fn get_conversations(&self) -> Result<Vec<ConversationId>, String> {
+        self.inner.get_conversations()
+    }
+
+    fn delete_conversation(&self, id: ConversationId) -> Result<(), String> {
+        let _writing = self.writes.read().unwrap();
+        self.inner.delete_conversation(id)
+    }
+
+    // events carry ids only, nothing to decrypt
+    fn subscribe(&self) -> Result<Receiver<StorageEvent>, String> {
+        self.inner.subscribe()
+    }
+
+    fn get_updated(&self, id: ConversationId) -> Result<u64, String> {
+        self.inner.get_updated(id)
+    }
+
+    // sealed values are what takes up the space
+    fn get_size(&self, id: ConversationId) -> Result<u64, String> {
+        self.inner.get_size(id)
+    }
+
+    fn list_conversations(
+        &self,
+        filter: &ConversationFilter,
+        after: Option<ConversationCursor>,
+        limit: usize,
+    ) -> Result<Page<ConversationEntry, ConversationCursor>, String> {
+        // `inner` can only match tags and folders against ciphertext
+        if filter.tag.is_some() || filter.folder.is_some() {
+            return scan_conversations(self, filter, after, limit);
+        }
+
+        let cipher = self.cipher();
+        let mut page = self.inner.list_conversations(filter, after, limit)?;
+        for entry in page.items.iter_mut() {
+            entry.meta = cipher.open_meta(std::mem::take(&mut entry.meta))?;
+        }
+        Ok(page)
+    }
+
+    fn get_messages(
+        &self,
+        id: ConversationId,
+        before: Option<MessageId>,
+        limit: usize,
+    ) -> Result<Page<PathNode, MessageId>, String> {
+        let cipher = self.cipher();
+        let mut page = self.inner.get_messages(id, before, limit)?;
+        for item in page.items.iter_mut() {
+            item.node.message.content = item.node.message.content.try_map(|s| cipher.decrypt(s))?;
+        }
+        Ok(page)
+    }
+
+    fn store_meta(&self, id: ConversationId, meta: ConversationMeta) -> Result<(), String> {
+        let _writing = self.writes.read().unwrap();
+        self.inner.store_meta(id, self.cipher().seal_meta(meta)?)
+    }
+
+    fn get_meta(&self, id: ConversationId) -> Result<ConversationMeta, String> {
+        self.cipher().open_meta(self.inner.get_meta(id)?)
+    }
+
+    fn get_prompts(&self) -> Result<Vec<Prompt>, String> {
+        let cipher = self.cipher();
+        self.inner
+            .get_prompts()?
+            .into_iter()
+            .map(|p| cipher.open_prompt(p))
+            .collect()
+    }
+
+    fn store_prompt(&self, prompt: Prompt) -> Result<(), String> {
+        let _writing = self.writes.read().unwrap();
+        self.inner.store_prompt(self.cipher().seal_prompt(prompt)?)
+    }
+
+    fn delete_prompt(&self, id: PromptId) -> Result<(), String> {
+        let _writing = self.writes.read().unwrap();
+        self.inner.delete_prompt(id)
+    }
+
+    fn get_prompt_versions(&self, id: PromptId) -> Result<Vec<PromptVersion>, String> {
+        let cipher = self.cipher();
+        self.inner
+            .get_prompt_versions(id)?
+            .into_iter()
+            .map(|v| cipher.open_version(v))
+            .collect()
+    }
+
+    fn store_prompt_version(&self, version: PromptVersion) -> Result<(), String> {
+        let _writing = self.writes.read().unwrap();
+        self.inner.store_prompt_version(self.cipher().seal_version(version)?)
+    }
+
+    fn store_prompt_version_if_missing(&self, version: PromptVersion) -> Result<bool, String> {
+        let _writing = self.writes.read().unwrap();
+        self.inner
+            .store_prompt_version_if_missing(self.cipher().seal_version(version)?)
+    }
+}
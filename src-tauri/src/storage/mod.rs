@@ -1,38 +1,276 @@
-use common::{ConversationId, Message};
+use common::{
+    ConversationCursor, ConversationEntry, ConversationFilter, ConversationId, ConversationMeta,
+    ConversationTree, Message, MessageId, Page, PathNode, Prompt, PromptId, PromptVersion,
+    SearchHit, StorageEvent, CONFLICT_ERROR, NOT_FOUND_ERROR,
+};
+use std::sync::mpsc::Receiver;
 use uuid::Uuid;
 
+pub mod conformance;
+pub mod encrypted;
+pub mod search;
+pub mod switch;
+
 pub trait Storage {
-    fn start_conversation(&self, ctx: Option<String>) -> ConversationId {
+    fn start_conversation(&self, ctx: Option<String>) -> Result<ConversationId, String> {
         let id = ConversationId(Uuid::new_v4());
         if let Some(ctx) = ctx {
-            self.store_message(id, Message::new_system(ctx));
+            self.store_message(id, Message::new_system(ctx))?;
         } else {
-            self.store_message(id, Message::new_system("act as a general chat.".into()));
+            self.store_message(id, Message::new_system("act as a general chat."))?;
         }
 
-        id
+        Ok(id)
     }
 
+    // replaces the title `id` had
     fn store_title(&self, id: ConversationId, msg: String) -> Result<(), String>;
     fn get_title(&self, id: ConversationId) -> Option<String>;
+    // store `title` unless `id` has one already, returns the title `id` ends up with
+    fn store_title_if_missing(&self, id: ConversationId, title: String) -> Result<String, String> {
+        match self.get_title(id) {
+            Some(old) => Ok(old),
+            None => self.store_title(id, title.clone()).map(|_| title),
+        }
+    }
+
+    // append to the active branch
+    fn store_message(&self, id: ConversationId, msg: Message) -> Result<(), String>;
+
+    // replace the active branch, other branches are kept
+    fn store_conversation(&self, id: ConversationId, msgs: Vec<Message>) -> Result<(), String> {
+        let mut tree = self.get_tree(id).unwrap_or_default();
+        tree.replace_path(msgs);
+        self.store_tree(id, tree)
+    }
+
+    // messages of the active branch
+    fn get_conversation(&self, id: ConversationId) -> Result<Vec<Message>, String> {
+        self.get_tree(id).map(|tree| tree.messages())
+    }
 
-    fn store_message(&self, id: ConversationId, msg: Message);
-    // replace whole conversation
-    fn store_conversation(&self, id: ConversationId, msgs: Vec<Message>) -> Result<(), String>;
-    fn get_conversation(&self, id: ConversationId) -> Result<Vec<Message>, String>;
+    fn store_tree(&self, id: ConversationId, tree: ConversationTree) -> Result<(), String>;
+    // fails with `NOT_FOUND_ERROR` for unknown ids, as do all reads of messages
+    fn get_tree(&self, id: ConversationId) -> Result<ConversationTree, String>;
+
+    // the tree with its version, which every write to `id` bumps
+    fn get_versioned_tree(&self, id: ConversationId) -> Result<(u64, ConversationTree), String>;
+    // Store `tree` only if `id` is still at `version`, 0 meaning not stored
+    // yet, and fail with `CONFLICT_ERROR` otherwise. Returns the new version.
+    fn compare_and_store_tree(&self, id: ConversationId, version: u64, tree: ConversationTree) -> Result<u64, String>;
+    // `store_message` if `id` is still at `version`, like `compare_and_store_tree`
+    // without writing the rest of the tree again. Returns the new version.
+    fn compare_and_append(&self, id: ConversationId, version: u64, msg: Message) -> Result<u64, String> {
+        let (current, mut tree) = match self.get_versioned_tree(id) {
+            Ok(found) => found,
+            Err(e) if e == NOT_FOUND_ERROR => (0, ConversationTree::default()),
+            Err(e) => return Err(e),
+        };
+        if current != version {
+            return Err(CONFLICT_ERROR.to_string());
+        }
+        tree.push(msg);
+        self.compare_and_store_tree(id, version, tree)
+    }
+    // make `current` the active branch if `id` is still at `version`, like
+    // `compare_and_store_tree` without writing any message. Returns the new version.
+    fn compare_and_set_current(&self, id: ConversationId, version: u64, current: Option<MessageId>) -> Result<u64, String> {
+        let (found, mut tree) = self.get_versioned_tree(id)?;
+        if found != version {
+            return Err(CONFLICT_ERROR.to_string());
+        }
+        tree.current = current;
+        self.compare_and_store_tree(id, version, tree)
+    }
     fn get_conversations(&self) -> Result<Vec<ConversationId>, String>;
+    // drop the messages, title and meta of `id`, unknown ids are fine
+    fn delete_conversation(&self, id: ConversationId) -> Result<(), String>;
+
+    // every change made from now on, until the receiver is dropped
+    fn subscribe(&self) -> Result<Receiver<StorageEvent>, String>;
+
+    fn store_meta(&self, id: ConversationId, meta: ConversationMeta) -> Result<(), String>;
+    // conversations never organised have the default meta
+    fn get_meta(&self, id: ConversationId) -> Result<ConversationMeta, String>;
+
+    // the prompt library, in no particular order
+    fn get_prompts(&self) -> Result<Vec<Prompt>, String>;
+    // replaces the prompt with the same id
+    fn store_prompt(&self, prompt: Prompt) -> Result<(), String>;
+    // its versions stay, conversations started with it point at them;
+    // unknown ids are fine
+    fn delete_prompt(&self, id: PromptId) -> Result<(), String>;
+
+    // the recorded versions of a prompt, oldest first
+    fn get_prompt_versions(&self, id: PromptId) -> Result<Vec<PromptVersion>, String>;
+    // replaces the version with the same prompt and number
+    fn store_prompt_version(&self, version: PromptVersion) -> Result<(), String>;
+    // store `version` unless its prompt has one with that number already,
+    // returns whether it was stored
+    fn store_prompt_version_if_missing(&self, version: PromptVersion) -> Result<bool, String> {
+        if self.get_prompt_versions(version.prompt)?.iter().any(|v| v.version == version.version) {
+            return Ok(false);
+        }
+        self.store_prompt_version(version).map(|_| true)
+    }
+
+    // unix time in milliseconds of the newest message of `id`
+    fn get_updated(&self, id: ConversationId) -> Result<u64, String> {
+        self.get_tree(id).map(|tree| tree.updated_at())
+    }
+
+    // bytes the messages of `id` take up, here those of the serialized tree
+    fn get_size(&self, id: ConversationId) -> Result<u64, String> {
+        let tree = self.get_tree(id)?;
+        Ok(serde_json::to_vec(&tree).map_err(|e| e.to_string())?.len() as u64)
+    }
+
+    // matching conversations, pinned ones first
+    fn filter_conversations(&self, filter: &ConversationFilter) -> Result<Vec<ConversationEntry>, String> {
+        let mut entries = vec![];
+        for id in self.get_conversations()? {
+            let meta = self.get_meta(id)?;
+            if filter.matches(&meta) {
+                let updated_at = self.get_updated(id)?;
+                entries.push(ConversationEntry { id, meta, updated_at });
+            }
+        }
+        entries.sort_by_key(|e| !e.meta.pinned);
+        Ok(entries)
+    }
+
+    // matching conversations after `after`, pinned ones first, then most recently updated
+    fn list_conversations(
+        &self,
+        filter: &ConversationFilter,
+        after: Option<ConversationCursor>,
+        limit: usize,
+    ) -> Result<Page<ConversationEntry, ConversationCursor>, String> {
+        scan_conversations(self, filter, after, limit)
+    }
+
+    // a page of the active branch, see `ConversationTree::page`
+    fn get_messages(
+        &self,
+        id: ConversationId,
+        before: Option<MessageId>,
+        limit: usize,
+    ) -> Result<Page<PathNode, MessageId>, String> {
+        self.get_tree(id).map(|tree| tree.page(before, limit))
+    }
+
+    // messages containing every word of `query`, in any branch, newest first
+    fn search(&self, query: &str) -> Result<Vec<SearchHit>, String> {
+        let query = search::tokenize(query);
+        let mut hits = vec![];
+        // the newest hits can be in any conversation, all of them are scanned
+        for id in self.get_conversations()? {
+            hits.extend(search::scan(id, &self.get_tree(id)?, &query));
+        }
+        Ok(search::newest(hits))
+    }
+}
+
+/// `list_conversations` for backends without an index on update time,
+/// reads the meta and update time of every conversation.
+pub fn scan_conversations<S: Storage + ?Sized>(
+    store: &S,
+    filter: &ConversationFilter,
+    after: Option<ConversationCursor>,
+    limit: usize,
+) -> Result<Page<ConversationEntry, ConversationCursor>, String> {
+    let mut entries = vec![];
+    for id in store.get_conversations()? {
+        let meta = store.get_meta(id)?;
+        if filter.matches(&meta) {
+            let entry = ConversationEntry { id, meta, updated_at: store.get_updated(id)? };
+            if after.map(|after| after.before(&entry.cursor())).unwrap_or(true) {
+                entries.push(entry);
+            }
+        }
+    }
+    entries.sort_by(|a, b| {
+        (b.meta.pinned, b.updated_at, b.id.0).cmp(&(a.meta.pinned, a.updated_at, a.id.0))
+    });
+    Ok(paginate(entries, limit))
+}
+
+/// Copy every conversation of `from` into `to` as stored, with its title and
+/// meta, replacing those `to` has under the same id, and the prompt library
+/// with its versions after them. `progress` is called with the number of conversations copied
+/// and the total after each one. Returns the total.
+pub fn migrate<F, T>(from: &F, to: &T, mut progress: impl FnMut(usize, usize)) -> Result<usize, String>
+where
+    F: Storage + ?Sized,
+    T: Storage + ?Sized,
+{
+    let ids = from.get_conversations()?;
+    let total = ids.len();
+    // deleted prompts keep the versions conversations were started with
+    let mut started_with = vec![];
+    progress(0, total);
+    for (n, id) in ids.into_iter().enumerate() {
+        to.store_tree(id, from.get_tree(id)?)?;
+        if let Some(title) = from.get_title(id) {
+            to.store_title(id, title)?;
+        }
+        let meta = from.get_meta(id)?;
+        started_with.extend(meta.prompt.map(|p| p.id));
+        to.store_meta(id, meta)?;
+        progress(n + 1, total);
+    }
+    let prompts = from.get_prompts()?;
+    started_with.extend(prompts.iter().map(|p| p.id));
+    started_with.sort_by_key(|id| id.0);
+    started_with.dedup();
+    for id in started_with {
+        for version in from.get_prompt_versions(id)? {
+            to.store_prompt_version(version)?;
+        }
+    }
+    for prompt in prompts {
+        to.store_prompt(prompt)?;
+    }
+    Ok(total)
+}
+
+// cut `entries` holding more than `limit` items into a page
+pub fn paginate(
+    mut entries: Vec<ConversationEntry>,
+    limit: usize,
+) -> Page<ConversationEntry, ConversationCursor> {
+    let limit = limit.max(1);
+    let next = if entries.len() > limit {
+        entries.truncate(limit);
+        entries.last().map(|e| e.cursor())
+    } else {
+        None
+    };
+    Page {
+        items: entries,
+        next,
+    }
 }
 
 #[cfg(feature = "local-storage")]
 pub mod local {
     use super::*;
 
-    use dashmap::DashMap;
+    use std::collections::BTreeMap;
+    use std::sync::mpsc::{channel, Sender};
+    use std::sync::Mutex;
+
+    use dashmap::{mapref::entry::Entry, DashMap};
 
     #[derive(Debug)]
     pub struct KVStorage {
-        data: DashMap<ConversationId, Vec<Message>>,
+        // version and tree of every conversation
+        data: DashMap<ConversationId, (u64, ConversationTree)>,
         titles: DashMap<ConversationId, String>,
+        metas: DashMap<ConversationId, ConversationMeta>,
+        prompts: DashMap<PromptId, Prompt>,
+        prompt_versions: DashMap<PromptId, BTreeMap<u32, PromptVersion>>,
+        subscribers: Mutex<Vec<Sender<StorageEvent>>>,
     }
 
     impl KVStorage {
@@ -40,129 +278,1006 @@ pub mod local {
             KVStorage {
                 data: DashMap::new(),
                 titles: DashMap::new(),
+                metas: DashMap::new(),
+                prompts: DashMap::new(),
+                prompt_versions: DashMap::new(),
+                subscribers: Mutex::new(vec![]),
             }
         }
+
+        // tell every live subscriber, forgetting the ones that went away
+        fn notify(&self, event: StorageEvent) {
+            self.subscribers
+                .lock()
+                .unwrap()
+                .retain(|tx| tx.send(event).is_ok());
+        }
+
+        fn changed(&self, id: ConversationId, created: bool) {
+            self.notify(if created {
+                StorageEvent::Created(id)
+            } else {
+                StorageEvent::Updated(id)
+            });
+        }
     }
 
     impl Storage for KVStorage {
-        fn store_message(&self, id: ConversationId, msg: Message) {
-            let mut chats = self.data.entry(id).or_default();
-            chats.push(msg);
+        fn store_message(&self, id: ConversationId, msg: Message) -> Result<(), String> {
+            let created = !self.data.contains_key(&id);
+            {
+                let mut chats = self.data.entry(id).or_default();
+                chats.0 += 1;
+                chats.1.push(msg);
+            }
+            self.changed(id, created);
+            Ok(())
         }
 
         fn store_conversation(&self, id: ConversationId, msgs: Vec<Message>) -> Result<(), String> {
-            let mut chats = self.data.entry(id).or_default();
-            *chats.value_mut() = msgs;
+            let created = !self.data.contains_key(&id);
+            {
+                let mut chats = self.data.entry(id).or_default();
+                chats.0 += 1;
+                chats.1.replace_path(msgs);
+            }
+            self.changed(id, created);
+            Ok(())
+        }
 
+        fn store_tree(&self, id: ConversationId, tree: ConversationTree) -> Result<(), String> {
+            let created = !self.data.contains_key(&id);
+            {
+                let mut chats = self.data.entry(id).or_default();
+                *chats = (chats.0 + 1, tree);
+            }
+            self.changed(id, created);
             Ok(())
         }
 
-        fn get_conversation(&self, id: ConversationId) -> Result<Vec<Message>, String> {
+        fn get_tree(&self, id: ConversationId) -> Result<ConversationTree, String> {
+            self.get_versioned_tree(id).map(|(_, tree)| tree)
+        }
+
+        fn get_versioned_tree(&self, id: ConversationId) -> Result<(u64, ConversationTree), String> {
             self.data
                 .get(&id)
                 .map(|kv| kv.value().clone())
-                .ok_or("no conversation found".to_string())
+                .ok_or(NOT_FOUND_ERROR.to_string())
+        }
+
+        fn compare_and_store_tree(&self, id: ConversationId, version: u64, tree: ConversationTree) -> Result<u64, String> {
+            // the entry stays locked until it's dropped, nothing gets in between
+            match self.data.entry(id) {
+                Entry::Occupied(mut chats) if chats.get().0 == version => {
+                    chats.insert((version + 1, tree));
+                }
+                Entry::Vacant(chats) if version == 0 => {
+                    chats.insert((1, tree));
+                }
+                _ => return Err(CONFLICT_ERROR.to_string()),
+            }
+            self.changed(id, version == 0);
+            Ok(version + 1)
+        }
+
+        fn compare_and_append(&self, id: ConversationId, version: u64, msg: Message) -> Result<u64, String> {
+            match self.data.entry(id) {
+                Entry::Occupied(mut chats) if chats.get().0 == version => {
+                    let chats = chats.get_mut();
+                    chats.0 += 1;
+                    chats.1.push(msg);
+                }
+                Entry::Vacant(chats) if version == 0 => {
+                    chats.insert((1, ConversationTree::from_messages(vec![msg])));
+                }
+                _ => return Err(CONFLICT_ERROR.to_string()),
+            }
+            self.changed(id, version == 0);
+            Ok(version + 1)
         }
 
         fn get_conversations(&self) -> Result<Vec<ConversationId>, String> {
             Ok(self.data.iter().map(|v| *v.key()).collect::<Vec<_>>())
         }
 
+        fn delete_conversation(&self, id: ConversationId) -> Result<(), String> {
+            self.titles.remove(&id);
+            self.metas.remove(&id);
+            if self.data.remove(&id).is_some() {
+                self.notify(StorageEvent::Deleted(id));
+            }
+            Ok(())
+        }
+
+        fn subscribe(&self) -> Result<Receiver<StorageEvent>, String> {
+            let (tx, rx) = channel();
+            self.subscribers.lock().unwrap().push(tx);
+            Ok(rx)
+        }
+
         fn store_title(&self, id: ConversationId, msg: String) -> Result<(), String> {
-            self.titles.entry(id).or_insert(msg);
+            self.titles.insert(id, msg);
+            self.notify(StorageEvent::TitleChanged(id));
             Ok(())
         }
 
         fn get_title(&self, id: ConversationId) -> Option<String> {
             self.titles.get(&id).map(|kv| kv.value().clone())
         }
+
+        fn store_title_if_missing(&self, id: ConversationId, title: String) -> Result<String, String> {
+            match self.titles.entry(id) {
+                Entry::Occupied(old) => Ok(old.get().clone()),
+                Entry::Vacant(entry) => {
+                    entry.insert(title.clone());
+                    self.notify(StorageEvent::TitleChanged(id));
+                    Ok(title)
+                }
+            }
+        }
+
+        fn store_meta(&self, id: ConversationId, meta: ConversationMeta) -> Result<(), String> {
+            self.metas.insert(id, meta);
+            self.notify(StorageEvent::MetaChanged(id));
+            Ok(())
+        }
+
+        fn get_meta(&self, id: ConversationId) -> Result<ConversationMeta, String> {
+            Ok(self.metas.get(&id).map(|kv| kv.value().clone()).unwrap_or_default())
+        }
+
+        fn get_prompts(&self) -> Result<Vec<Prompt>, String> {
+            Ok(self.prompts.iter().map(|kv| kv.value().clone()).collect())
+        }
+
+        fn store_prompt(&self, prompt: Prompt) -> Result<(), String> {
+            self.prompts.insert(prompt.id, prompt);
+            Ok(())
+        }
+
+        fn delete_prompt(&self, id: PromptId) -> Result<(), String> {
+            self.prompts.remove(&id);
+            Ok(())
+        }
+
+        fn get_prompt_versions(&self, id: PromptId) -> Result<Vec<PromptVersion>, String> {
+            Ok(self
+                .prompt_versions
+                .get(&id)
+                .map(|kv| kv.value().values().cloned().collect())
+                .unwrap_or_default())
+        }
+
+        fn store_prompt_version(&self, version: PromptVersion) -> Result<(), String> {
+            self.prompt_versions
+                .entry(version.prompt)
+                .or_default()
+                .insert(version.version, version);
+            Ok(())
+        }
+
+        fn store_prompt_version_if_missing(&self, version: PromptVersion) -> Result<bool, String> {
+            let mut versions = self.prompt_versions.entry(version.prompt).or_default();
+            if versions.contains_key(&version.version) {
+                return Ok(false);
+            }
+            versions.insert(version.version, version);
+            Ok(true)
+        }
     }
 }
 
 #[cfg(feature = "persist-storage")]
 pub mod disk {
+    use std::collections::{HashMap, HashSet};
     use std::path::Path;
+    use std::sync::mpsc::channel;
 
     use super::*;
 
+    use common::{Content, MessageId, MessageMeta, MessageNode, Role};
     use itertools::Itertools;
     use serde::{Deserialize, Serialize};
-    use sled::{Config, Db, IVec};
+    use sled::transaction::{ConflictableTransactionError, TransactionError};
+    use sled::{Batch, Config, Db, Event, IVec, Transactional, Tree};
+
+    /// zstd level used for message bodies unless told otherwise
+    pub const DEFAULT_COMPRESSION: i32 = 3;
+    // bodies shorter than this don't get any smaller
+    const COMPRESS_MIN: usize = 256;
+    const PLAIN: u8 = 0;
+    const ZSTD: u8 = 1;
 
     #[derive(Debug)]
     pub struct KVStorage {
+        // conversation -> `Head`, titles under `<id>:title`
         db: Db,
+        // `conversation seq` -> message node, seq counting up from 0 in insertion order
+        messages: Tree,
+        // `conversation seq` -> `message parent`, the shape of the tree without the bodies
+        links: Tree,
+        // zstd level for message bodies, 0 stores them as they are
+        compression: i32,
+        // `term \0 conversation message` -> ()
+        postings: Tree,
+        // `conversation term` -> (), the terms it has postings for, to drop them on reindex
+        indexed: Tree,
+        metas: Tree,
+        // conversation -> (), the ones whose meta says pinned
+        pinned: Tree,
+        // conversation -> newest message time, unix ms as big endian
+        recent: Tree,
+        // `time conversation` -> (), lists conversations by update time
+        updated: Tree,
+        // prompt id -> prompt
+        prompts: Tree,
+        // `prompt version` -> prompt version, the number big endian
+        prompt_versions: Tree,
     }
 
     impl KVStorage {
         pub fn new<P: AsRef<Path>>(path: P) -> sled::Result<Self> {
-            Ok(KVStorage {
-                db: Config::new().temporary(false).path(path).open()?,
+            let db = Config::new().temporary(false).path(path).open()?;
+            let storage = KVStorage {
+                messages: db.open_tree("messages")?,
+                links: db.open_tree("messages.links")?,
+                compression: DEFAULT_COMPRESSION,
+                postings: db.open_tree("search.postings")?,
+                indexed: db.open_tree("search.terms")?,
+                metas: db.open_tree("meta")?,
+                pinned: db.open_tree("meta.pinned")?,
+                recent: db.open_tree("recent")?,
+                updated: db.open_tree("updated")?,
+                prompts: db.open_tree("prompts")?,
+                prompt_versions: db.open_tree("prompts.versions")?,
+                db,
+            };
+
+            // conversations written as a single value are split into one key per message
+            if let Err(e) = storage.migrate() {
+                eprintln!("migrate conversations failed: {}", e);
+            }
+            // databases written before search existed have no index yet, those
+            // written before terms had a key each have one in the old layout
+            if storage.indexed.is_empty() {
+                let rebuilt = storage
+                    .postings
+                    .clear()
+                    .map_err(|e| e.to_string())
+                    .and_then(|_| storage.reindex_all());
+                match rebuilt {
+                    Ok(_) => {
+                        storage.db.drop_tree("search.indexed")?;
+                    }
+                    Err(e) => eprintln!("reindex failed: {}", e),
+                }
+            }
+            if storage.recent.is_empty() {
+                if let Err(e) = storage.retouch_all() {
+                    eprintln!("update times failed: {}", e);
+                }
+            }
+            if storage.pinned.is_empty() {
+                if let Err(e) = storage.repin_all() {
+                    eprintln!("pinned conversations failed: {}", e);
+                }
+            }
+            Ok(storage)
+        }
+
+        /// zstd level for message bodies written from now on, 0 turns compression off
+        pub fn compression(mut self, level: i32) -> Self {
+            self.compression = level;
+            self
+        }
+
+        fn migrate(&self) -> Result<(), String> {
+            for id in self.get_conversations()? {
+                let value = match self.db.get(&id.0).map_err(|e| e.to_string())? {
+                    Some(value) => value,
+                    None => continue,
+                };
+                if let Some((version, tree)) = StoredConversation::decode(&value)?.legacy() {
+                    self.write_tree(id, Some(version), tree)?;
+                }
+            }
+            Ok(())
+        }
+
+        fn message_key(id: ConversationId, seq: u64) -> Vec<u8> {
+            let mut key = id.0.as_bytes().to_vec();
+            key.extend_from_slice(&seq.to_be_bytes());
+            key
+        }
+
+        fn encode_node(&self, node: &MessageNode) -> Result<Vec<u8>, String> {
+            let data = serde_json::to_vec(node).map_err(|e| e.to_string())?;
+            if self.compression > 0 && data.len() >= COMPRESS_MIN {
+                let mut value = vec![ZSTD];
+                value.extend(zstd::encode_all(&data[..], self.compression).map_err(|e| e.to_string())?);
+                Ok(value)
+            } else {
+                let mut value = vec![PLAIN];
+                value.extend(data);
+                Ok(value)
+            }
+        }
+
+        // parent links of the whole conversation and the key of every message
+        fn skeleton(&self, id: ConversationId, head: &Head) -> Result<(ConversationTree, HashMap<MessageId, IVec>), String> {
+            let mut keys = HashMap::new();
+            let mut tree = ConversationTree {
+                nodes: vec![],
+                current: head.current,
+            };
+            for item in self.links.scan_prefix(id.0.as_bytes()) {
+                let (key, value) = item.map_err(|e| e.to_string())?;
+                let (message, parent) = decode_link(&value)?;
+                keys.insert(message, key);
+                tree.nodes.push(MessageNode {
+                    parent,
+                    message: Message {
+                        id: message,
+                        role: Role::User,
+                        content: Content::default(),
+                        created_at: 0,
+                        meta: Default::default(),
+                    },
+                });
+            }
+            Ok((tree, keys))
+        }
+
+        fn load_tree(&self, id: ConversationId, head: &Head) -> Result<ConversationTree, String> {
+            let nodes = self
+                .messages
+                .scan_prefix(id.0.as_bytes())
+                .values()
+                .map(|value| decode_node(&value.map_err(|e| e.to_string())?))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(ConversationTree {
+                nodes,
+                current: head.current,
             })
         }
+
+        // Replace every message of `id` with those of `tree`, if `id` is still at
+        // `expected` when given. Returns the new version.
+        fn write_tree(&self, id: ConversationId, expected: Option<u64>, tree: ConversationTree) -> Result<u64, String> {
+            let nodes = tree
+                .nodes
+                .iter()
+                .map(|node| Ok((self.encode_node(node)?, encode_link(node))))
+                .collect::<Result<Vec<_>, String>>()?;
+
+            let result = (&*self.db, &self.messages, &self.links).transaction(|(db, messages, links)| {
+                let head = read_head(db.get(id.0.as_bytes())?).map_err(ConflictableTransactionError::Abort)?;
+                if expected.map(|v| v != head.version).unwrap_or(false) {
+                    return Err(ConflictableTransactionError::Abort(CONFLICT_ERROR.to_string()));
+                }
+
+                for (seq, (node, link)) in nodes.iter().enumerate() {
+                    let key = Self::message_key(id, seq as u64);
+                    messages.insert(key.clone(), node.clone())?;
+                    links.insert(key, link.clone())?;
+                }
+                // only keys past the new end are left over from before
+                for seq in nodes.len() as u64..head.next {
+                    let key = Self::message_key(id, seq);
+                    messages.remove(key.clone())?;
+                    links.remove(key)?;
+                }
+
+                let version = head.version + 1;
+                let head = Head {
+                    version,
+                    current: tree.current,
+                    next: nodes.len() as u64,
+                };
+                db.insert(id.0.as_bytes(), encode_head(head).map_err(ConflictableTransactionError::Abort)?)?;
+                Ok(version)
+            });
+            flatten(result)
+        }
+
+        // Store `msg` under the next key of `id`, leaving the others untouched.
+        // Fails unless `id` is at `expected` when that is given.
+        fn append(&self, id: ConversationId, expected: Option<u64>, msg: &Message) -> Result<u64, String> {
+            let result = (&*self.db, &self.messages, &self.links).transaction(|(db, messages, links)| {
+                let head = read_head(db.get(id.0.as_bytes())?).map_err(ConflictableTransactionError::Abort)?;
+                if expected.map(|v| v != head.version).unwrap_or(false) {
+                    return Err(ConflictableTransactionError::Abort(CONFLICT_ERROR.to_string()));
+                }
+                let node = MessageNode {
+                    parent: head.current,
+                    message: msg.clone(),
+                };
+
+                let key = Self::message_key(id, head.next);
+                messages.insert(key.clone(), self.encode_node(&node).map_err(ConflictableTransactionError::Abort)?)?;
+                links.insert(key, encode_link(&node))?;
+
+                let version = head.version + 1;
+                let head = Head {
+                    version,
+                    current: Some(msg.id),
+                    next: head.next + 1,
+                };
+                db.insert(id.0.as_bytes(), encode_head(head).map_err(ConflictableTransactionError::Abort)?)?;
+                Ok(version)
+            });
+            flatten(result)
+        }
+
+        // point the head of `id` at `current`, the messages stay as they are
+        fn set_current(&self, id: ConversationId, expected: u64, current: Option<MessageId>) -> Result<u64, String> {
+            let result = self.db.transaction(|db| {
+                let head = match db.get(id.0.as_bytes())? {
+                    Some(value) => read_head(Some(value)).map_err(ConflictableTransactionError::Abort)?,
+                    None => return Err(ConflictableTransactionError::Abort(NOT_FOUND_ERROR.to_string())),
+                };
+                if head.version != expected {
+                    return Err(ConflictableTransactionError::Abort(CONFLICT_ERROR.to_string()));
+                }
+
+                let version = head.version + 1;
+                let head = Head {
+                    version,
+                    current,
+                    ..head
+                };
+                db.insert(id.0.as_bytes(), encode_head(head).map_err(ConflictableTransactionError::Abort)?)?;
+                Ok(version)
+            });
+            flatten(result)
+        }
+
+        // `prompt version`, the number big endian so versions list in order
+        fn version_key(version: &PromptVersion) -> Vec<u8> {
+            let mut key = version.prompt.0.as_bytes().to_vec();
+            key.extend_from_slice(&version.version.to_be_bytes());
+            key
+        }
+
+        fn updated_key(at: u64, id: ConversationId) -> Vec<u8> {
+            let mut key = at.to_be_bytes().to_vec();
+            key.extend_from_slice(id.0.as_bytes());
+            key
+        }
+
+        fn retouch_all(&self) -> Result<(), String> {
+            for id in self.get_conversations()? {
+                self.touch(id, self.get_tree(id)?.updated_at())?;
+            }
+            Ok(())
+        }
+
+        // move `id` to `at` in the update order, unless it was updated later already
+        fn touch(&self, id: ConversationId, at: u64) -> Result<(), String> {
+            let result = (&self.recent, &self.updated).transaction(|(recent, updated)| {
+                let old = recent.get(id.0.as_bytes())?.map(be_u64);
+                match old {
+                    Some(old) if old >= at => return Ok(()),
+                    Some(old) => {
+                        updated.remove(Self::updated_key(old, id))?;
+                    }
+                    None => {}
+                }
+                recent.insert(id.0.as_bytes(), &at.to_be_bytes())?;
+                updated.insert(Self::updated_key(at, id), &[])?;
+                Ok(())
+            });
+            flatten(result)
+        }
+
+        fn repin_all(&self) -> Result<(), String> {
+            for id in self.get_conversations()? {
+                if self.get_meta(id)?.pinned {
+                    self.pinned.insert(&id.0, &[]).map_err(|e| e.to_string())?;
+                }
+            }
+            Ok(())
+        }
+
+        fn posting_key(term: &str, id: ConversationId, msg: MessageId) -> Vec<u8> {
+            let mut key = term.as_bytes().to_vec();
+            key.push(0);
+            key.extend_from_slice(id.0.as_bytes());
+            key.extend_from_slice(msg.0.as_bytes());
+            key
+        }
+
+        fn reindex_all(&self) -> Result<(), String> {
+            for id in self.get_conversations()? {
+                self.reindex(id, &self.get_tree(id)?)?;
+            }
+            Ok(())
+        }
+
+        // drop every posting of `id` and index the messages of `tree` instead
+        fn reindex(&self, id: ConversationId, tree: &ConversationTree) -> Result<(), String> {
+            let mut postings = Batch::default();
+            let mut indexed = Batch::default();
+            for key in self.indexed.scan_prefix(id.0.as_bytes()).keys() {
+                let key = key.map_err(|e| e.to_string())?;
+                let mut prefix = key[16..].to_vec();
+                prefix.push(0);
+                prefix.extend_from_slice(id.0.as_bytes());
+                for posting in self.postings.scan_prefix(prefix).keys() {
+                    postings.remove(posting.map_err(|e| e.to_string())?);
+                }
+                indexed.remove(key);
+            }
+            self.postings.apply_batch(postings).map_err(|e| e.to_string())?;
+            self.indexed.apply_batch(indexed).map_err(|e| e.to_string())?;
+
+            for node in &tree.nodes {
+                self.index_message(id, &node.message)?;
+            }
+            Ok(())
+        }
+
+        // only the terms of `msg` are written, whatever else `id` has stays as is
+        fn index_message(&self, id: ConversationId, msg: &Message) -> Result<(), String> {
+            let mut postings = Batch::default();
+            let mut indexed = Batch::default();
+            for term in search::terms(&msg.content.text()) {
+                postings.insert(Self::posting_key(&term, id, msg.id), &[]);
+                let mut key = id.0.as_bytes().to_vec();
+                key.extend_from_slice(term.as_bytes());
+                indexed.insert(key, &[]);
+            }
+            self.postings.apply_batch(postings).map_err(|e| e.to_string())?;
+            self.indexed.apply_batch(indexed).map_err(|e| e.to_string())
+        }
+
+        // index and order a message `append` stored, failing here leaves it stored
+        fn appended(&self, id: ConversationId, msg: &Message) {
+            if let Err(e) = self.index_message(id, msg) {
+                eprintln!("index message: {}", e);
+            }
+            if let Err(e) = self.touch(id, msg.created_at) {
+                eprintln!("touch conversation: {}", e);
+            }
+        }
+    }
+
+    // what the conversation key holds, its messages have keys of their own
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    struct Head {
+        version: u64,
+        current: Option<MessageId>,
+        // seq of the next message
+        next: u64,
+    }
+
+    // Conversations used to be a single value: a versioned tree, before that a
+    // bare tree, and before branching was supported a plain list.
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StoredConversation {
+        Head(Head),
+        Versioned { version: u64, tree: LegacyTree },
+        Tree(LegacyTree),
+        List(Vec<LegacyMessage>),
     }
 
-    #[derive(Serialize, Deserialize)]
-    struct MessageList(Vec<Message>);
+    // A message of a single value conversation. The first ones had neither
+    // id nor time, trees kept the ids on their nodes for a while.
+    #[derive(Deserialize)]
+    struct LegacyMessage {
+        #[serde(default)]
+        id: Option<MessageId>,
+        role: Role,
+        content: Content,
+        #[serde(default)]
+        created_at: Option<u64>,
+        #[serde(default)]
+        meta: MessageMeta,
+    }
+
+    #[derive(Deserialize)]
+    struct LegacyNode {
+        #[serde(default)]
+        id: Option<MessageId>,
+        parent: Option<MessageId>,
+        message: LegacyMessage,
+    }
+
+    #[derive(Deserialize)]
+    struct LegacyTree {
+        nodes: Vec<LegacyNode>,
+        current: Option<MessageId>,
+    }
+
+    impl LegacyMessage {
+        // Missing ids and times are made up here, once, the migration writes
+        // them back. Unknown times become the time of the migration.
+        fn upgrade(self, id: Option<MessageId>) -> Message {
+            let fresh = Message::new(self.role, self.content);
+            Message {
+                id: self.id.or(id).unwrap_or(fresh.id),
+                created_at: self.created_at.filter(|&t| t > 0).unwrap_or(fresh.created_at),
+                meta: self.meta,
+                ..fresh
+            }
+        }
+    }
 
-    impl TryFrom<IVec> for MessageList {
-        type Error = String;
+    impl LegacyTree {
+        fn upgrade(self) -> ConversationTree {
+            ConversationTree {
+                nodes: self
+                    .nodes
+                    .into_iter()
+                    .map(|node| MessageNode {
+                        parent: node.parent,
+                        message: node.message.upgrade(node.id),
+                    })
+                    .collect(),
+                current: self.current,
+            }
+        }
+    }
 
-        fn try_from(value: IVec) -> Result<Self, Self::Error> {
+    impl StoredConversation {
+        fn decode<V: AsRef<[u8]>>(value: V) -> Result<Self, String> {
             serde_json::from_slice(value.as_ref()).map_err(|e| e.to_string())
         }
+
+        // version and tree of a conversation stored as a single value
+        fn legacy(self) -> Option<(u64, ConversationTree)> {
+            match self {
+                StoredConversation::Head(_) => None,
+                StoredConversation::Versioned { version, tree } => Some((version, tree.upgrade())),
+                StoredConversation::Tree(tree) => Some((0, tree.upgrade())),
+                StoredConversation::List(msgs) => Some((
+                    0,
+                    ConversationTree::from_messages(msgs.into_iter().map(|m| m.upgrade(None)).collect()),
+                )),
+            }
+        }
+    }
+
+    // a conversation not stored yet is at version 0 without messages
+    fn read_head(value: Option<IVec>) -> Result<Head, String> {
+        match value {
+            Some(value) => match StoredConversation::decode(&value)? {
+                StoredConversation::Head(head) => Ok(head),
+                legacy => Ok(Head {
+                    version: legacy.legacy().map(|(v, _)| v).unwrap_or(0),
+                    ..Default::default()
+                }),
+            },
+            None => Ok(Head::default()),
+        }
+    }
+
+    fn encode_head(head: Head) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(&head).map_err(|e| e.to_string())
+    }
+
+    fn decode_node(value: &[u8]) -> Result<MessageNode, String> {
+        match value.split_first() {
+            Some((&PLAIN, data)) => serde_json::from_slice(data).map_err(|e| e.to_string()),
+            Some((&ZSTD, data)) => {
+                let data = zstd::decode_all(data).map_err(|e| e.to_string())?;
+                serde_json::from_slice(&data).map_err(|e| e.to_string())
+            }
+            _ => Err("corrupted message".to_string()),
+        }
+    }
+
+    // message id followed by the parent id, if any
+    fn encode_link(node: &MessageNode) -> Vec<u8> {
+        let mut link = node.id().0.as_bytes().to_vec();
+        if let Some(parent) = node.parent {
+            link.extend_from_slice(parent.0.as_bytes());
+        }
+        link
     }
 
-    impl TryFrom<MessageList> for Vec<u8> {
-        type Error = String;
+    fn decode_link(value: &[u8]) -> Result<(MessageId, Option<MessageId>), String> {
+        let (message, parent) = value.split_at(16.min(value.len()));
+        let message = Uuid::from_slice(message).map_err(|e| e.to_string())?;
+        let parent = match parent.is_empty() {
+            true => None,
+            false => Some(MessageId(Uuid::from_slice(parent).map_err(|e| e.to_string())?)),
+        };
+        Ok((MessageId(message), parent))
+    }
 
-        fn try_from(value: MessageList) -> Result<Self, Self::Error> {
-            serde_json::to_vec(&value).map_err(|e| e.to_string())
+    fn flatten<T>(result: Result<T, TransactionError<String>>) -> Result<T, String> {
+        match result {
+            Ok(v) => Ok(v),
+            Err(TransactionError::Abort(e)) => Err(e),
+            Err(TransactionError::Storage(e)) => Err(e.to_string()),
         }
     }
 
+    // the conversation a `<id>:title` key belongs to
+    fn title_owner(key: &[u8]) -> Option<ConversationId> {
+        let id = std::str::from_utf8(key).ok()?.strip_suffix(":title")?;
+        Uuid::parse_str(id).ok().map(ConversationId)
+    }
+
+    fn be_u64<V: AsRef<[u8]>>(value: V) -> u64 {
+        value
+            .as_ref()
+            .try_into()
+            .map(u64::from_be_bytes)
+            .unwrap_or(0)
+    }
+
     impl Storage for KVStorage {
-        fn store_message(&self, id: ConversationId, msg: Message) {
-            self.db
-                .fetch_and_update(&id.0, |old| {
-                    let mut msgs = MessageList(vec![]);
-                    match old {
-                        Some(old) => { msgs = IVec::from(old).try_into().unwrap(); }
-                        None => {},
-                    }
-                    msgs.0.push(msg.clone());
-                    Some::<Vec<u8>>(msgs.try_into().unwrap())
-                })
-                .unwrap();
+        fn store_message(&self, id: ConversationId, msg: Message) -> Result<(), String> {
+            self.append(id, None, &msg)?;
+            self.appended(id, &msg);
+            Ok(())
         }
 
-        fn store_conversation(&self, id: ConversationId, msgs: Vec<Message>) -> Result<(), String> {
-            let data: Vec<u8> = MessageList(msgs).try_into().unwrap();
-            self.db
-                .insert(&id.0, data)
-                .map(|_| ())
-                .map_err(|e| e.to_string())
+        fn store_tree(&self, id: ConversationId, tree: ConversationTree) -> Result<(), String> {
+            // indexed once stored, never for content that failed to be written
+            self.write_tree(id, None, tree.clone())?;
+            self.reindex(id, &tree)?;
+            self.touch(id, tree.updated_at())
         }
 
-        fn get_conversation(&self, id: ConversationId) -> Result<Vec<Message>, String> {
-            self.db
-                .get(&id.0)
-                .map_err(|e| e.to_string())
-                .and_then(|old| match old {
-                    Some(val) => val.try_into().map(|v: MessageList| v.0),
-                    None => Err("".to_string()),
-                })
+        fn get_tree(&self, id: ConversationId) -> Result<ConversationTree, String> {
+            self.get_versioned_tree(id).map(|(_, tree)| tree)
+        }
+
+        fn get_versioned_tree(&self, id: ConversationId) -> Result<(u64, ConversationTree), String> {
+            match self.db.get(&id.0).map_err(|e| e.to_string())? {
+                Some(value) => {
+                    let head = read_head(Some(value))?;
+                    Ok((head.version, self.load_tree(id, &head)?))
+                }
+                None => Err(NOT_FOUND_ERROR.to_string()),
+            }
+        }
+
+        fn compare_and_store_tree(&self, id: ConversationId, version: u64, tree: ConversationTree) -> Result<u64, String> {
+            let version = self.write_tree(id, Some(version), tree.clone())?;
+            self.reindex(id, &tree)?;
+            self.touch(id, tree.updated_at())?;
+            Ok(version)
+        }
+
+        fn compare_and_append(&self, id: ConversationId, version: u64, msg: Message) -> Result<u64, String> {
+            let version = self.append(id, Some(version), &msg)?;
+            self.appended(id, &msg);
+            Ok(version)
+        }
+
+        // only the head is written, nothing to compress or index again
+        fn compare_and_set_current(&self, id: ConversationId, version: u64, current: Option<MessageId>) -> Result<u64, String> {
+            self.set_current(id, version, current)
+        }
+
+        // only the bodies of the messages on the page are read
+        fn get_messages(
+            &self,
+            id: ConversationId,
+            before: Option<MessageId>,
+            limit: usize,
+        ) -> Result<Page<PathNode, MessageId>, String> {
+            let head = match self.db.get(&id.0).map_err(|e| e.to_string())? {
+                Some(value) => read_head(Some(value))?,
+                None => return Err(NOT_FOUND_ERROR.to_string()),
+            };
+
+            let (skeleton, keys) = self.skeleton(id, &head)?;
+            let mut page = skeleton.page(before, limit);
+            for item in page.items.iter_mut() {
+                let value = keys
+                    .get(&item.node.id())
+                    .and_then(|key| self.messages.get(key).transpose())
+                    .ok_or(CONFLICT_ERROR.to_string())?
+                    .map_err(|e| e.to_string())?;
+                let node = decode_node(&value)?;
+                // replaced by a writer in between reading links and bodies
+                if node.id() != item.node.id() {
+                    return Err(CONFLICT_ERROR.to_string());
+                }
+                item.node = node;
+            }
+            Ok(page)
         }
 
         fn get_conversations(&self) -> Result<Vec<ConversationId>, String> {
+            // titles share the tree under `<id>:title`, only raw uuids are conversations
             self.db
                 .scan_prefix(&[])
                 .keys()
-                .map_ok(|k| ConversationId(Uuid::from_slice(&k).unwrap()))
+                .filter_map_ok(|k| Uuid::from_slice(&k).ok().map(ConversationId))
                 .try_collect::<_, Vec<_>, _>()
                 .map_err(|e| e.to_string())
         }
 
+        fn delete_conversation(&self, id: ConversationId) -> Result<(), String> {
+            // indexing nothing drops the postings
+            self.reindex(id, &ConversationTree::default())?;
+            if let Some(at) = self.recent.remove(&id.0).map_err(|e| e.to_string())? {
+                self.updated
+                    .remove(Self::updated_key(be_u64(at), id))
+                    .map_err(|e| e.to_string())?;
+            }
+            self.metas.remove(&id.0).map_err(|e| e.to_string())?;
+            self.pinned.remove(&id.0).map_err(|e| e.to_string())?;
+            self.db
+                .remove(format!("{}:title", id.0))
+                .map_err(|e| e.to_string())?;
+
+            // the head goes last, without it the messages are unreachable anyway
+            let mut batch = Batch::default();
+            for key in self.messages.scan_prefix(id.0.as_bytes()).keys() {
+                batch.remove(key.map_err(|e| e.to_string())?);
+            }
+            self.messages.apply_batch(batch).map_err(|e| e.to_string())?;
+            let mut batch = Batch::default();
+            for key in self.links.scan_prefix(id.0.as_bytes()).keys() {
+                batch.remove(key.map_err(|e| e.to_string())?);
+            }
+            self.links.apply_batch(batch).map_err(|e| e.to_string())?;
+            self.db.remove(&id.0).map(|_| ()).map_err(|e| e.to_string())
+        }
+
+        fn subscribe(&self) -> Result<Receiver<StorageEvent>, String> {
+            let (tx, rx) = channel();
+            let conversations = self.db.watch_prefix(vec![]);
+            let metas = self.metas.watch_prefix(vec![]);
+            // sled only tells inserts from removes, remember what existed to spot
+            // new ones; taken once watching so nothing created in between is missed
+            let mut known = self.get_conversations()?.into_iter().collect::<HashSet<_>>();
+
+            let meta_tx = tx.clone();
+            std::thread::spawn(move || {
+                for event in conversations {
+                    let event = match event {
+                        Event::Insert { key, .. } => match Uuid::from_slice(&key) {
+                            Ok(id) if known.insert(ConversationId(id)) => StorageEvent::Created(ConversationId(id)),
+                            Ok(id) => StorageEvent::Updated(ConversationId(id)),
+                            Err(_) => match title_owner(&key) {
+                                Some(id) => StorageEvent::TitleChanged(id),
+                                None => continue,
+                            },
+                        },
+                        Event::Remove { key } => match Uuid::from_slice(&key) {
+                            Ok(id) => {
+                                known.remove(&ConversationId(id));
+                                StorageEvent::Deleted(ConversationId(id))
+                            }
+                            Err(_) => continue,
+                        },
+                    };
+                    if tx.send(event).is_err() {
+                        break;
+                    }
+                }
+            });
+            std::thread::spawn(move || {
+                for event in metas {
+                    // meta goes away with its conversation, that's reported already
+                    if let Event::Insert { key, .. } = event {
+                        if let Ok(id) = Uuid::from_slice(&key) {
+                            if meta_tx.send(StorageEvent::MetaChanged(ConversationId(id))).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                }
+            });
+            Ok(rx)
+        }
+
+        fn get_updated(&self, id: ConversationId) -> Result<u64, String> {
+            match self.recent.get(&id.0).map_err(|e| e.to_string())? {
+                Some(at) => Ok(be_u64(at)),
+                None => self.get_tree(id).map(|tree| tree.updated_at()),
+            }
+        }
+
+        // the stored values as they are, compressed bodies count compressed
+        fn get_size(&self, id: ConversationId) -> Result<u64, String> {
+            let head = match self.db.get(&id.0).map_err(|e| e.to_string())? {
+                Some(head) => head,
+                None => return Err(NOT_FOUND_ERROR.to_string()),
+            };
+            let mut size = head.len() as u64;
+            for tree in [&self.messages, &self.links] {
+                for value in tree.scan_prefix(id.0.as_bytes()).values() {
+                    size += value.map_err(|e| e.to_string())?.len() as u64;
+                }
+            }
+            Ok(size)
+        }
+
+        fn list_conversations(
+            &self,
+            filter: &ConversationFilter,
+            after: Option<ConversationCursor>,
+            limit: usize,
+        ) -> Result<Page<ConversationEntry, ConversationCursor>, String> {
+            let mut entries = vec![];
+            // there are only ever a few pinned conversations, they are sorted here
+            if after.map(|after| after.pinned).unwrap_or(true) {
+                for id in self.pinned.iter().keys() {
+                    let id = ConversationId(Uuid::from_slice(&id.map_err(|e| e.to_string())?).map_err(|e| e.to_string())?);
+                    let meta = self.get_meta(id)?;
+                    if filter.matches(&meta) {
+                        let entry = ConversationEntry { id, meta, updated_at: self.get_updated(id)? };
+                        if after.map(|after| after.before(&entry.cursor())).unwrap_or(true) {
+                            entries.push(entry);
+                        }
+                    }
+                }
+                entries.sort_by(|a, b| (b.updated_at, b.id.0).cmp(&(a.updated_at, a.id.0)));
+            }
+
+            let keys = match after {
+                Some(after) if !after.pinned => self.updated.range(..Self::updated_key(after.updated_at, after.id)),
+                _ => self.updated.iter(),
+            };
+            // one more than asked for tells whether there is a next page
+            for key in keys.keys().rev() {
+                if entries.len() > limit.max(1) {
+                    break;
+                }
+                let key = key.map_err(|e| e.to_string())?;
+                let id = Uuid::from_slice(&key[8..]).map_err(|e| e.to_string())?;
+                let id = ConversationId(id);
+                let meta = self.get_meta(id)?;
+                if filter.matches(&meta) && !meta.pinned {
+                    let updated_at = be_u64(&key[..8]);
+                    entries.push(ConversationEntry { id, meta, updated_at });
+                }
+            }
+            Ok(paginate(entries, limit))
+        }
+
+        fn search(&self, query: &str) -> Result<Vec<SearchHit>, String> {
+            let query = search::tokenize(query);
+
+            // messages having a word starting with every term of the query
+            let mut candidates: Option<HashSet<(ConversationId, MessageId)>> = None;
+            for term in &query {
+                let mut found = HashSet::new();
+                for key in self.postings.scan_prefix(term.as_bytes()).keys() {
+                    let key = key.map_err(|e| e.to_string())?;
+                    let ids = &key[key.len() - 32..];
+                    let cid = Uuid::from_slice(&ids[..16]).map_err(|e| e.to_string())?;
+                    let mid = Uuid::from_slice(&ids[16..]).map_err(|e| e.to_string())?;
+                    found.insert((ConversationId(cid), MessageId(mid)));
+                }
+
+                candidates = Some(match candidates {
+                    Some(prev) => prev.intersection(&found).copied().collect(),
+                    None => found,
+                });
+            }
+
+            let mut by_conversation: HashMap<ConversationId, Vec<MessageId>> = HashMap::new();
+            for (cid, mid) in candidates.unwrap_or_default() {
+                by_conversation.entry(cid).or_default().push(mid);
+            }
+
+            let mut hits = vec![];
+            for (cid, mids) in by_conversation {
+                let tree = match self.get_tree(cid) {
+                    Ok(tree) => tree,
+                    Err(_) => continue,
+                };
+                hits.extend(mids.into_iter().filter_map(|mid| {
+                    let node = tree.get(mid)?;
+                    let hit = search::hit(cid, mid, &node.message.content.text(), &query)?;
+                    Some((node.message.created_at, hit))
+                }));
+            }
+            Ok(search::newest(hits))
+        }
+
         fn store_title(&self, id: ConversationId, msg: String) -> Result<(), String> {
             let title_key = format!("{}:title", id.0);
 
@@ -182,5 +1297,88 @@ pub mod disk {
                         .unwrap_or("".to_string())
                 })
         }
+
+        fn store_title_if_missing(&self, id: ConversationId, title: String) -> Result<String, String> {
+            let title_key = format!("{}:title", id.0);
+            match self.db.compare_and_swap(&title_key, None as Option<&[u8]>, Some(title.as_bytes())) {
+                Ok(Ok(())) => Ok(title),
+                Ok(Err(e)) => Ok(e
+                    .current
+                    .map(|v| String::from_utf8_lossy(v.as_ref()).into_owned())
+                    .unwrap_or_default()),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+
+        fn store_meta(&self, id: ConversationId, meta: ConversationMeta) -> Result<(), String> {
+            let data = serde_json::to_vec(&meta).map_err(|e| e.to_string())?;
+            let result = (&self.metas, &self.pinned).transaction(|(metas, pinned)| {
+                metas.insert(id.0.as_bytes(), data.clone())?;
+                if meta.pinned {
+                    pinned.insert(id.0.as_bytes(), &[])?;
+                } else {
+                    pinned.remove(id.0.as_bytes())?;
+                }
+                Ok(())
+            });
+            flatten(result)
+        }
+
+        fn get_meta(&self, id: ConversationId) -> Result<ConversationMeta, String> {
+            match self.metas.get(&id.0).map_err(|e| e.to_string())? {
+                Some(val) => serde_json::from_slice(&val).map_err(|e| e.to_string()),
+                None => Ok(ConversationMeta::default()),
+            }
+        }
+
+        fn get_prompts(&self) -> Result<Vec<Prompt>, String> {
+            self.prompts
+                .iter()
+                .values()
+                .map(|v| serde_json::from_slice(&v.map_err(|e| e.to_string())?).map_err(|e| e.to_string()))
+                .collect()
+        }
+
+        fn store_prompt(&self, prompt: Prompt) -> Result<(), String> {
+            let data = serde_json::to_vec(&prompt).map_err(|e| e.to_string())?;
+            self.prompts
+                .insert(prompt.id.0.as_bytes(), data)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        }
+
+        fn delete_prompt(&self, id: PromptId) -> Result<(), String> {
+            self.prompts
+                .remove(id.0.as_bytes())
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        }
+
+        fn get_prompt_versions(&self, id: PromptId) -> Result<Vec<PromptVersion>, String> {
+            self.prompt_versions
+                .scan_prefix(id.0.as_bytes())
+                .values()
+                .map(|v| serde_json::from_slice(&v.map_err(|e| e.to_string())?).map_err(|e| e.to_string()))
+                .collect()
+        }
+
+        fn store_prompt_version(&self, version: PromptVersion) -> Result<(), String> {
+            let data = serde_json::to_vec(&version).map_err(|e| e.to_string())?;
+            self.prompt_versions
+                .insert(Self::version_key(&version), data)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        }
+
+        fn store_prompt_version_if_missing(&self, version: PromptVersion) -> Result<bool, String> {
+            let data = serde_json::to_vec(&version).map_err(|e| e.to_string())?;
+            match self
+                .prompt_versions
+                .compare_and_swap(Self::version_key(&version), None as Option<&[u8]>, Some(data))
+            {
+                Ok(stored) => Ok(stored.is_ok()),
+                Err(e) => Err(e.to_string()),
+            }
+        }
     }
 }
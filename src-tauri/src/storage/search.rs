@@ -0,0 +1,120 @@
+use std::collections::HashSet;
+
+use common::{ConversationId, ConversationTree, MessageId, SearchHit};
+
+// characters of context kept on each side of the first match
+const SNIPPET_CONTEXT: usize = 60;
+pub const MAX_HITS: usize = 50;
+
+/// lowercased words of `text`, `_` counts as part of a word so identifiers stay whole
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+pub fn terms(text: &str) -> HashSet<String> {
+    tokenize(text).into_iter().collect()
+}
+
+// byte ranges of words in `text` starting with one of `query`, and which
+// words of `query` were seen at all
+fn find_terms(text: &str, query: &[String]) -> (Vec<(usize, usize)>, Vec<bool>) {
+    let mut found = vec![];
+    let mut seen = vec![false; query.len()];
+    let mut start = None;
+    for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+        let in_word = c.is_alphanumeric() || c == '_';
+        match (start, in_word) {
+            (None, true) => start = Some(i),
+            (Some(s), false) => {
+                let word = text[s..i].to_lowercase();
+                let mut longest = 0;
+                for (n, q) in query.iter().enumerate() {
+                    if word.starts_with(q.as_str()) {
+                        seen[n] = true;
+                        longest = longest.max(q.chars().count());
+                    }
+                }
+                if longest > 0 {
+                    // highlight only the matched prefix of the word
+                    let end = text[s..i]
+                        .char_indices()
+                        .nth(longest)
+                        .map(|(n, _)| s + n)
+                        .unwrap_or(i);
+                    found.push((s, end));
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    (found, seen)
+}
+
+/// Build a hit when every word of `query` prefixes some word of `text`. The
+/// snippet is cut around the first match and `highlights` index into it.
+pub fn hit(
+    conversation: ConversationId,
+    message: MessageId,
+    text: &str,
+    query: &[String],
+) -> Option<SearchHit> {
+    if query.is_empty() {
+        return None;
+    }
+
+    let (found, seen) = find_terms(text, query);
+    if seen.contains(&false) {
+        return None;
+    }
+
+    let first = found.first()?.0;
+    let begin = text[..first]
+        .char_indices()
+        .rev()
+        .nth(SNIPPET_CONTEXT - 1)
+        .map(|(i, _)| i)
+        .unwrap_or(0);
+    let end = text[first..]
+        .char_indices()
+        .nth(SNIPPET_CONTEXT * 2)
+        .map(|(i, _)| first + i)
+        .unwrap_or(text.len());
+
+    let prefix = if begin > 0 { "…" } else { "" };
+    let suffix = if end < text.len() { "…" } else { "" };
+    let snippet = format!("{}{}{}", prefix, &text[begin..end], suffix);
+    let shift = prefix.len();
+    let highlights = found
+        .into_iter()
+        .filter(|(s, e)| *s >= begin && *e <= end)
+        .map(|(s, e)| (s - begin + shift, e - begin + shift))
+        .collect();
+
+    Some(SearchHit {
+        conversation,
+        message,
+        snippet,
+        highlights,
+    })
+}
+
+/// scan every message of `tree`, used by backends without an index; each hit
+/// comes with the time its message was written
+pub fn scan(id: ConversationId, tree: &ConversationTree, query: &[String]) -> Vec<(u64, SearchHit)> {
+    tree.nodes
+        .iter()
+        .filter_map(|n| Some((n.message.created_at, hit(id, n.id(), &n.message.content.text(), query)?)))
+        .collect()
+}
+
+/// The `MAX_HITS` newest of `hits` by the time their message was written,
+/// newest first. Ids are no measure of that, imported messages get new ones.
+pub fn newest(mut hits: Vec<(u64, SearchHit)>) -> Vec<SearchHit> {
+    hits.sort_by(|(a, ha), (b, hb)| (b, hb.message.0).cmp(&(a, ha.message.0)));
+    hits.truncate(MAX_HITS);
+    hits.into_iter().map(|(_, hit)| hit).collect()
+}
@@ -0,0 +1,234 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use super::*;
+
+type Backend = Arc<dyn Storage + Send + Sync>;
+
+// how often a relay waiting for events checks whether its backend was replaced
+const RELAY_POLL: Duration = Duration::from_millis(200);
+
+/// Passes everything on to a backend that can be replaced while the app runs.
+/// Writes wait while `switch` copies one backend into another, so none of
+/// them is left behind, and subscribers keep getting the events of whichever
+/// backend is in use.
+pub struct SwitchableStorage {
+    backend: RwLock<Backend>,
+    // held for reading by every write, for writing while a switch copies
+    writes: RwLock<()>,
+    // bumped by every switch, tells the relays of the old backend to stop
+    generation: Arc<AtomicU64>,
+    subscribers: Mutex<Vec<Sender<StorageEvent>>>,
+}
+
+impl SwitchableStorage {
+    pub fn new(backend: Backend) -> Self {
+        SwitchableStorage {
+            backend: RwLock::new(backend),
+            writes: RwLock::new(()),
+            generation: Arc::new(AtomicU64::new(0)),
+            subscribers: Mutex::new(vec![]),
+        }
+    }
+
+    /// the backend in use
+    pub fn backend(&self) -> Backend {
+        self.backend.read().unwrap().clone()
+    }
+
+    /// Copy `from` into `to` with `migrate` and use `to` from then on.
+    /// Returns the number of conversations copied.
+    pub fn switch(&self, from: &dyn Storage, to: Backend, progress: impl FnMut(usize, usize)) -> Result<usize, String> {
+        let _paused = self.writes.write().unwrap();
+        let copied = migrate(from, to.as_ref(), progress)?;
+
+        // subscribed before anything changes, failing here keeps the old backend
+        let subscribers = self.subscribers.lock().unwrap();
+        let events = subscribers
+            .iter()
+            .map(|_| to.subscribe())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        *self.backend.write().unwrap() = to;
+        let current = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        for (tx, events) in subscribers.iter().zip(events) {
+            self.relay(current, events, tx.clone());
+        }
+        Ok(copied)
+    }
+
+    // Pass `events` on to `tx` while the backend of generation `current` is in
+    // use. The relay ends within `RELAY_POLL` of a switch, dropping `events`,
+    // rather than with the next event of a backend no longer written to.
+    fn relay(&self, current: u64, events: Receiver<StorageEvent>, tx: Sender<StorageEvent>) {
+        let generation = self.generation.clone();
+        std::thread::spawn(move || {
+            while generation.load(Ordering::SeqCst) == current {
+                match events.recv_timeout(RELAY_POLL) {
+                    Ok(event) => {
+                        if generation.load(Ordering::SeqCst) != current || tx.send(event).is_err() {
+                            break;
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        });
+    }
+}
+
+impl Storage for SwitchableStorage {
+    fn start_conversation(&self, ctx: Option<String>) -> Result<ConversationId, String> {
+        let _writing = self.writes.read().unwrap();
+        self.backend().start_conversation(ctx)
+    }
+
+    fn store_title(&self, id: ConversationId, msg: String) -> Result<(), String> {
+        let _writing = self.writes.read().unwrap();
+        self.backend().store_title(id, msg)
+    }
+
+    fn get_title(&self, id: ConversationId) -> Option<String> {
+        self.backend().get_title(id)
+    }
+
+    fn store_title_if_missing(&self, id: ConversationId, title: String) -> Result<String, String> {
+        let _writing = self.writes.read().unwrap();
+        self.backend().store_title_if_missing(id, title)
+    }
+
+    fn store_message(&self, id: ConversationId, msg: Message) -> Result<(), String> {
+        let _writing = self.writes.read().unwrap();
+        self.backend().store_message(id, msg)
+    }
+
+    fn store_conversation(&self, id: ConversationId, msgs: Vec<Message>) -> Result<(), String> {
+        let _writing = self.writes.read().unwrap();
+        self.backend().store_conversation(id, msgs)
+    }
+
+    fn get_conversation(&self, id: ConversationId) -> Result<Vec<Message>, String> {
+        self.backend().get_conversation(id)
+    }
+
+    fn store_tree(&self, id: ConversationId, tree: ConversationTree) -> Result<(), String> {
+        let _writing = self.writes.read().unwrap();
+        self.backend().store_tree(id, tree)
+    }
+
+    fn get_tree(&self, id: ConversationId) -> Result<ConversationTree, String> {
+        self.backend().get_tree(id)
+    }
+
+    fn get_versioned_tree(&self, id: ConversationId) -> Result<(u64, ConversationTree), String> {
+        self.backend().get_versioned_tree(id)
+    }
+
+    fn compare_and_store_tree(&self, id: ConversationId, version: u64, tree: ConversationTree) -> Result<u64, String> {
+        let _writing = self.writes.read().unwrap();
+        self.backend().compare_and_store_tree(id, version, tree)
+    }
+
+    fn compare_and_append(&self, id: ConversationId, version: u64, msg: Message) -> Result<u64, String> {
+        let _writing = self.writes.read().unwrap();
+        self.backend().compare_and_append(id, version, msg)
+    }
+
+    fn compare_and_set_current(&self, id: ConversationId, version: u64, current: Option<MessageId>) -> Result<u64, String> {
+        let _writing = self.writes.read().unwrap();
+        self.backend().compare_and_set_current(id, version, current)
+    }
+
+    fn get_conversations(&self) -> Result<Vec<ConversationId>, String> {
+        self.backend().get_conversations()
+    }
+
+    fn delete_conversation(&self, id: ConversationId) -> Result<(), String> {
+        let _writing = self.writes.read().unwrap();
+        self.backend().delete_conversation(id)
+    }
+
+    fn subscribe(&self) -> Result<Receiver<StorageEvent>, String> {
+        let (tx, rx) = channel();
+        let mut subscribers = self.subscribers.lock().unwrap();
+        // a switch bumps the generation holding `subscribers` too
+        let events = self.backend().subscribe()?;
+        self.relay(self.generation.load(Ordering::SeqCst), events, tx.clone());
+        subscribers.push(tx);
+        Ok(rx)
+    }
+
+    fn store_meta(&self, id: ConversationId, meta: ConversationMeta) -> Result<(), String> {
+        let _writing = self.writes.read().unwrap();
+        self.backend().store_meta(id, meta)
+    }
+
+    fn get_meta(&self, id: ConversationId) -> Result<ConversationMeta, String> {
+        self.backend().get_meta(id)
+    }
+
+    fn get_prompts(&self) -> Result<Vec<Prompt>, String> {
+        self.backend().get_prompts()
+    }
+
+    fn store_prompt(&self, prompt: Prompt) -> Result<(), String> {
+        let _writing = self.writes.read().unwrap();
+        self.backend().store_prompt(prompt)
+    }
+
+    fn delete_prompt(&self, id: PromptId) -> Result<(), String> {
+        let _writing = self.writes.read().unwrap();
+        self.backend().delete_prompt(id)
+    }
+
+    fn get_prompt_versions(&self, id: PromptId) -> Result<Vec<PromptVersion>, String> {
+        self.backend().get_prompt_versions(id)
+    }
+
+    fn store_prompt_version(&self, version: PromptVersion) -> Result<(), String> {
+        let _writing = self.writes.read().unwrap();
+        self.backend().store_prompt_version(version)
+    }
+
+    fn store_prompt_version_if_missing(&self, version: PromptVersion) -> Result<bool, String> {
+        let _writing = self.writes.read().unwrap();
+        self.backend().store_prompt_version_if_missing(version)
+    }
+
+    fn get_updated(&self, id: ConversationId) -> Result<u64, String> {
+        self.backend().get_updated(id)
+    }
+
+    fn get_size(&self, id: ConversationId) -> Result<u64, String> {
+        self.backend().get_size(id)
+    }
+
+    fn filter_conversations(&self, filter: &ConversationFilter) -> Result<Vec<ConversationEntry>, String> {
+        self.backend().filter_conversations(filter)
+    }
+
+    fn list_conversations(
+        &self,
+        filter: &ConversationFilter,
+        after: Option<ConversationCursor>,
+        limit: usize,
+    ) -> Result<Page<ConversationEntry, ConversationCursor>, String> {
+        self.backend().list_conversations(filter, after, limit)
+    }
+
+    fn get_messages(
+        &self,
+        id: ConversationId,
+        before: Option<MessageId>,
+        limit: usize,
+    ) -> Result<Page<PathNode, MessageId>, String> {
+        self.backend().get_messages(id, before, limit)
+    }
+
+    fn search(&self, query: &str) -> Result<Vec<SearchHit>, String> {
+        self.backend().search(query)
+    }
+}
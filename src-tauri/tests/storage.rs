@@ -0,0 +1,258 @@
+use chatgpt_backend::storage::conformance;
+
+#[cfg(feature = "local-storage")]
+#[test]
+fn local_storage_conforms() {
+    use chatgpt_backend::storage::local::KVStorage;
+
+    conformance::run(KVStorage::new).unwrap();
+}
+
+#[cfg(feature = "persist-storage")]
+#[test]
+fn disk_storage_conforms() {
+    use chatgpt_backend::storage::disk::KVStorage;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let root = std::env::temp_dir().join(format!("storage-conformance-{}", std::process::id()));
+    let result = conformance::run(|| {
+        let path = root.join(COUNT.fetch_add(1, Ordering::SeqCst).to_string());
+        KVStorage::new(path).unwrap()
+    });
+    std::fs::remove_dir_all(&root).ok();
+    result.unwrap();
+}
+
+#[cfg(feature = "local-storage")]
+#[test]
+fn encrypted_storage_conforms() {
+    use chatgpt_backend::storage::encrypted::{Cipher, EncryptedStorage};
+    use chatgpt_backend::storage::local::KVStorage;
+    use std::sync::Arc;
+
+    // deriving a key is slow on purpose, one is enough
+    let (cipher, _) = Cipher::create("conformance").unwrap();
+    conformance::run(|| EncryptedStorage::new(Arc::new(KVStorage::new()), cipher.clone())).unwrap();
+}
+
+#[cfg(feature = "local-storage")]
+#[test]
+fn switchable_storage_conforms() {
+    use chatgpt_backend::storage::local::KVStorage;
+    use chatgpt_backend::storage::switch::SwitchableStorage;
+    use std::sync::Arc;
+
+    // checked on the backend it was switched to, with its events relayed
+    conformance::run(|| {
+        let store = SwitchableStorage::new(Arc::new(KVStorage::new()));
+        store.switch(store.backend().as_ref(), Arc::new(KVStorage::new()), |_, _| {}).unwrap();
+        store
+    })
+    .unwrap();
+}
+
+#[cfg(all(feature = "local-storage", feature = "persist-storage"))]
+#[test]
+fn writes_during_a_switch_reach_the_new_backend() {
+    use chatgpt_backend::storage::switch::SwitchableStorage;
+    use chatgpt_backend::storage::{disk, local, Storage};
+    use common::Message;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{mpsc, Arc};
+
+    let store = Arc::new(SwitchableStorage::new(Arc::new(local::KVStorage::new())));
+    let ids = (0..50)
+        .map(|_| store.start_conversation(None).unwrap())
+        .collect::<Vec<_>>();
+    let dir = std::env::temp_dir().join(format!("storage-switch-{}", std::process::id()));
+    let to = Arc::new(disk::KVStorage::new(&dir).unwrap());
+
+    // writes until the switch is over, telling when it has begun
+    let switched = Arc::new(AtomicBool::new(false));
+    let (writing, begun) = mpsc::channel();
+    let writer = {
+        let (store, ids, switched) = (store.clone(), ids.clone(), switched.clone());
+        std::thread::spawn(move || {
+            let mut rounds = 0;
+            let mut started = vec![];
+            while !switched.load(Ordering::SeqCst) {
+                for &id in &ids {
+                    store.store_message(id, Message::new_user(rounds.to_string())).unwrap();
+                }
+                started.push(store.start_conversation(None).unwrap());
+                rounds += 1;
+                writing.send(()).ok();
+            }
+            (rounds, started)
+        })
+    };
+    begun.recv().unwrap();
+    store.switch(store.backend().as_ref(), to.clone(), |_, _| {}).unwrap();
+    switched.store(true, Ordering::SeqCst);
+    let (rounds, started) = writer.join().unwrap();
+
+    for id in ids {
+        // the context prompt and every message written
+        assert_eq!(to.get_conversation(id).unwrap().len(), rounds + 1);
+    }
+    for id in started {
+        assert!(to.get_tree(id).is_ok());
+    }
+    drop(to);
+    drop(store);
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[cfg(feature = "local-storage")]
+#[test]
+fn interrupted_rekey_is_finished_by_the_next() {
+    use chatgpt_backend::storage::encrypted::{Cipher, EncryptedStorage};
+    use chatgpt_backend::storage::{local::KVStorage, Storage};
+    use common::{Content, Message};
+    use std::sync::Arc;
+
+    let base = Arc::new(KVStorage::new());
+    let (old, _) = Cipher::create("old").unwrap();
+    let (new, _) = Cipher::create("new").unwrap();
+    let vault = EncryptedStorage::new(base.clone(), old.clone());
+    let a = vault.start_conversation(None).unwrap();
+    vault.store_message(a, Message::new_user("first")).unwrap();
+    let b = vault.start_conversation(None).unwrap();
+    vault.store_message(b, Message::new_user("second")).unwrap();
+
+    // only `a` was sealed with the new key when the app stopped
+    let tree = vault.get_tree(a).unwrap();
+    EncryptedStorage::new(base.clone(), new.clone()).store_tree(a, tree).unwrap();
+    assert!(vault.get_tree(a).is_err());
+
+    vault.rekey(new.clone()).unwrap();
+    let reopened = EncryptedStorage::new(base, new);
+    assert_eq!(reopened.get_conversation(a).unwrap()[1].content, Content::Text("first".into()));
+    assert_eq!(reopened.get_conversation(b).unwrap()[1].content, Content::Text("second".into()));
+}
+
+#[cfg(feature = "local-storage")]
+#[test]
+fn rekey_keeps_messages_written_meanwhile() {
+    use chatgpt_backend::storage::encrypted::{Cipher, EncryptedStorage};
+    use chatgpt_backend::storage::{local::KVStorage, Storage};
+    use common::Message;
+    use std::sync::Arc;
+
+    let base = Arc::new(KVStorage::new());
+    let (old, _) = Cipher::create("old").unwrap();
+    let (new, _) = Cipher::create("new").unwrap();
+    let vault = Arc::new(EncryptedStorage::new(base.clone(), old));
+    let ids = (0..20)
+        .map(|_| {
+            let id = vault.start_conversation(None).unwrap();
+            vault.store_message(id, Message::new_user("hello")).unwrap();
+            id
+        })
+        .collect::<Vec<_>>();
+
+    let writer = {
+        let (vault, ids) = (vault.clone(), ids.clone());
+        std::thread::spawn(move || {
+            for n in 0..50 {
+                for &id in &ids {
+                    vault.store_message(id, Message::new_user(n.to_string())).unwrap();
+                }
+            }
+        })
+    };
+    vault.rekey(new.clone()).unwrap();
+    writer.join().unwrap();
+
+    let reopened = EncryptedStorage::new(base, new);
+    for id in ids {
+        // the context prompt, the greeting and every message written
+        assert_eq!(reopened.get_conversation(id).unwrap().len(), 52);
+    }
+}
+
+#[cfg(feature = "local-storage")]
+#[test]
+fn rekey_keeps_versions_of_deleted_prompts() {
+    use chatgpt_backend::storage::encrypted::{Cipher, EncryptedStorage};
+    use chatgpt_backend::storage::{local::KVStorage, Storage};
+    use common::{ConversationMeta, Prompt, PromptVersion};
+    use std::sync::Arc;
+
+    let base = Arc::new(KVStorage::new());
+    let (old, _) = Cipher::create("old").unwrap();
+    let (new, _) = Cipher::create("new").unwrap();
+    let vault = EncryptedStorage::new(base.clone(), old);
+    let prompt = Prompt::new("Translator", "translate everything to english");
+    vault.store_prompt_version(PromptVersion::new(&prompt, 10)).unwrap();
+    vault.store_prompt(prompt.clone()).unwrap();
+    let id = vault.start_conversation(None).unwrap();
+    let meta = ConversationMeta {
+        prompt: Some(prompt.reference()),
+        ..Default::default()
+    };
+    vault.store_meta(id, meta).unwrap();
+    vault.delete_prompt(prompt.id).unwrap();
+
+    vault.rekey(new.clone()).unwrap();
+    let reopened = EncryptedStorage::new(base, new);
+    let versions = reopened.get_prompt_versions(prompt.id).unwrap();
+    assert_eq!(versions.len(), 1);
+    assert_eq!(versions[0].content, prompt.content);
+}
+
+#[cfg(feature = "persist-storage")]
+#[test]
+fn disk_storage_upgrades_old_conversations() {
+    use chatgpt_backend::storage::disk::KVStorage;
+    use chatgpt_backend::storage::Storage;
+    use common::{ConversationId, MessageId};
+    use uuid::Uuid;
+
+    let path = std::env::temp_dir().join(format!("storage-upgrade-{}", std::process::id()));
+    let list = ConversationId(Uuid::new_v4());
+    let tree = ConversationId(Uuid::new_v4());
+    let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+    {
+        // a plain list from before branching, and a tree keeping ids on its nodes
+        let db = sled::open(&path).unwrap();
+        let old_list = r#"[{"role":"system","content":"be brief"},{"role":"user","content":"hi"}]"#;
+        let old_tree = format!(
+            r#"{{"nodes":[{{"id":"{a}","parent":null,"message":{{"role":"user","content":"a"}}}},
+                {{"id":"{b}","parent":"{a}","message":{{"role":"assistant","content":"b"}}}}],"current":"{b}"}}"#,
+            a = a,
+            b = b
+        );
+        db.insert(list.0.as_bytes(), old_list.as_bytes()).unwrap();
+        db.insert(tree.0.as_bytes(), old_tree.as_bytes()).unwrap();
+        db.flush().unwrap();
+    }
+
+    let read = || {
+        // sled lets go of its lock from a background thread, give it a moment
+        let store = (0..100)
+            .find_map(|_| {
+                let store = KVStorage::new(&path).ok();
+                if store.is_none() {
+                    std::thread::sleep(std::time::Duration::from_millis(20));
+                }
+                store
+            })
+            .unwrap();
+        (store.get_tree(list).unwrap(), store.get_tree(tree).unwrap())
+    };
+    let (first_list, first_tree) = read();
+    let (again_list, again_tree) = read();
+    std::fs::remove_dir_all(&path).ok();
+
+    // ids are given once and kept, times are never left at 0
+    assert_eq!(first_list, again_list);
+    assert_eq!(first_tree, again_tree);
+    assert_eq!(first_list.messages().len(), 2);
+    assert!(first_list.nodes.iter().all(|n| n.message.created_at > 0));
+    let ids = first_tree.nodes.iter().map(|n| n.id()).collect::<Vec<_>>();
+    assert_eq!(ids, vec![MessageId(a), MessageId(b)]);
+    assert_eq!(first_tree.current, Some(MessageId(b)));
+    assert_eq!(first_tree.nodes[1].parent, Some(MessageId(a)));
+}
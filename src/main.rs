@@ -1,5 +1,6 @@
 #![allow(non_snake_case)]
 
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
 use futures::channel::oneshot;
@@ -37,6 +38,10 @@ enum AppRoutes {
     GenImage,
     #[to("/voice")]
     Voice,
+    #[to("/security")]
+    Security,
+    #[to("/backup")]
+    Backup,
     #[not_found]
     NotFound,
 }
@@ -55,11 +60,17 @@ fn About<G: Html>(ctx: Scope) -> View<G> {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 struct Conversation<'a> {
     id: &'a Signal<Option<ConversationId>>,
     title: &'a Signal<String>,
-    chats: &'a Signal<Vec<Message>>,
+    // only the loaded part of the active branch
+    tree: &'a Signal<ConversationTree>,
+    siblings: &'a Signal<HashMap<MessageId, Vec<MessageId>>>,
+    // oldest loaded message while older ones remain
+    older: &'a Signal<Option<MessageId>>,
+    // set each time a turn, an edit or a branch switch has been stored
+    stored: &'a Signal<()>,
 }
 
 impl<'a> Conversation<'a> {
@@ -67,7 +78,10 @@ impl<'a> Conversation<'a> {
         Conversation {
             id: create_signal(ctx, None),
             title: create_signal(ctx, "".to_string()),
-            chats: create_signal(ctx, Vec::default()),
+            tree: create_signal(ctx, ConversationTree::default()),
+            siblings: create_signal(ctx, HashMap::new()),
+            older: create_signal(ctx, None),
+            stored: create_signal(ctx, ()),
         }
     }
 }
@@ -85,11 +99,31 @@ struct BubbleProps {
     actor: String,
     at_start: bool,
     content: String,
+    // urls of attached images, shown as thumbnails below the text
+    images: Vec<String>,
+}
+
+#[component(inline_props)]
+fn Thumbnails<G: Html>(ctx: Scope, images: Vec<String>) -> View<G> {
+    let images = create_signal(ctx, images);
+    view! { ctx,
+        div(class="flex flex-row flex-wrap gap-1 mt-1") {
+            Indexed(
+                iterable=images,
+                view=|ctx, url| view! { ctx,
+                    a(href=url.clone(), target="_blank") {
+                        img(class="h-24 rounded object-cover", src=url)
+                    }
+                },
+            )
+        }
+    }
 }
 
 #[component]
 fn Bubble<G: Html>(ctx: Scope, props: BubbleProps) -> View<G> {
     let html_content = markdown_to_html(&props.content);
+    let images = props.images;
     if props.at_start {
         view! {
             ctx,
@@ -97,8 +131,10 @@ fn Bubble<G: Html>(ctx: Scope, props: BubbleProps) -> View<G> {
                 div(class="chat-image avatar") {
                     label(class="btn btn-circle rounded-full bg-slate-200") { (props.actor) }
                 }
-                div(class=("chat-bubble chat-bubble-secondary"),
-                dangerously_set_inner_html=&html_content)
+                div(class="chat-bubble chat-bubble-secondary") {
+                    div(dangerously_set_inner_html=&html_content)
+                    Thumbnails(images=images)
+                }
             }
         }
     } else {
@@ -108,40 +144,132 @@ fn Bubble<G: Html>(ctx: Scope, props: BubbleProps) -> View<G> {
                 div(class="chat-image avatar") {
                     label(class="btn btn-circle rounded-full bg-slate-200") { (props.actor) }
                 }
-                div(class="chat-bubble chat-bubble-success",
-                dangerously_set_inner_html=&html_content)
+                div(class="chat-bubble chat-bubble-success") {
+                    div(dangerously_set_inner_html=&html_content)
+                    Thumbnails(images=images)
+                }
             }
         }
     }
 }
 
-async fn start_conversation(system_hint: String) {
-    if let Err(e) = openai_start_conversation(Some(system_hint))
-        .await
-        .map_err(|e| format!("{:?}", e))
-        .and_then(|id| {
-            wasm_log!("created: {:?}", id);
-            serde_wasm_bindgen::from_value::<ConversationId>(id).map_err(|e| e.to_string())
-        })
-        .and_then(|cid| {
+async fn start_conversation(system_hint: String, prompt: Option<PromptRef>) {
+    match call(ipc::StartConversation { hint: Some(system_hint), prompt }).await {
+        Ok(cid) => {
+            wasm_log!("created: {:?}", cid);
             navigate(&format!("/chats/{}", cid.0));
-            Ok(())
-        })
-    {
-        wasm_log!("{}", e);
+        }
+        Err(e) => wasm_log!("{}", e),
+    }
+}
+
+async fn fetch_conversations(
+    filter: &ConversationFilter,
+    after: Option<ConversationCursor>,
+) -> Option<Page<ConversationSummary, ConversationCursor>> {
+    let filter = filter.clone();
+    match call(ipc::ListConversations { filter, after, limit: CONVERSATION_PAGE_SIZE }).await {
+        Ok(page) => Some(page),
+        Err(e) => {
+            wasm_log!("{}", e);
+            None
+        }
+    }
+}
+
+// the first page of conversations matching `filter`
+async fn load_conversations(
+    conversations: &Signal<Vec<ConversationSummary>>,
+    filter: &Signal<ConversationFilter>,
+    next: &Signal<Option<ConversationCursor>>,
+) -> bool {
+    match fetch_conversations(filter.get_untracked().as_ref(), None).await {
+        Some(page) => {
+            wasm_log!("conversations loaded: {}", page.items.len());
+            conversations.set(page.items);
+            next.set(page.next);
+            true
+        }
+        None => false,
+    }
+}
+
+async fn load_more_conversations(
+    conversations: &Signal<Vec<ConversationSummary>>,
+    filter: &Signal<ConversationFilter>,
+    next: &Signal<Option<ConversationCursor>>,
+) {
+    let after = match *next.get_untracked() {
+        Some(after) => after,
+        None => return,
+    };
+
+    if let Some(page) = fetch_conversations(filter.get_untracked().as_ref(), Some(after)).await {
+        // the filter may have changed while this page was on its way
+        if *next.get_untracked() == Some(after) {
+            conversations.modify().extend(page.items);
+            next.set(page.next);
+        }
+    }
+}
+
+// Put `summary` where the list orders it, in place of the entry it had. It is
+// dropped when it no longer matches `filter`, and left to scrolling when it
+// goes past the pages loaded so far.
+fn place_conversation(
+    conversations: &Signal<Vec<ConversationSummary>>,
+    filter: &Signal<ConversationFilter>,
+    next: &Signal<Option<ConversationCursor>>,
+    summary: ConversationSummary,
+) {
+    let mut list = conversations.modify();
+    list.retain(|e| e.id != summary.id);
+    if !filter.get_untracked().matches(&summary.meta) {
+        return;
+    }
+    let cursor = summary.cursor();
+    match list.iter().position(|e| cursor.before(&e.cursor())) {
+        Some(at) => list.insert(at, summary),
+        None if next.get_untracked().is_none() => list.push(summary),
+        None => {}
+    }
+}
+
+async fn fetch_title(id: ConversationId) -> Option<String> {
+    // conversations without a title yet are not worth logging
+    call(ipc::GetTitle { id }).await.ok().filter(|t| !t.is_empty())
+}
+
+// the prompt version a conversation was started with, if any and still known
+async fn fetch_started_with(id: ConversationId) -> Option<PromptVersion> {
+    let prompt = call(ipc::GetMeta { id }).await.ok()?.prompt?;
+    match call(ipc::GetPromptVersions { id: prompt.id }).await {
+        Ok(versions) => versions.into_iter().find(|v| v.version == prompt.version),
+        Err(e) => {
+            wasm_log!("{}", e);
+            None
+        }
     }
 }
 
 #[derive(Prop)]
 struct ChatAppProps {
     id: String,
+    // message to scroll to once the conversation is loaded
+    focus: String,
 }
 
 #[component]
 fn ChatApp<G: Html>(ctx: Scope, sub: ChatAppProps) -> View<G> {
-    let conversations: &Signal<Vec<ConversationId>> = create_signal(ctx, vec![]);
+    let conversations: &Signal<Vec<ConversationSummary>> = create_signal(ctx, vec![]);
     provide_context_ref(ctx, conversations);
 
+    let filter = create_signal(ctx, ConversationFilter::default());
+    provide_context_ref(ctx, filter);
+
+    let next_page: &Signal<Option<ConversationCursor>> = create_signal(ctx, None);
+    provide_context_ref(ctx, next_page);
+
     let conversations_loaded = create_signal(ctx, false);
     provide_context_ref(ctx, conversations_loaded);
 
@@ -152,6 +280,8 @@ fn ChatApp<G: Html>(ctx: Scope, sub: ChatAppProps) -> View<G> {
     provide_context_ref(ctx, request_new_conversation);
 
     let system_hint = create_signal(ctx, "".to_string());
+    // the library prompt the system prompt came from
+    let started_from = create_signal(ctx, None::<PromptRef>);
 
     create_effect(ctx, move || {
         request_new_conversation.track();
@@ -165,60 +295,483 @@ fn ChatApp<G: Html>(ctx: Scope, sub: ChatAppProps) -> View<G> {
         }
 
         sycamore::futures::spawn_local_scoped(ctx, async move {
-            start_conversation(system_hint.get_untracked().as_ref().clone()).await;
+            start_conversation(system_hint.get_untracked().as_ref().clone(), *started_from.get_untracked()).await;
         });
     });
 
     sycamore::futures::spawn_local_scoped(ctx, async move {
-        match openai_get_conversations().await {
-            Ok(list) => match serde_wasm_bindgen::from_value::<Vec<ConversationId>>(list) {
-                Ok(list) => {
-                    wasm_log!("conversations loaded: {:?}", list);
-                    conversations.set(list);
-                    conversations_loaded.set(true);
+        if load_conversations(conversations, filter, next_page).await {
+            conversations_loaded.set(true);
+        }
+    });
+
+    let refreshing = create_signal(ctx, false);
+    let stale = create_signal(ctx, false);
+    // changes from any window, the backend or an import
+    storage_listener(ctx, move |event| match event {
+        StorageEvent::Deleted(id) => {
+            conversations.modify().retain(|e| e.id != id);
+        }
+        StorageEvent::TitleChanged(id) => {
+            sycamore::futures::spawn_local_scoped(ctx, async move {
+                match call(ipc::GetSummary { id }).await {
+                    Ok(summary) => {
+                        if let Some(entry) = conversations.modify().iter_mut().find(|e| e.id == id) {
+                            *entry = summary;
+                        }
+                    }
+                    Err(e) => wasm_log!("{}", e),
                 }
-                Err(e) => {
-                    wasm_log!("{:?}", e);
+            });
+        }
+        // only that entry changed, the pages loaded by scrolling stay
+        StorageEvent::Updated(id) | StorageEvent::MetaChanged(id) => {
+            sycamore::futures::spawn_local_scoped(ctx, async move {
+                match call(ipc::GetSummary { id }).await {
+                    Ok(summary) => place_conversation(conversations, filter, next_page, summary),
+                    Err(e) => wasm_log!("{}", e),
                 }
-            },
-            Err(e) => {
-                wasm_log!("{:?}", e);
+            });
+        }
+        StorageEvent::Created(_) => {
+            // an import sends a burst of these, reload once more after the
+            // current reload instead of once per event
+            if *refreshing.get_untracked() {
+                stale.set(true);
+                return;
             }
-        };
+            refreshing.set(true);
+            sycamore::futures::spawn_local_scoped(ctx, async move {
+                loop {
+                    load_conversations(conversations, filter, next_page).await;
+                    if !*stale.get_untracked() {
+                        break;
+                    }
+                    stale.set(false);
+                }
+                refreshing.set(false);
+            });
+        }
     });
 
     if sub.id.is_empty() {
         view! {ctx,
             div(class="flex-1 flex flex-row") {
                 ChatList {}
-                NewChatGuide(prompt=system_hint, request_new=request_new_conversation)
+                NewChatGuide(prompt=system_hint, started_from=started_from, request_new=request_new_conversation)
             }
         }
     } else {
         view! {ctx,
             div(class="flex-1 flex flex-row") {
                 ChatList {}
-                ChatCompletion(id=sub.id.clone())
+                ChatCompletion(id=sub.id.clone(), focus=sub.focus.clone())
             }
         }
     }
 }
 
+async fn load_prompts(prompts: &Signal<Vec<Prompt>>) {
+    match call(ipc::ListPrompts {}).await {
+        Ok(list) => prompts.set(list),
+        Err(e) => wasm_log!("{}", e),
+    }
+}
+
+// store `prompt` and reload the library, which keeps it sorted
+async fn save_prompt(prompt: Prompt, prompts: &Signal<Vec<Prompt>>) -> Result<(), String> {
+    call(ipc::SavePrompt { prompt }).await?;
+    load_prompts(prompts).await;
+    Ok(())
+}
+
 #[component(inline_props)]
-fn PromptItem<'a, G: Html>(ctx: Scope<'a>, prompt: Prompt, used: &'a Signal<String>) -> View<G> {
-    let c = prompt.content.clone();
+fn PromptItem<'a, G: Html>(
+    ctx: Scope<'a>,
+    prompt: Prompt,
+    used: &'a Signal<Option<Prompt>>,
+    prompts: &'a Signal<Vec<Prompt>>,
+    editing: &'a Signal<Option<Prompt>>,
+    history: &'a Signal<Option<Prompt>>,
+    // the row picked with the arrow keys
+    active: &'a ReadSignal<Option<PromptId>>,
+) -> View<G> {
+    let prompt = create_ref(ctx, prompt);
+    let id = prompt.id;
+
+    let on_favorite = move |_: web_sys::Event| {
+        let mut prompt = prompt.clone();
+        prompt.favorite = !prompt.favorite;
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            if let Err(e) = save_prompt(prompt, prompts).await {
+                wasm_log!("{}", e);
+            }
+        });
+    };
+
+    let on_delete = move |_: web_sys::Event| {
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            match call(ipc::DeletePrompt { id }).await {
+                Ok(()) => load_prompts(prompts).await,
+                Err(e) => wasm_log!("{}", e),
+            }
+        });
+    };
 
     view! {ctx,
-        tr(class="w-full") {
+        tr(id=format!("prompt-{}", id.0), class=if *active.get() == Some(id) { "w-full active" } else { "w-full" }) {
             td { button(class="btn btn-info btn-outline btn-sm", on:click=move |_| {
-                used.set(c.clone());
+                used.set(Some(prompt.clone()));
             }) { "use" } }
-            td { (prompt.act) }
+            td {
+                button(class="btn btn-ghost btn-xs", title="favourite", on:click=on_favorite) {
+                    (if prompt.favorite { "★" } else { "☆" })
+                }
+                (prompt.act.clone())
+                (match &prompt.category {
+                    Some(category) => {
+                        let category = category.clone();
+                        view! { ctx, span(class="badge badge-outline badge-xs ml-1") { (category) } }
+                    }
+                    None => view! { ctx, },
+                })
+            }
             td(class="w-full h-full block") {
                 p(class="text-ellipsis overflow-hidden break-all", style="width: 400px") {
-                    (prompt.content)
+                    (prompt.content.clone())
                 }
             }
+            td {
+                button(class="btn btn-ghost btn-xs", on:click=move |_| editing.set(Some(prompt.clone()))) { "edit" }
+                button(class="btn btn-ghost btn-xs", on:click=move |_| history.set(Some(prompt.clone()))) { "history" }
+                button(class="btn btn-ghost btn-xs text-error", on:click=on_delete) { "delete" }
+            }
+        }
+    }
+}
+
+// form for a new prompt or changes to one of the library
+#[component(inline_props)]
+fn PromptEditor<'a, G: Html>(
+    ctx: Scope<'a>,
+    prompt: Prompt,
+    prompts: &'a Signal<Vec<Prompt>>,
+    editing: &'a Signal<Option<Prompt>>,
+) -> View<G> {
+    let act = create_signal(ctx, prompt.act.clone());
+    let category = create_signal(ctx, prompt.category.clone().unwrap_or_default());
+    let content = create_signal(ctx, prompt.content.clone());
+    let error = create_signal(ctx, "".to_string());
+    let prompt = create_ref(ctx, prompt);
+
+    let on_save = move |_: web_sys::Event| {
+        let mut prompt = prompt.clone();
+        prompt.act = act.get_untracked().to_string();
+        prompt.category = Some(category.get_untracked().to_string());
+        prompt.content = content.get_untracked().to_string();
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            match save_prompt(prompt, prompts).await {
+                Ok(()) => editing.set(None),
+                Err(e) => error.set(e),
+            }
+        });
+    };
+
+    view! {ctx,
+        div(class="flex flex-col gap-1 mb-2") {
+            div(class="flex flex-row gap-1") {
+                input(class="input input-bordered input-sm flex-1", placeholder="name", bind:value=act)
+                input(class="input input-bordered input-sm w-40", placeholder="category", bind:value=category)
+            }
+            textarea(class="textarea textarea-bordered w-full", rows=4, placeholder="the system prompt, {{name|default|description}} asks for a value when used", bind:value=content)
+            div(class="flex flex-row gap-1 items-center") {
+                button(class="btn btn-outline btn-info btn-xs", on:click=on_save) { "save" }
+                button(class="btn btn-ghost btn-xs", on:click=move |_| editing.set(None)) { "cancel" }
+                label(class="text-xs text-error") { (error.get()) }
+            }
+        }
+    }
+}
+
+// what changed from `old` to `new`, word by word
+fn diff_view<'a, G: Html>(ctx: Scope<'a>, old: &str, new: &str) -> View<G> {
+    View::new_fragment(
+        diff::diff(old, new)
+            .into_iter()
+            .map(|change| match change {
+                diff::Change::Same(text) => view! { ctx, span { (text) } },
+                diff::Change::Added(text) => view! { ctx, span(class="bg-success text-success-content") { (text) } },
+                diff::Change::Removed(text) => view! { ctx, span(class="line-through text-error") { (text) } },
+            })
+            .collect(),
+    )
+}
+
+async fn load_versions(id: PromptId, versions: &Signal<Vec<PromptVersion>>) {
+    match call(ipc::GetPromptVersions { id }).await {
+        Ok(list) => versions.set(list),
+        Err(e) => wasm_log!("{}", e),
+    }
+}
+
+// the versions of a library prompt with what each one changed, and the
+// answers two of them give to the same message side by side
+#[component(inline_props)]
+fn PromptHistory<'a, G: Html>(
+    ctx: Scope<'a>,
+    prompt: Prompt,
+    prompts: &'a Signal<Vec<Prompt>>,
+    history: &'a Signal<Option<Prompt>>,
+) -> View<G> {
+    let id = prompt.id;
+    let versions = create_signal(ctx, Vec::<PromptVersion>::new());
+    // numbers of the versions compared as A and B
+    let left = create_signal(ctx, None::<u32>);
+    let right = create_signal(ctx, None::<u32>);
+    let input = create_signal(ctx, "".to_string());
+    // what the variables of A and B are filled in with
+    let values = create_signal(ctx, HashMap::<String, String>::new());
+    // answers of the last comparison with the version numbers asked
+    let answers = create_signal(ctx, Vec::<(u32, Message)>::new());
+    let running = create_signal(ctx, false);
+    let status = create_signal(ctx, "".to_string());
+
+    sycamore::futures::spawn_local_scoped(ctx, async move {
+        load_versions(id, versions).await;
+    });
+
+    // the latest version against the one before it
+    create_effect(ctx, move || {
+        let list = versions.get();
+        let mut newest = list.iter().rev().map(|v| v.version);
+        let latest = newest.next();
+        right.set(latest);
+        left.set(newest.next().or(latest));
+    });
+
+    // the variables of A and B together, each asked for once
+    let variables = create_memo(ctx, || {
+        let list = versions.get();
+        let mut all = Vec::<template::Variable>::new();
+        for number in [*left.get(), *right.get()].into_iter().flatten() {
+            let content = match list.iter().find(|v| v.version == number) {
+                Some(version) => &version.content,
+                None => continue,
+            };
+            for var in template::Template::parse(content).variables() {
+                if !all.iter().any(|known| known.name == var.name) {
+                    all.push(var.clone());
+                }
+            }
+        }
+        all
+    });
+
+    // newest first, each with the version before it
+    let rows = create_memo(ctx, || {
+        let list = versions.get();
+        list.iter()
+            .enumerate()
+            .map(|(i, v)| (v.clone(), i.checked_sub(1).map(|p| list[p].clone())))
+            .rev()
+            .collect::<Vec<_>>()
+    });
+
+    // saved as a new version, the history stays as it is
+    let restore = move |version: PromptVersion| {
+        let current = prompts.get_untracked().iter().find(|p| p.id == id).cloned();
+        let mut prompt = match current {
+            Some(prompt) => prompt,
+            None => return,
+        };
+        prompt.act = version.act;
+        prompt.content = version.content;
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            match save_prompt(prompt, prompts).await {
+                Ok(()) => load_versions(id, versions).await,
+                Err(e) => status.set(e),
+            }
+        });
+    };
+
+    let on_compare = move |_: web_sys::Event| {
+        let (a, b) = match (*left.get_untracked(), *right.get_untracked()) {
+            (Some(a), Some(b)) => (PromptRef { id, version: a }, PromptRef { id, version: b }),
+            _ => return,
+        };
+        let text = input.get_untracked().trim().to_string();
+        if text.is_empty() {
+            return;
+        }
+        let values = values.get_untracked().as_ref().clone();
+        let missing = variables
+            .get_untracked()
+            .iter()
+            .filter(|var| var.default.is_none() && values.get(&var.name).filter(|v| !v.trim().is_empty()).is_none())
+            .map(|var| var.name.clone())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            status.set(format!("fill in {} first", missing.join(", ")));
+            return;
+        }
+        running.set(true);
+        answers.set(vec![]);
+        status.set("waiting for both answers...".to_string());
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            match call(ipc::ComparePrompts { a, b, input: text, values }).await {
+                Ok((first, second)) => {
+                    answers.set(vec![(a.version, first), (b.version, second)]);
+                    status.set("".to_string());
+                }
+                Err(e) => status.set(e),
+            }
+            running.set(false);
+        });
+    };
+
+    view! {ctx,
+        div(class="flex flex-col gap-1 mb-2 border rounded-box p-2") {
+            div(class="flex flex-row items-center") {
+                h3(class="font-bold flex-1") { (format!("history of {}", prompt.act)) }
+                button(class="btn btn-ghost btn-xs", on:click=move |_| history.set(None)) { "close" }
+            }
+            ul(class="overflow-y-auto", style="max-height: 40vh") {
+                Indexed(
+                    iterable=rows,
+                    view=move |ctx, (version, previous)| {
+                        let number = version.version;
+                        let saved = js_sys::Date::new(&JsValue::from_f64(version.saved_at as f64));
+                        let saved = String::from(saved.to_locale_string("default", &JsValue::UNDEFINED));
+                        let renamed = previous
+                            .as_ref()
+                            .filter(|p| p.act != version.act)
+                            .map(|p| format!("renamed from {}", p.act))
+                            .unwrap_or_default();
+                        let changes = match &previous {
+                            Some(previous) => diff_view(ctx, &previous.content, &version.content),
+                            None => {
+                                let content = version.content.clone();
+                                view! { ctx, (content) }
+                            }
+                        };
+                        view! { ctx,
+                            li(class="border-b py-1") {
+                                div(class="flex flex-row gap-1 items-center text-xs") {
+                                    span(class="font-bold") { (format!("v{}", number)) }
+                                    span { (saved) }
+                                    span(class="italic") { (renamed) }
+                                    div(class="flex-1")
+                                    button(class=if *left.get() == Some(number) { "btn btn-xs btn-active" } else { "btn btn-xs btn-ghost" },
+                                        on:click=move |_| left.set(Some(number))) { "A" }
+                                    button(class=if *right.get() == Some(number) { "btn btn-xs btn-active" } else { "btn btn-xs btn-ghost" },
+                                        on:click=move |_| right.set(Some(number))) { "B" }
+                                    button(class="btn btn-ghost btn-xs", on:click=move |_| restore(version.clone())) { "restore" }
+                                }
+                                p(class="text-sm whitespace-pre-wrap") { (changes) }
+                            }
+                        }
+                    },
+                )
+            }
+
+            Indexed(
+                iterable=variables,
+                view=move |ctx, var| {
+                    let name = var.name.clone();
+                    let hint = var.default.clone().or_else(|| var.hint().map(str::to_string)).unwrap_or_default();
+                    let value = create_signal(ctx, values.get_untracked().get(&name).cloned().unwrap_or_default());
+                    if var.is_builtin() && var.default.is_none() && value.get_untracked().is_empty() {
+                        let name = name.clone();
+                        sycamore::futures::spawn_local_scoped(ctx, async move {
+                            if let Some(builtin) = builtin_value(&name).await {
+                                value.set(builtin);
+                            }
+                        });
+                    }
+                    create_effect(ctx, {
+                        let name = name.clone();
+                        move || {
+                            values.modify().insert(name.clone(), value.get().to_string());
+                        }
+                    });
+                    view! { ctx,
+                        div(class="flex flex-row gap-1 items-center") {
+                            label(class="label text-xs") { (name) }
+                            input(class="input input-bordered input-xs flex-1", placeholder=hint, bind:value=value)
+                        }
+                    }
+                },
+            )
+            textarea(class="textarea textarea-bordered w-full", rows=2,
+                placeholder="a message to answer with versions A and B",
+                bind:value=input)
+            div(class="flex flex-row gap-1 items-center") {
+                button(class="btn btn-outline btn-info btn-xs", disabled=*running.get(), on:click=on_compare) { "compare" }
+                label(class="text-xs") { (status.get()) }
+            }
+            div(class="grid grid-cols-2 gap-2") {
+                Indexed(
+                    iterable=answers,
+                    view=|ctx, (number, answer)| {
+                        let html = markdown_to_html(answer.content.text());
+                        let (info, _) = message_info(&answer);
+                        view! { ctx,
+                            div(class="border rounded p-2") {
+                                h4(class="font-bold text-xs") { (format!("v{}", number)) }
+                                div(class="text-sm", dangerously_set_inner_html=&html)
+                                span(class="text-xs opacity-60") { (info) }
+                            }
+                        }
+                    },
+                )
+            }
+        }
+    }
+}
+
+// import into and export from the library, by a path typed in
+#[component(inline_props)]
+fn PromptFiles<'a, G: Html>(ctx: Scope<'a>, prompts: &'a Signal<Vec<Prompt>>) -> View<G> {
+    let path = create_signal(ctx, "".to_string());
+    let status = create_signal(ctx, "".to_string());
+
+    let on_import = move |_: web_sys::Event| {
+        let path = path.get_untracked().to_string();
+        if path.is_empty() {
+            return;
+        }
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            match call(ipc::ImportPrompts { path }).await {
+                Ok(summary) => {
+                    status.set(format!("imported {}, skipped {}", summary.imported, summary.skipped));
+                    load_prompts(prompts).await;
+                }
+                Err(e) => status.set(e),
+            }
+        });
+    };
+
+    let on_export = move |_: web_sys::Event| {
+        let path = path.get_untracked().to_string();
+        if path.is_empty() {
+            return;
+        }
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            match call(ipc::ExportPrompts { path }).await {
+                Ok(count) => status.set(format!("exported {}", count)),
+                Err(e) => status.set(e),
+            }
+        });
+    };
+
+    view! {ctx,
+        div(class="flex flex-row gap-1 items-center") {
+            input(class="input input-bordered input-xs w-48",
+                placeholder="prompts .csv or .json path",
+                bind:value=path)
+            button(class="btn btn-outline btn-xs", on:click=on_import) { "import" }
+            button(class="btn btn-outline btn-xs", on:click=on_export) { "export" }
+            label(class="text-xs truncate") { (status.get()) }
         }
     }
 }
@@ -263,90 +816,689 @@ fn TextArea<'a, G: Html>(ctx: Scope<'a>, props: TextAreaProps<'a>) -> View<G> {
     }
 }
 
-//TODO: suggesting prompts while typing
+// the value of a built-in template variable, `None` for other names
+async fn builtin_value(name: &str) -> Option<String> {
+    let now = js_sys::Date::new_0();
+    match name {
+        "date" => Some(now.to_locale_date_string("default", &JsValue::UNDEFINED).into()),
+        "time" => Some(now.to_locale_time_string("default").into()),
+        "os" => window().and_then(|w| w.navigator().platform().ok()),
+        // only read when a prompt asks for it, browsers may ask for permission
+        "clipboard" => read_clipboard().await.as_string(),
+        _ => None,
+    }
+}
+
+// asks for the variables of a prompt template and puts the filled in prompt
+// into `prompt`
+#[component(inline_props)]
+fn TemplateForm<'a, G: Html>(
+    ctx: Scope<'a>,
+    text: String,
+    prompt: &'a Signal<String>,
+    filling: &'a Signal<Option<String>>,
+) -> View<G> {
+    let template = create_ref(ctx, template::Template::parse(&text));
+    let fields = create_ref(
+        ctx,
+        template
+            .variables()
+            .iter()
+            .map(|var| (var.clone(), create_signal(ctx, var.default.clone().unwrap_or_default())))
+            .collect::<Vec<_>>(),
+    );
+
+    sycamore::futures::spawn_local_scoped(ctx, async move {
+        for (var, value) in fields.iter().filter(|(var, _)| var.is_builtin()) {
+            // a default given in the template wins
+            if var.default.is_some() {
+                continue;
+            }
+            if let Some(builtin) = builtin_value(&var.name).await {
+                value.set(builtin);
+            }
+        }
+    });
+
+    let on_use = move |_: web_sys::Event| {
+        let values = fields
+            .iter()
+            .map(|(var, value)| (var.name.clone(), value.get_untracked().to_string()))
+            .filter(|(_, value)| !value.is_empty())
+            .collect::<HashMap<_, _>>();
+        prompt.set(template.render(&values));
+        filling.set(None);
+    };
+
+    let inputs = View::new_fragment(
+        fields
+            .iter()
+            .map(|(var, value)| {
+                let value: &Signal<String> = value;
+                let name = var.name.clone();
+                let hint = var.hint().unwrap_or_default().to_string();
+                view! { ctx,
+                    label(class="label text-xs") { (name) }
+                    input(class="input input-bordered input-sm", placeholder=hint, bind:value=value)
+                }
+            })
+            .collect(),
+    );
+
+    view! {ctx,
+        div(class="flex flex-col mb-2") {
+            (inputs)
+            div(class="flex flex-row gap-1 mt-1") {
+                button(class="btn btn-outline btn-info btn-xs", on:click=on_use) { "use" }
+                button(class="btn btn-ghost btn-xs", on:click=move |_| filling.set(None)) { "cancel" }
+            }
+        }
+    }
+}
+
+// move `cursor` through `len` items on the arrow keys, true when `key` was one
+fn step_cursor(cursor: &Signal<usize>, len: usize, key: &str) -> bool {
+    if len == 0 {
+        return false;
+    }
+    match key {
+        "ArrowDown" => cursor.set((*cursor.get_untracked() + 1) % len),
+        "ArrowUp" => cursor.set((*cursor.get_untracked() + len - 1) % len),
+        _ => return false,
+    }
+    true
+}
+
 #[component(inline_props)]
 fn NewChatGuide<'a, G: Html>(
     ctx: Scope<'a>,
     prompt: &'a Signal<String>,
+    started_from: &'a Signal<Option<PromptRef>>,
     request_new: &'a Signal<Option<()>>,
 ) -> View<G> {
-    let selected = create_signal(ctx, "".to_string());
-    let preset_prompts = create_signal(ctx, vec![]);
+    let selected = create_signal(ctx, None::<Prompt>);
+    // a chosen prompt with variables, until they are filled in
+    let filling = create_signal(ctx, None::<String>);
+    // the chosen prompt as it went into `prompt`, until it is typed over
+    let rendered = create_signal(ctx, None::<String>);
+    let prompts = create_signal(ctx, Vec::<Prompt>::new());
+    let editing = create_signal(ctx, None::<Prompt>);
+    let history = create_signal(ctx, None::<Prompt>);
+    // "" shows every category
+    let category = create_signal(ctx, "".to_string());
+    let favorites_only = create_signal(ctx, false);
+    let query = create_signal(ctx, "".to_string());
+    let cursor = create_signal(ctx, 0usize);
 
     create_effect(ctx, move || {
-        if selected.get().is_empty() {
-            return;
+        let text = match selected.get().as_ref() {
+            Some(picked) => picked.content.clone(),
+            None => return,
+        };
+
+        if template::Template::parse(&text).variables().is_empty() {
+            filling.set(None);
+            rendered.set(Some(text.clone()));
+            prompt.set(text);
+        } else {
+            filling.set(Some(text));
         }
+    });
 
-        sycamore::futures::spawn_local_scoped(ctx, async move {
-            prompt.set((*selected.get_untracked()).clone());
-        });
+    // the conversation only counts as started from the chosen prompt while
+    // the text is what it rendered to, edits or clearing it out forget it
+    create_effect(ctx, move || {
+        let text = prompt.get();
+        // the template form fills `prompt` before it closes
+        if filling.get_untracked().is_some() {
+            rendered.set(Some(text.to_string()));
+        }
+        if rendered.get_untracked().as_deref() == Some(text.as_str()) {
+            started_from.set(selected.get_untracked().as_ref().as_ref().map(Prompt::reference));
+        } else {
+            started_from.set(None);
+        }
     });
 
     sycamore::futures::spawn_local_scoped(ctx, async move {
-        if let Ok(prompts) = openai_bundled_prompts().await {
-            if let Ok(prompts) = serde_wasm_bindgen::from_value::<Vec<Prompt>>(prompts) {
-                preset_prompts.set(prompts);
+        load_prompts(prompts).await;
+    });
+
+    let categories = create_memo(ctx, || {
+        let mut all = prompts
+            .get()
+            .iter()
+            .filter_map(|p| p.category.clone())
+            .collect::<Vec<_>>();
+        all.sort();
+        all.dedup();
+        all
+    });
+    let shown = create_memo(ctx, || {
+        let category = category.get();
+        let matching = prompts
+            .get()
+            .iter()
+            .filter(|p| category.is_empty() || p.category.as_deref() == Some(category.as_str()))
+            .filter(|p| p.favorite || !*favorites_only.get())
+            .cloned()
+            .collect::<Vec<_>>();
+        fuzzy::rank_prompts(&query.get(), &matching)
+            .into_iter()
+            .cloned()
+            .collect::<Vec<_>>()
+    });
+    create_effect(ctx, move || {
+        shown.track();
+        cursor.set(0);
+    });
+    let active = create_memo(ctx, || shown.get().get(*cursor.get()).map(|p| p.id));
+
+    let on_search_key = move |e: web_sys::Event| {
+        let e: web_sys::KeyboardEvent = e.unchecked_into();
+        let key = e.key();
+        if step_cursor(cursor, shown.get_untracked().len(), &key) {
+            e.prevent_default();
+            if let Some(row) = window()
+                .and_then(|w| w.document())
+                .zip(*active.get_untracked())
+                .and_then(|(d, id)| d.get_element_by_id(&format!("prompt-{}", id.0)))
+            {
+                row.scroll_into_view_with_bool(false);
             }
+        } else if key == "Enter" {
+            if let Some(p) = shown.get_untracked().get(*cursor.get_untracked()) {
+                selected.set(Some(p.clone()));
+            }
+        } else if key == "Escape" {
+            query.set("".to_string());
+        }
+    };
+
+    // prompts whose name is like what is being typed as the system prompt,
+    // until it gets too long to be a name or the list is dismissed
+    let dismissed = create_signal(ctx, "".to_string());
+    let suggestions = create_memo(ctx, || {
+        let text = prompt.get();
+        let typed = text.trim();
+        let len = typed.chars().count();
+        if len < 2 || len > 40 || typed.contains('\n') || *dismissed.get() == *text {
+            return vec![];
         }
+        fuzzy::rank_prompts(typed, &prompts.get())
+            .into_iter()
+            .take(5)
+            .cloned()
+            .collect::<Vec<_>>()
     });
+    let suggest_cursor = create_signal(ctx, 0usize);
+    create_effect(ctx, move || {
+        suggestions.track();
+        suggest_cursor.set(0);
+    });
+    let suggested = create_memo(ctx, || suggestions.get().get(*suggest_cursor.get()).map(|p| p.id));
+
+    // keys pressed in the system prompt bubble up here
+    let on_prompt_key = move |e: web_sys::Event| {
+        let list = suggestions.get_untracked();
+        if list.is_empty() {
+            return;
+        }
+        let e: web_sys::KeyboardEvent = e.unchecked_into();
+        let key = e.key();
+        if step_cursor(suggest_cursor, list.len(), &key) {
+            e.prevent_default();
+        } else if key == "Tab" {
+            e.prevent_default();
+            if let Some(p) = list.get(*suggest_cursor.get_untracked()) {
+                selected.set(Some(p.clone()));
+            }
+        } else if key == "Escape" {
+            dismissed.set(prompt.get_untracked().to_string());
+        }
+    };
 
     view! {ctx,
         div(class="flex flex-col h-full w-full") {
+            div(class="flex flex-row flex-wrap gap-2 mb-2 items-center") {
+                input(class="input input-bordered input-xs w-48",
+                    placeholder="search prompts, ↑↓ and enter to pick",
+                    bind:value=query,
+                    on:keydown=on_search_key)
+                select(class="select select-bordered select-xs", bind:value=category) {
+                    option(value="") { "all categories" }
+                    Indexed(
+                        iterable=categories,
+                        view=|ctx, c| {
+                            let value = c.clone();
+                            view! { ctx, option(value=value) { (c) } }
+                        },
+                    )
+                }
+                label(class="label cursor-pointer gap-1 text-xs") {
+                    input(type="checkbox", class="checkbox checkbox-xs", bind:checked=favorites_only)
+                    "favourites"
+                }
+                button(class="btn btn-outline btn-xs", on:click=move |_| {
+                    editing.set(Some(Prompt::new("", "")));
+                }) { "new prompt" }
+                PromptFiles(prompts=prompts)
+            }
+
+            (match editing.get().as_ref() {
+                Some(p) => {
+                    let p = p.clone();
+                    view! { ctx, PromptEditor(prompt=p, prompts=prompts, editing=editing) }
+                }
+                None => view! { ctx, },
+            })
+
+            (match history.get().as_ref() {
+                Some(p) => {
+                    let p = p.clone();
+                    view! { ctx, PromptHistory(prompt=p, prompts=prompts, history=history) }
+                }
+                None => view! { ctx, },
+            })
+
             table(class="flex-1 table table-fixed border-spacing-0 boder-collapse overflow-hidden") {
                 thead(class="w-full block") {
                     tr(class="flex w-full") {
                         th(class="w-1/6") { }
                         th(class="w-1/6") { "Act" }
                         th(class="flex-1") { "Content" }
+                        th(class="w-1/6") { }
                     }
                 }
 
                 tbody(class="block overflow-y-auto w-full", style="height: calc(100vh - 300px)") {
-                    Keyed(iterable=preset_prompts,
+                    Keyed(iterable=shown,
                         view=move |cx, x| {
-                            view!{cx, PromptItem(prompt=x, used=selected)}
+                            view!{cx, PromptItem(prompt=x, used=selected, prompts=prompts, editing=editing, history=history, active=active)}
                         },
-                        key=|x| x.act.clone())
+                        key=|x| x.clone())
                 }
             }
 
-            TextArea(placeholder="choose a system prompt or type your own...".to_string(),
-                content=prompt,
-                request_new=request_new)
+            (match filling.get().as_ref() {
+                Some(text) => {
+                    let text = text.clone();
+                    view! { ctx, TemplateForm(text=text, prompt=prompt, filling=filling) }
+                }
+                None => view! { ctx, },
+            })
+
+            (if suggestions.get().is_empty() {
+                view! { ctx, }
+            } else {
+                view! { ctx,
+                    ul(class="menu menu-compact bg-base-200 rounded-box mb-1") {
+                        Indexed(
+                            iterable=suggestions,
+                            view=move |ctx, p| {
+                                let id = p.id;
+                                let act = p.act.clone();
+                                view! { ctx,
+                                    li {
+                                        a(class=if *suggested.get() == Some(id) { "active" } else { "" },
+                                            on:click=move |_| selected.set(Some(p.clone()))) { (act) }
+                                    }
+                                }
+                            },
+                        )
+                        li(class="menu-title") { span(class="text-xs") { "tab to use, ↑↓ to choose, esc to hide" } }
+                    }
+                }
+            })
+
+            div(on:keydown=on_prompt_key) {
+                TextArea(placeholder="choose a system prompt or type your own...".to_string(),
+                    content=prompt,
+                    request_new=request_new)
+            }
         }
     }
 }
 
 #[component]
-fn ChatList<G: Html>(ctx: Scope) -> View<G> {
-    let conversations = use_context::<Signal<Vec<ConversationId>>>(ctx);
+fn ImportForm<G: Html>(ctx: Scope) -> View<G> {
+    let conversations = use_context::<Signal<Vec<ConversationSummary>>>(ctx);
+    let filter = use_context::<Signal<ConversationFilter>>(ctx);
+    let next_page = use_context::<Signal<Option<ConversationCursor>>>(ctx);
+    let path = create_signal(ctx, "".to_string());
+    let status = create_signal(ctx, "".to_string());
+
+    let on_import = move |_: web_sys::Event| {
+        if path.get_untracked().is_empty() {
+            return;
+        }
+
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            status.set("importing...".to_string());
+            match call(ipc::ImportConversations { path: path.get_untracked().to_string() }).await {
+                Ok(summary) => {
+                    status.set(format!(
+                        "imported {}, skipped {}",
+                        summary.imported, summary.skipped
+                    ));
+                    path.set("".to_string());
+                    load_conversations(conversations, filter, next_page).await;
+                }
+                Err(e) => status.set(e),
+            }
+        });
+    };
+
+    view! { ctx,
+        div(class="flex flex-col my-1 w-40") {
+            input(class="input input-bordered input-xs",
+                placeholder="conversations.json path",
+                bind:value=path)
+            button(class="btn btn-outline btn-xs mt-1", on:click=on_import) { "import" }
+            label(class="text-xs truncate") { (status.get()) }
+        }
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn highlighted_snippet(hit: &SearchHit) -> String {
+    let mut html = String::new();
+    let mut last = 0;
+    for (start, end) in &hit.highlights {
+        let (before, matched) = match (hit.snippet.get(last..*start), hit.snippet.get(*start..*end)) {
+            (Some(before), Some(matched)) => (before, matched),
+            _ => continue,
+        };
+        html.push_str(&escape_html(before));
+        html.push_str("<mark>");
+        html.push_str(&escape_html(matched));
+        html.push_str("</mark>");
+        last = *end;
+    }
+    html.push_str(&escape_html(hit.snippet.get(last..).unwrap_or("")));
+    html
+}
+
+#[component]
+fn SearchBox<G: Html>(ctx: Scope) -> View<G> {
+    let query = create_signal(ctx, "".to_string());
+    let hits: &Signal<Vec<SearchHit>> = create_signal(ctx, vec![]);
+
+    let on_search = move |e: web_sys::Event| {
+        let e: web_sys::KeyboardEvent = e.unchecked_into();
+        if e.key() != "Enter" {
+            return;
+        }
+
+        let q = query.get_untracked().to_string();
+        if q.trim().is_empty() {
+            hits.set(vec![]);
+            return;
+        }
+
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            match call(ipc::Search { query: q }).await {
+                Ok(found) => hits.set(found),
+                Err(e) => wasm_log!("{}", e),
+            }
+        });
+    };
+
+    view! { ctx,
+        div(class="flex flex-col w-40") {
+            input(class="input input-bordered input-sm",
+                placeholder="search...",
+                bind:value=query,
+                on:keyup=on_search)
+            ul(class="menu menu-compact w-40 max-h-64 overflow-y-auto") {
+                Keyed(iterable=hits,
+                    view=move |cx, hit| {
+                        let href = format!("/chats/{}/{}", hit.conversation.0, hit.message.0);
+                        let html = highlighted_snippet(&hit);
+                        view! {cx,
+                            li {
+                                a(class="text-xs whitespace-normal",
+                                    href=href,
+                                    on:click=move |_| hits.set(vec![]),
+                                    dangerously_set_inner_html=&html)
+                            }
+                        }
+                    },
+                    key=|hit| hit.message)
+            }
+        }
+    }
+}
+
+// pinned conversations first, then one group per folder, then the rest
+fn group_conversations(entries: &[ConversationSummary]) -> Vec<(String, Vec<ConversationSummary>)> {
+    let mut pinned = vec![];
+    let mut folders: std::collections::BTreeMap<String, Vec<ConversationSummary>> = Default::default();
+    let mut rest = vec![];
+    for entry in entries {
+        if entry.meta.pinned {
+            pinned.push(entry.clone());
+        } else if let Some(folder) = &entry.meta.folder {
+            folders.entry(folder.clone()).or_default().push(entry.clone());
+        } else {
+            rest.push(entry.clone());
+        }
+    }
+
+    let mut groups = vec![];
+    if !pinned.is_empty() {
+        groups.push(("Pinned".to_string(), pinned));
+    }
+    groups.extend(folders);
+    if !rest.is_empty() {
+        groups.push(("Conversations".to_string(), rest));
+    }
+    groups
+}
+
+async fn update_meta(
+    id: ConversationId,
+    meta: ConversationMeta,
+    conversations: &Signal<Vec<ConversationSummary>>,
+) {
+    match call(ipc::SetMeta { id, meta: meta.clone() }).await {
+        Ok(()) => {
+            if let Some(entry) = conversations.modify().iter_mut().find(|e| e.id == id) {
+                entry.meta = meta;
+            }
+        }
+        Err(e) => wasm_log!("{}", e),
+    }
+}
+
+#[component(inline_props)]
+fn ConversationItem<G: Html>(ctx: Scope, entry: ConversationSummary) -> View<G> {
+    let conversations = use_context::<Signal<Vec<ConversationSummary>>>(ctx);
     let current_id = use_context::<Signal<Option<ConversationId>>>(ctx);
+    let filter = use_context::<Signal<ConversationFilter>>(ctx);
+
+    let id = entry.id;
+    let title = create_ref(ctx, entry.label().to_string());
+    // untitled conversations already show the preview as their title
+    let preview = create_ref(ctx, if entry.title.is_some() { entry.preview.clone() } else { "".to_string() });
+    let meta = create_ref(ctx, entry.meta);
+    let editing = create_signal(ctx, false);
+    let folder = create_signal(ctx, meta.folder.clone().unwrap_or_default());
+    let tags = create_signal(ctx, meta.tags.join(", "));
+    let tag_list = create_signal(ctx, meta.tags.clone());
+
+    let on_pin = move |_: web_sys::Event| {
+        let mut meta = meta.clone();
+        meta.pinned = !meta.pinned;
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            update_meta(id, meta, conversations).await;
+        });
+    };
+
+    let on_save = move |_: web_sys::Event| {
+        editing.set(false);
+        let folder = folder.get_untracked().trim().to_string();
+        let meta = ConversationMeta {
+            tags: tags
+                .get_untracked()
+                .split(',')
+                .map(|t| t.trim().to_string())
+                .filter(|t| !t.is_empty())
+                .collect(),
+            folder: Some(folder).filter(|f| !f.is_empty()),
+            pinned: meta.pinned,
+            prompt: meta.prompt,
+        };
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            update_meta(id, meta, conversations).await;
+        });
+    };
+
+    // the list and an open conversation go away on the change event
+    let on_delete = move |_: web_sys::Event| {
+        editing.set(false);
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            if let Err(e) = call(ipc::DeleteConversation { id }).await {
+                wasm_log!("{}", e);
+            }
+        });
+    };
 
     view! { ctx,
-        div(class="h-full flex flex-col mr-2 min-w-fit") {
-            div(class="title shrink flex flex-row") {
-                h2(class="shrink"){"Conversations"}
+        li(class="hover-bordered") {
+            div(class="flex flex-row p-0") {
+                a(class=if *current_id.get() == Some(id) { "flex-1 flex-col items-start gap-0 truncate active" } else { "flex-1 flex-col items-start gap-0 truncate" },
+                    href=format!("/chats/{}", id.0), title=title.clone()) {
+                    span(class="w-full truncate") { (title.clone()) }
+                    (if preview.is_empty() {
+                        view! { ctx, }
+                    } else {
+                        view! { ctx, span(class="w-full truncate text-xs opacity-60") { (preview.clone()) } }
+                    })
+                }
+                button(class="btn btn-ghost btn-xs", title="pin", on:click=on_pin) {
+                    (if meta.pinned { "★" } else { "☆" })
+                }
+                button(class="btn btn-ghost btn-xs", title="folder and tags", on:click=move |_| {
+                    editing.set(!*editing.get_untracked());
+                }) { "⋯" }
             }
 
-            ul(class="flex-1 flex flex-col my-2 overflow-y-scroll menu w-40 truncate") {
-                Keyed(iterable=conversations,
-                    view=|cx, x| {
-                        if *current_id.get() == Some(x) {
-                            view!(cx,
-                                li(class="hover-bordered"){
-                                    a(class="active", href=format!("/chats/{}", x.0)){(x.0)}
-                                })
+            (if *editing.get() {
+                view! { ctx,
+                    div(class="flex flex-col p-1") {
+                        input(class="input input-bordered input-xs mb-1",
+                            placeholder="folder",
+                            bind:value=folder)
+                        input(class="input input-bordered input-xs mb-1",
+                            placeholder="tags, comma separated",
+                            bind:value=tags)
+                        button(class="btn btn-outline btn-xs mb-1", on:click=on_save) { "save" }
+                        button(class="btn btn-outline btn-error btn-xs", on:click=on_delete) { "delete" }
+                    }
+                }
+            } else {
+                view! { ctx, }
+            })
 
-                        } else {
-                            view!(cx,
-                                li(class="hover-bordered"){
-                                    a(href=format!("/chats/{}", x.0)){(x.0)}
-                                })
+            div(class="flex flex-row flex-wrap p-0") {
+                Indexed(iterable=tag_list,
+                    view=move |cx, tag| {
+                        let t = tag.clone();
+                        view! { cx,
+                            span(class="badge badge-outline badge-xs mr-1 cursor-pointer",
+                                on:click=move |_| {
+                                    filter.modify().tag = Some(t.clone());
+                                }) { (tag) }
+                        }
+                    })
+            }
+        }
+    }
+}
 
+#[component]
+fn ChatList<G: Html>(ctx: Scope) -> View<G> {
+    let conversations = use_context::<Signal<Vec<ConversationSummary>>>(ctx);
+    let filter = use_context::<Signal<ConversationFilter>>(ctx);
+    let next_page = use_context::<Signal<Option<ConversationCursor>>>(ctx);
+    let loading = create_signal(ctx, false);
+    let list_ref = create_node_ref(ctx);
+    let groups = create_memo(ctx, || group_conversations(&conversations.get()));
+
+    let tag_filter = create_signal(ctx, "".to_string());
+    let mounted = create_signal(ctx, false);
+    create_effect(ctx, move || {
+        // typing into the box or clicking a tag badge both end up here
+        let tag = filter.get().tag.clone().unwrap_or_default();
+        if *tag_filter.get_untracked() != tag {
+            tag_filter.set(tag);
+        }
+        // `ChatApp` loads the first page, only a changed filter reloads here
+        if !*mounted.get_untracked() {
+            mounted.set(true);
+            return;
+        }
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            load_conversations(conversations, filter, next_page).await;
+        });
+    });
+
+    // fetch the next page once the list is scrolled close to its end
+    let on_scroll = move |_: web_sys::Event| {
+        let list = list_ref.get::<DomNode>().unchecked_into::<web_sys::Element>();
+        let near_end = list.scroll_top() + list.client_height() + 40 >= list.scroll_height();
+        if !near_end || *loading.get_untracked() || next_page.get_untracked().is_none() {
+            return;
+        }
+
+        loading.set(true);
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            load_more_conversations(conversations, filter, next_page).await;
+            loading.set(false);
+        });
+    };
+
+    let on_filter = move |e: web_sys::Event| {
+        let e: web_sys::KeyboardEvent = e.unchecked_into();
+        if e.key() != "Enter" {
+            return;
+        }
+        let tag = tag_filter.get_untracked().trim().to_string();
+        filter.modify().tag = Some(tag).filter(|t| !t.is_empty());
+    };
+
+    view! { ctx,
+        div(class="h-full flex flex-col mr-2 min-w-fit") {
+            SearchBox {}
+
+            div(class="title shrink flex flex-row") {
+                h2(class="shrink"){"Conversations"}
+            }
+            input(class="input input-bordered input-xs w-40 mt-1",
+                placeholder="filter by tag",
+                bind:value=tag_filter,
+                on:keyup=on_filter)
+
+            ul(ref=list_ref,
+                class="flex-1 flex flex-col my-2 overflow-y-scroll menu menu-compact w-40 truncate",
+                on:scroll=on_scroll) {
+                Keyed(iterable=groups,
+                    view=|cx, (name, entries)| {
+                        let entries = create_signal(cx, entries);
+                        view! {cx,
+                            li(class="menu-title") { span { (name) } }
+                            Keyed(iterable=entries,
+                                view=|cx, entry| view! {cx, ConversationItem(entry=entry)},
+                                key=|entry| entry.clone())
                         }
                     },
-                    key=|x| x.clone())
+                    key=|group| group.clone())
             }
 
             div(class="flex justify-center my-1") {
@@ -360,6 +1512,8 @@ fn ChatList<G: Html>(ctx: Scope) -> View<G> {
                     }
                 }
             }
+
+            ImportForm {}
         }
     }
 }
@@ -367,77 +1521,425 @@ fn ChatList<G: Html>(ctx: Scope) -> View<G> {
 async fn continue_conversation<'a>(
     conversation: &Signal<Conversation<'a>>,
     question: &Signal<String>,
+    attachments: &Signal<Vec<String>>,
 ) {
     let cnv = conversation.get_untracked();
+    let id = match *cnv.id.get_untracked() {
+        Some(id) => id,
+        None => return,
+    };
 
-    cnv.chats.modify().push(Message::new_user({
-        let q = question.get().to_string();
-        if q.is_empty() {
-            return;
-        }
-        q
-    }));
-
+    let asked = question.get().to_string();
+    let images = attachments.get().as_ref().clone();
+    if asked.is_empty() && images.is_empty() {
+        return;
+    }
+    let content = Content::with_images(asked.clone(), images.clone());
+    cnv.tree.modify().push(Message::new_user(content.clone()));
     question.set("".to_string());
-    let prompt = cnv.chats.get();
-    cnv.chats
+    attachments.set(vec![]);
+    let pending = cnv
+        .tree
         .modify()
         .push(Message::new_assistant("...".to_string()));
 
-    match serde_wasm_bindgen::to_value(prompt.as_ref()) {
-        Ok(prompt) => {
-            let id = serde_wasm_bindgen::to_value(cnv.id.get_untracked().as_ref()).unwrap();
-            let msg: Message = match openai_completion(id, prompt).await {
-                Ok(msg) => serde_wasm_bindgen::from_value(msg).unwrap(),
-                Err(e) => {
-                    wasm_log!("{:?}", e);
-                    cnv.chats.modify().pop();
-                    return;
-                }
+    if let Err(e) = call(ipc::AppendUserMessage { id, content }).await {
+        wasm_log!("{}", e);
+        drop_pending(cnv.tree, pending);
+        if e == CONFLICT_ERROR {
+            // another request got stored first, offer the question again
+            question.set(asked);
+            attachments.set(images);
+        }
+    }
+
+    // the backend assigned its own ids to the new messages, and kept the
+    // question even if it could not be answered
+    reload_tree(&cnv).await;
+    cnv.stored.set(());
+}
+
+// larger images are scaled down to this many pixels on their longer side,
+// which is as much as models look at
+const MAX_IMAGE_SIDE: u32 = 2048;
+// files above this are not even read, the api turns them away
+const MAX_IMAGE_FILE: f64 = 20.0 * 1024.0 * 1024.0;
+
+// add the images among `files` to `attachments` as data urls, anything else is ignored
+fn attach_images<'a>(ctx: Scope<'a>, files: Option<web_sys::FileList>, attachments: &'a Signal<Vec<String>>) {
+    let files = match files {
+        Some(files) => (0..files.length())
+            .filter_map(|i| files.get(i))
+            .filter(|f| f.type_().starts_with("image/"))
+            .collect::<Vec<_>>(),
+        None => return,
+    };
+
+    for file in files {
+        if file.size() > MAX_IMAGE_FILE {
+            wasm_log!("{} is too large to attach", file.name());
+            continue;
+        }
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            let url = match read_data_url(&file).await {
+                Ok(url) => downscale(url).await,
+                Err(e) => Err(e),
             };
-            if let Some(p) = cnv.chats.modify().last_mut() {
-                assert!(p.role == msg.role);
-                p.content = msg.content;
+            match url {
+                Ok(url) => attachments.modify().push(url),
+                Err(e) => wasm_log!("read {}: {:?}", file.name(), e),
             }
+        });
+    }
+}
+
+async fn read_data_url(file: &web_sys::File) -> Result<String, JsValue> {
+    let reader = web_sys::FileReader::new()?;
+    let (tx, rx) = oneshot::channel();
+    let mut tx = Some(tx);
+    let on_load: Box<dyn FnMut()> = Box::new(move || {
+        if let Some(tx) = tx.take() {
+            let _ = tx.send(());
+        }
+    });
+    let on_load = Closure::wrap(on_load);
+    reader.set_onloadend(Some(on_load.as_ref().unchecked_ref()));
+    reader.read_as_data_url(file)?;
+
+    rx.await.map_err(|_| JsValue::from_str("file reader went away"))?;
+    reader
+        .result()?
+        .as_string()
+        .ok_or_else(|| JsValue::from_str("file was not read"))
+}
 
-            highlightAll();
+// `url` drawn at most MAX_IMAGE_SIDE pixels wide and high, as is if it fits
+async fn downscale(url: String) -> Result<String, JsValue> {
+    let img = web_sys::HtmlImageElement::new()?;
+    let (tx, rx) = oneshot::channel();
+    let mut tx = Some(tx);
+    let on_load: Box<dyn FnMut()> = Box::new(move || {
+        if let Some(tx) = tx.take() {
+            let _ = tx.send(());
         }
+    });
+    let on_load = Closure::wrap(on_load);
+    img.set_onload(Some(on_load.as_ref().unchecked_ref()));
+    img.set_onerror(Some(on_load.as_ref().unchecked_ref()));
+    img.set_src(&url);
+    rx.await.map_err(|_| JsValue::from_str("image went away"))?;
+
+    let (width, height) = (img.natural_width(), img.natural_height());
+    if width == 0 || height == 0 {
+        return Err(JsValue::from_str("not an image"));
+    }
+    let longer = width.max(height);
+    if longer <= MAX_IMAGE_SIDE {
+        return Ok(url);
+    }
+    let scale = MAX_IMAGE_SIDE as f64 / longer as f64;
+    let (width, height) = ((width as f64 * scale).round() as u32, (height as f64 * scale).round() as u32);
+
+    let canvas: web_sys::HtmlCanvasElement = window()
+        .and_then(|w| w.document())
+        .ok_or_else(|| JsValue::from_str("no document"))?
+        .create_element("canvas")?
+        .unchecked_into();
+    canvas.set_width(width);
+    canvas.set_height(height);
+    let context: web_sys::CanvasRenderingContext2d = canvas
+        .get_context("2d")?
+        .ok_or_else(|| JsValue::from_str("no canvas"))?
+        .unchecked_into();
+    context.draw_image_with_html_image_element_and_dw_and_dh(&img, 0.0, 0.0, width as f64, height as f64)?;
+    // photos are what gets this large, jpeg keeps them small
+    canvas.to_data_url_with_type_and_encoder_options("image/jpeg", &JsValue::from_f64(0.9))
+}
+
+fn drop_pending(tree: &Signal<ConversationTree>, pending: MessageId) {
+    let mut tree = tree.modify();
+    tree.current = tree.get(pending).and_then(|n| n.parent);
+    tree.nodes.retain(|n| n.id() != pending);
+}
+
+// load the most recent messages of the active branch
+async fn reload_tree<'a>(cnv: &Conversation<'a>) {
+    let id = match *cnv.id.get_untracked() {
+        Some(id) => id,
+        None => return,
+    };
+
+    match call(ipc::GetMessages { id, before: None, limit: MESSAGE_PAGE_SIZE }).await {
+        Ok(page) => set_page(cnv, page, true),
+        Err(e) => wasm_log!("{}", e),
+    }
+}
+
+// load the page of messages right before the oldest one shown
+async fn load_older<'a>(cnv: &Conversation<'a>) {
+    let before = match *cnv.older.get_untracked() {
+        Some(before) => before,
+        None => return,
+    };
+
+    let id = match *cnv.id.get_untracked() {
+        Some(id) => id,
+        None => return,
+    };
+
+    match call(ipc::GetMessages { id, before: Some(before), limit: MESSAGE_PAGE_SIZE }).await {
+        Ok(page) => {
+            if *cnv.older.get_untracked() == Some(before) {
+                set_page(cnv, page, false);
+            }
+        }
+        Err(e) => wasm_log!("{}", e),
+    }
+}
+
+// show a page of messages, replacing what is loaded or prepending to it
+fn set_page<'a>(cnv: &Conversation<'a>, page: Page<PathNode, MessageId>, replace: bool) {
+    let mut nodes = vec![];
+    let mut siblings = HashMap::new();
+    for item in page.items {
+        siblings.insert(item.node.id(), item.siblings);
+        nodes.push(item.node);
+    }
+
+    // siblings go first so the items rendered for `nodes` find theirs
+    if replace {
+        cnv.siblings.set(siblings);
+        cnv.tree.set(ConversationTree {
+            current: nodes.last().map(|n| n.id()),
+            nodes,
+        });
+    } else {
+        cnv.siblings.modify().extend(siblings);
+        let mut tree = cnv.tree.modify();
+        let newer = std::mem::replace(&mut tree.nodes, nodes);
+        tree.nodes.extend(newer);
+        drop(tree);
+    }
+    cnv.older.set(page.next);
+    highlightAll();
+}
+
+async fn switch_branch<'a>(cnv: &Conversation<'a>, target: MessageId) {
+    let id = match *cnv.id.get_untracked() {
+        Some(id) => id,
+        None => return,
+    };
+
+    match call(ipc::SwitchBranch { id, message_id: target }).await {
+        Ok(page) => {
+            set_page(cnv, page, true);
+            cnv.stored.set(());
+        }
+        Err(e) => wasm_log!("{}", e),
+    }
+}
+
+async fn edit_message<'a>(cnv: &Conversation<'a>, target: MessageId, content: Content) {
+    let id = match *cnv.id.get_untracked() {
+        Some(id) => id,
+        None => return,
+    };
+    {
+        let mut tree = cnv.tree.modify();
+        tree.fork(target, Message::new_user(content.clone()));
+        tree.push(Message::new_assistant("...".to_string()));
+    }
+
+    match call(ipc::EditMessage { id, message_id: target, content }).await {
+        Ok(page) => set_page(cnv, page, true),
         Err(e) => {
-            wasm_log!("{:?}", e);
-            cnv.chats.modify().pop();
+            wasm_log!("{}", e);
+            reload_tree(cnv).await;
         }
     }
+    cnv.stored.set(());
+}
+
+// bring `target` onto the active branch and scroll it into view
+async fn focus_message<'a>(cnv: &Conversation<'a>, target: MessageId) {
+    let on_path = cnv.tree.get_untracked().path().iter().any(|n| n.id() == target);
+    if !on_path {
+        switch_branch(cnv, target).await;
+    }
+    while cnv.tree.get_untracked().get(target).is_none() && cnv.older.get_untracked().is_some() {
+        load_older(cnv).await;
+    }
+
+    if let Some(el) = window()
+        .and_then(|w| w.document())
+        .and_then(|d| d.get_element_by_id(&format!("msg-{}", target.0)))
+    {
+        el.scroll_into_view();
+    }
 }
 
 async fn load_conversation<'a>(cid: ConversationId, conversation: &Signal<Conversation<'a>>) {
     wasm_log!("load conversation {:?}", cid);
 
-    conversation.get_untracked().id.set(Some(cid));
+    let cnv = conversation.get_untracked();
+    cnv.id.set(Some(cid));
+    if let Some(title) = fetch_title(cid).await {
+        cnv.title.set(title);
+    }
+    reload_tree(&cnv).await;
+}
 
-    let id = match serde_wasm_bindgen::to_value(&cid) {
-        Ok(id) => id,
-        Err(e) => {
-            wasm_log!("{:?}", e);
-            return;
+// a short summary shown under the bubble and the full details for its tooltip
+fn message_info(msg: &Message) -> (String, String) {
+    let meta = &msg.meta;
+    let mut short = vec![];
+    if let Some(model) = &meta.model {
+        short.push(model.clone());
+    }
+    if let Some(latency) = meta.latency_ms {
+        short.push(format!("{:.1}s", latency as f64 / 1000.0));
+    }
+    if let Some(tokens) = meta.completion_tokens {
+        short.push(format!("{} tokens", tokens));
+    }
+
+    let sent = js_sys::Date::new(&JsValue::from_f64(msg.created_at as f64));
+    let sent = String::from(sent.to_locale_string("default", &JsValue::UNDEFINED));
+    let mut details = vec![format!("sent {}", sent)];
+    if let Some(reason) = &meta.finish_reason {
+        details.push(format!("finish reason: {}", reason));
+    }
+    if let Some(tokens) = meta.prompt_tokens {
+        details.push(format!("prompt tokens: {}", tokens));
+    }
+
+    (short.join(" · "), details.join("\n"))
+}
+
+#[component(inline_props)]
+fn ChatItem<'a, G: Html>(
+    ctx: Scope<'a>,
+    node: MessageNode,
+    conversation: Conversation<'a>,
+) -> View<G> {
+    let id = node.id();
+    let tree = conversation.tree;
+    let known = conversation.siblings;
+    // messages forked locally are not known to the backend yet
+    let siblings = create_memo(ctx, move || {
+        known
+            .get()
+            .get(&id)
+            .cloned()
+            .unwrap_or_else(|| tree.get().siblings(id))
+    });
+    let position = create_memo(ctx, move || {
+        siblings.get().iter().position(|s| *s == id).unwrap_or(0)
+    });
+
+    let editing = create_signal(ctx, false);
+    let draft = create_signal(ctx, node.message.content.text());
+    let original = node.message.content.clone();
+
+    let switch = move |offset: isize| {
+        let target = position.get_untracked().checked_add_signed(offset);
+        if let Some(target) = target.and_then(|i| siblings.get_untracked().get(i).copied()) {
+            sycamore::futures::spawn_local_scoped(ctx, async move {
+                switch_branch(&conversation, target).await;
+            });
         }
     };
 
-    let msgs = match openai_get_conversation(id).await {
-        Ok(msgs) => msgs,
-        Err(e) => {
-            wasm_log!("{:?}", e);
+    let on_save = move |_: web_sys::Event| {
+        editing.set(false);
+        let content = draft.get_untracked().to_string();
+        if content.is_empty() {
             return;
         }
+        // attached images stay, only the text is edited
+        let content = original.replace_text(content);
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            edit_message(&conversation, id, content).await;
+        });
     };
-    //wasm_log!("{:?}", msgs);
-    let msgs: Vec<Message> = serde_wasm_bindgen::from_value(msgs).unwrap();
-    conversation.get_untracked().chats.set(msgs);
-    highlightAll();
+
+    let is_user = node.message.role == Role::User;
+    let (summary, details) = message_info(&node.message);
+    let text = node.message.content.text();
+    let images = node.message.content.images().into_iter().map(|i| i.url.clone()).collect::<Vec<_>>();
+    let bubble = match &node.message.role {
+        Role::Assistant => view! {ctx,
+            Bubble(actor="AI".to_string(),
+            at_start=false,
+            content=text,
+            images=images)
+        },
+        Role::User => view! {ctx,
+            Bubble(actor="H".to_string(),
+            at_start=true,
+            content=text,
+            images=images)
+        },
+        // instructions aren't part of the chat
+        Role::System | Role::Developer => return view! {ctx, },
+        // tool output and roles this app doesn't know yet, on the answering side
+        other => view! {ctx,
+            Bubble(actor=other.to_string(),
+            at_start=false,
+            content=text,
+            images=images)
+        },
+    };
+    let controls = if is_user {
+        "flex flex-row items-center text-xs ml-12"
+    } else {
+        "flex flex-row items-center justify-end text-xs mr-12"
+    };
+
+    view! {ctx,
+        div(id=format!("msg-{}", id.0)) {
+            (if *editing.get() {
+                view! {ctx,
+                    div(class="flex flex-col mx-12") {
+                        textarea(class="textarea textarea-info w-full", rows=3, bind:value=draft)
+                        div(class="flex flex-row justify-end mt-1") {
+                            button(class="btn btn-outline btn-info btn-xs mr-1", on:click=on_save) { "save & submit" }
+                            button(class="btn btn-ghost btn-xs", on:click=move |_| editing.set(false)) { "cancel" }
+                        }
+                    }
+                }
+            } else {
+                bubble.clone()
+            })
+
+            div(class=controls) {
+                (if siblings.get().len() > 1 {
+                    view! {ctx,
+                        button(class="btn btn-ghost btn-xs", on:click=move |_| switch(-1)) { "<" }
+                        span { (format!("{}/{}", *position.get() + 1, siblings.get().len())) }
+                        button(class="btn btn-ghost btn-xs", on:click=move |_| switch(1)) { ">" }
+                    }
+                } else {
+                    view! {ctx, }
+                })
+                span(class="opacity-50 mx-1", title=details) { (summary) }
+                (if is_user && !*editing.get() {
+                    view! {ctx,
+                        button(class="btn btn-ghost btn-xs", on:click=move |_| editing.set(true)) { "edit" }
+                    }
+                } else {
+                    view! {ctx, }
+                })
+            }
+        }
+    }
 }
 
 #[component]
 fn ChatCompletion<G: Html>(ctx: Scope, props: ChatAppProps) -> View<G> {
     let question = create_signal(ctx, "".to_string());
+    // data urls of images pasted or dropped into the question
+    let attachments = create_signal(ctx, Vec::<String>::new());
     let clicked = create_signal(ctx, ());
     let waiting_for_response = create_signal(ctx, false);
     let submit_state = create_memo(ctx, || {
@@ -448,7 +1950,66 @@ fn ChatCompletion<G: Html>(ctx: Scope, props: ChatAppProps) -> View<G> {
         }
     });
 
-    let conversation = create_signal(ctx, Conversation::new(ctx));
+    let conversation = create_signal(ctx, Conversation::new(ctx));
+    // the library prompt the conversation was started with
+    let started_with = create_signal(ctx, None::<PromptVersion>);
+    let path = create_memo(ctx, || {
+        conversation
+            .get()
+            .tree
+            .get()
+            .path()
+            .into_iter()
+            .cloned()
+            .collect::<Vec<_>>()
+    });
+
+    // the context the stored conversation takes up, refreshed once something
+    // is stored rather than on every change of what is shown
+    let budget = create_signal(ctx, None::<tokens::TokenBudget>);
+    create_effect(ctx, move || {
+        let cnv = conversation.get();
+        cnv.stored.track();
+        let id = match *cnv.id.get() {
+            Some(id) => id,
+            None => return,
+        };
+
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            match call(ipc::GetTokenBudget { id }).await {
+                Ok(b) => budget.set(Some(b)),
+                Err(e) => wasm_log!("{}", e),
+            }
+        });
+    });
+
+    // what the draft would cost, counted here so it keeps up with typing
+    let draft = create_memo(ctx, move || {
+        Option::as_ref(&budget.get()).map(|b| {
+            let content = Content::with_images(question.get().to_string(), attachments.get().to_vec());
+            let cost = b.cost(&content);
+            (cost, b.remaining(cost))
+        })
+    });
+
+    let messages_ref = create_node_ref(ctx);
+    let loading_older = create_signal(ctx, false);
+    let on_scroll = move |_: web_sys::Event| {
+        let list = messages_ref.get::<DomNode>().unchecked_into::<web_sys::Element>();
+        let cnv = conversation.get_untracked();
+        if list.scroll_top() > 40 || *loading_older.get_untracked() || cnv.older.get_untracked().is_none() {
+            return;
+        }
+
+        loading_older.set(true);
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            let height = list.scroll_height();
+            load_older(&cnv).await;
+            // keep the messages that were on screen where they are
+            list.set_scroll_top(list.scroll_top() + list.scroll_height() - height);
+            loading_older.set(false);
+        });
+    };
 
     let current_id = use_context::<Signal<Option<ConversationId>>>(ctx);
     create_effect(ctx, move || {
@@ -462,33 +2023,62 @@ fn ChatCompletion<G: Html>(ctx: Scope, props: ChatAppProps) -> View<G> {
 
         sycamore::futures::spawn_local_scoped(ctx, async move {
             let cnv = conversation.get_untracked();
-            if cnv.id.get().is_none() {
-                return;
-            }
+            let id = match *cnv.id.get() {
+                Some(id) => id,
+                None => return,
+            };
 
             waiting_for_response.set(true);
-            continue_conversation(conversation, question).await;
+            continue_conversation(conversation, question, attachments).await;
             waiting_for_response.set(false);
 
             if cnv.title.get_untracked().is_empty() {
                 // suggest one
-                let id = serde_wasm_bindgen::to_value(cnv.id.get_untracked().as_ref()).unwrap();
-                match openai_suggest_title(id).await {
-                    Ok(title) => match serde_wasm_bindgen::from_value(title) {
-                        Ok(title) => cnv.title.set(title),
-                        Err(e) => wasm_log!("{:?}", e),
-                    },
-                    Err(e) => wasm_log!("{:?}", e),
+                match call(ipc::SuggestTitle { id }).await {
+                    Ok(title) => cnv.title.set(title),
+                    Err(e) => wasm_log!("{}", e),
                 }
             }
         });
     });
 
+    // follow changes made to this conversation elsewhere, our own show up
+    // here too but are reloaded once the answer arrives
+    storage_listener(ctx, move |event| {
+        let cnv = conversation.get_untracked();
+        if Some(event.id()) != *cnv.id.get_untracked() {
+            return;
+        }
+
+        match event {
+            StorageEvent::Deleted(_) => navigate("/chats"),
+            StorageEvent::TitleChanged(id) => {
+                sycamore::futures::spawn_local_scoped(ctx, async move {
+                    if let Some(title) = fetch_title(id).await {
+                        cnv.title.set(title);
+                    }
+                });
+            }
+            StorageEvent::Updated(_) if !*waiting_for_response.get_untracked() => {
+                sycamore::futures::spawn_local_scoped(ctx, async move {
+                    reload_tree(&cnv).await;
+                    cnv.stored.set(());
+                });
+            }
+            _ => {}
+        }
+    });
+
     let id = props.id.clone();
+    let focus = props.focus.clone();
     if !id.is_empty() {
         sycamore::futures::spawn_local_scoped(ctx, async move {
             let cid = ConversationId(Uuid::parse_str(&id).expect("uuid"));
             load_conversation(cid, conversation).await;
+            started_with.set(fetch_started_with(cid).await);
+            if let Ok(focus) = Uuid::parse_str(&focus) {
+                focus_message(&conversation.get_untracked(), MessageId(focus)).await;
+            }
         });
     }
 
@@ -499,26 +2089,24 @@ fn ChatCompletion<G: Html>(ctx: Scope, props: ChatAppProps) -> View<G> {
                     placeholder="context prompt") {
                     (conversation.get().title.get())
                 }
+                (match started_with.get().as_ref() {
+                    Some(version) => {
+                        let name = format!("{} v{}", version.act, version.version);
+                        let content = version.content.clone();
+                        view! { ctx,
+                            span(class="badge badge-ghost mb-2 ml-1", title=content) { (name) }
+                        }
+                    }
+                    None => view! { ctx, },
+                })
             }
 
-            ul(class="flex-1 flex flex-col my-2 overflow-y-scroll") {
-                Keyed(iterable=conversation.get().chats,
-                view=|cx, x| {
-                    match x.role {
-                        v if v == <KnownRoles as Into<&str>>::into(KnownRoles::Assistant) => view! {cx,
-                        Bubble(actor="AI".to_string(),
-                        at_start=false,
-                        content=x.content)
-                        },
-                        v if v == <KnownRoles as Into<&str>>::into(KnownRoles::User) => view! {cx,
-                            Bubble(actor="H".to_string(),
-                            at_start=true,
-                            content=x.content)
-                        },
-                        _ => view! {cx, }
-                    }
+            ul(ref=messages_ref, class="flex-1 flex flex-col my-2 overflow-y-scroll", on:scroll=on_scroll) {
+                Keyed(iterable=path,
+                view=move |cx, x| view! {cx,
+                    ChatItem(node=x, conversation=*conversation.get_untracked())
                 },
-                key=|x| x.clone())
+                key=|x| x.id())
             }
 
             div(class="relative mb-2") {
@@ -539,9 +2127,60 @@ fn ChatCompletion<G: Html>(ctx: Scope, props: ChatAppProps) -> View<G> {
                         }
                     }
                 }
+                (if attachments.get().is_empty() {
+                    view! { ctx, }
+                } else {
+                    view! { ctx,
+                        div(class="flex flex-row flex-wrap gap-1 mb-1") {
+                            Indexed(
+                                iterable=attachments,
+                                view=move |ctx, url| {
+                                    let remove = url.clone();
+                                    view! { ctx,
+                                        div(class="relative") {
+                                            img(class="h-16 rounded object-cover", src=url)
+                                            button(class="btn btn-circle btn-xs absolute top-0 right-0",
+                                                on:click=move |_| attachments.modify().retain(|u| *u != remove)) { "✕" }
+                                        }
+                                    }
+                                },
+                            )
+                        }
+                    }
+                })
+                (match *draft.get() {
+                    Some((cost, Some(remaining))) => view! { ctx,
+                        div(class=if remaining < 0 { "text-xs text-right text-error mb-1" } else { "text-xs text-right opacity-60 mb-1" }) {
+                            (format!("{} tokens, {} of the context left", cost, remaining))
+                        }
+                    },
+                    // a window that is not known is left to the api
+                    Some((cost, None)) => view! { ctx,
+                        div(class="text-xs text-right opacity-60 mb-1") {
+                            (format!("{} tokens", cost))
+                        }
+                    },
+                    None => view! { ctx, },
+                })
                 textarea(class="textarea textarea-info w-full",
                     rows=4,
-                    placeholder="ask your question...",
+                    placeholder="ask your question, paste or drop images to ask about them...",
+                    on:paste=move |e: web_sys::Event| {
+                        let files = e
+                            .dyn_ref::<web_sys::ClipboardEvent>()
+                            .and_then(|e| e.clipboard_data())
+                            .and_then(|data| data.files());
+                        attach_images(ctx, files, attachments);
+                    },
+                    on:dragover=|e: web_sys::Event| e.prevent_default(),
+                    on:drop=move |e: web_sys::Event| {
+                        e.prevent_default();
+                        let files = e
+                            .dyn_ref::<web_sys::DragEvent>()
+                            .and_then(|e| e.data_transfer())
+                            .and_then(|data| data.files());
+                        attach_images(ctx, files, attachments);
+                    },
                     bind:value=question)
             }
         }
@@ -693,54 +2332,84 @@ fn Voice<G: Html>(ctx: Scope) -> View<G> {
     }
 }
 
+// the entry of `all` named `name`
+fn pick<T: Copy>(all: &[T], name: &str, as_str: fn(&T) -> &'static str) -> Option<T> {
+    all.iter().copied().find(|t| as_str(t) == name)
+}
+
 #[component]
 fn ImageGen<G: Html>(ctx: Scope) -> View<G> {
     let prompt = create_signal(ctx, "".to_string());
     let request_new = create_signal(ctx, None);
     let response: &Signal<Option<GenerateImageResult>> = create_signal(ctx, None);
+    let message = create_signal(ctx, "".to_string());
+
+    // names as the api takes them, empty ones leave the choice to the api
+    let model = create_signal(ctx, ImageModel::DallE2.as_str().to_string());
+    let size = create_signal(ctx, "".to_string());
+    let quality = create_signal(ctx, "".to_string());
+    let style = create_signal(ctx, "".to_string());
+    let format = create_signal(ctx, ImageFormat::B64Json.as_str().to_string());
+    let count = create_signal(ctx, "1".to_string());
+
+    // only what the picked model takes is used, the rest is kept for switching back
+    let params = create_memo(ctx, move || {
+        let model = pick(ImageModel::ALL, &model.get(), ImageModel::as_str).unwrap_or(ImageModel::DallE2);
+        let mut params = GenerateImageParams::new(model, prompt.get().to_string());
+        if let Some(size) = pick(model.sizes(), &size.get(), ImageSize::as_str) {
+            params.size = size;
+        }
+        if model.has_quality_and_style() {
+            params.quality = pick(ImageQuality::ALL, &quality.get(), ImageQuality::as_str);
+            params.style = pick(ImageStyle::ALL, &style.get(), ImageStyle::as_str);
+        }
+        if let Some(format) = pick(ImageFormat::ALL, &format.get(), ImageFormat::as_str) {
+            params.response_format = format;
+        }
+        params.n = count.get().parse().unwrap_or(0);
+        params
+    });
+    let sizes = create_memo(ctx, move || params.get().model.sizes().to_vec());
+    let models = create_signal(ctx, ImageModel::ALL.to_vec());
+    let qualities = create_signal(ctx, ImageQuality::ALL.to_vec());
+    let styles = create_signal(ctx, ImageStyle::ALL.to_vec());
+    let formats = create_signal(ctx, ImageFormat::ALL.to_vec());
+    let problem = create_memo(ctx, move || match params.get().validate() {
+        // an empty prompt is only a problem once sending
+        Err(e) if !prompt.get().is_empty() => e,
+        _ => "".to_string(),
+    });
 
-    let imgdata = create_memo(ctx, || {
+    let images = create_memo(ctx, move || {
         response
             .get()
             .as_ref()
-            .clone()
-            .map(|r| r.data.get(0).unwrap().b64_json.clone())
-            .unwrap_or("".to_string())
+            .as_ref()
+            .map(|r| r.data.iter().filter_map(|d| Some((d.src()?, d.revised_prompt.clone().unwrap_or_default()))).collect::<Vec<_>>())
+            .unwrap_or_default()
     });
 
     create_effect(ctx, move || {
         request_new.track();
 
-        if prompt.get_untracked().is_empty() {
+        if request_new.get_untracked().is_none() {
             return;
         }
 
-        if request_new.get_untracked().is_none() {
+        let params = params.get_untracked().as_ref().clone();
+        if let Err(e) = params.validate() {
+            message.set(e);
             return;
         }
 
         sycamore::futures::spawn_local_scoped(ctx, async move {
             wasm_log!("request image");
-            let params = GenerateImageParams {
-                prompt: prompt.get_untracked().to_string(),
-                n: 1,
-                response_format: "b64_json".to_string(),
-                size: "512x512".to_string(),
-            };
-
-            match serde_wasm_bindgen::to_value(&params) {
-                Ok(params) => match openai_generate_image(params).await {
-                    Ok(resp) => {
-                        let resp =
-                            serde_wasm_bindgen::from_value::<GenerateImageResult>(resp).unwrap();
-                        response.set(Some(resp));
-                    }
-                    Err(e) => {
-                        wasm_log!("{:?}", e);
-                    }
-                },
+            message.set("".to_string());
+            match call(ipc::GenerateImage { params }).await {
+                Ok(resp) => response.set(Some(resp)),
                 Err(e) => {
-                    wasm_log!("{:?}", e);
+                    wasm_log!("{}", e);
+                    message.set(e);
                 }
             }
         });
@@ -748,11 +2417,61 @@ fn ImageGen<G: Html>(ctx: Scope) -> View<G> {
 
     view! { ctx,
         div(class="flex flex-col w-full") {
+            div(class="flex flex-row flex-wrap gap-2 mb-2 text-xs items-center") {
+                select(class="select select-bordered select-xs", bind:value=model) {
+                    Indexed(
+                        iterable=models,
+                        view=|ctx, m| view! { ctx, option(value=m.as_str()) { (m.as_str()) } },
+                    )
+                }
+                select(class="select select-bordered select-xs", bind:value=size) {
+                    Indexed(
+                        iterable=sizes,
+                        view=|ctx, s| view! { ctx, option(value=s.as_str()) { (s.as_str()) } },
+                    )
+                }
+                (if params.get().model.has_quality_and_style() {
+                    view! { ctx,
+                        select(class="select select-bordered select-xs", bind:value=quality) {
+                            option(value="") { "default quality" }
+                            Indexed(
+                                iterable=qualities,
+                                view=|ctx, q| view! { ctx, option(value=q.as_str()) { (q.as_str()) } },
+                            )
+                        }
+                        select(class="select select-bordered select-xs", bind:value=style) {
+                            option(value="") { "default style" }
+                            Indexed(
+                                iterable=styles,
+                                view=|ctx, s| view! { ctx, option(value=s.as_str()) { (s.as_str()) } },
+                            )
+                        }
+                    }
+                } else {
+                    view! { ctx, }
+                })
+                select(class="select select-bordered select-xs", bind:value=format) {
+                    Indexed(
+                        iterable=formats,
+                        view=|ctx, f| view! { ctx, option(value=f.as_str()) { (f.as_str()) } },
+                    )
+                }
+                label { "images" }
+                input(type="number", class="input input-bordered input-xs w-16", min="1",
+                    max=params.get().model.max_n().to_string(), bind:value=count)
+                span(class="text-error") { (problem.get()) }
+            }
             TextArea(placeholder="image prompt...".to_string(),
                 content=prompt,
                 request_new=request_new)
-            div(class="bg-slate-300 flex-1 flex flex-row items-center justify-center") {
-                img(class="object-scale-down", src=format!("data:image/png;base64,{}", imgdata))
+            label(class="text-sm") { (message.get()) }
+            div(class="bg-slate-300 flex-1 flex flex-row flex-wrap items-center justify-center gap-2 overflow-y-auto") {
+                Indexed(
+                    iterable=images,
+                    view=|ctx, (src, revised)| view! { ctx,
+                        img(class="object-scale-down max-h-full", src=src, title=revised)
+                    },
+                )
             }
         }
     }
@@ -822,6 +2541,471 @@ fn Home<G: Html>(ctx: Scope) -> View<G> {
     }
 }
 
+#[component]
+fn UnlockPrompt<G: Html>(ctx: Scope) -> View<G> {
+    let locked = create_signal(ctx, false);
+    let passphrase = create_signal(ctx, "".to_string());
+    let error = create_signal(ctx, "".to_string());
+
+    sycamore::futures::spawn_local_scoped(ctx, async move {
+        match call(ipc::GetEncryptionStatus {}).await {
+            Ok(status) => locked.set(status.locked),
+            Err(e) => wasm_log!("encryption_status: {}", e),
+        }
+    });
+
+    let on_unlock = move |_: web_sys::Event| {
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            match call(ipc::Unlock { passphrase: passphrase.get_untracked().to_string() }).await {
+                Ok(()) => {
+                    passphrase.set("".to_string());
+                    error.set("".to_string());
+                    locked.set(false);
+                    // views rendered while locked came up empty
+                    navigate("/");
+                }
+                Err(e) => error.set(e),
+            }
+        });
+    };
+
+    view! { ctx,
+        (if *locked.get() {
+            view! { ctx,
+                div(class="modal modal-open") {
+                    div(class="modal-box flex flex-col") {
+                        h3(class="font-bold text-lg") { "Conversations are encrypted" }
+                        input(class="input input-bordered my-2", type="password",
+                            placeholder="passphrase", bind:value=passphrase)
+                        label(class="text-xs text-error") { (error.get()) }
+                        div(class="modal-action") {
+                            button(class="btn btn-primary", on:click=on_unlock) { "unlock" }
+                        }
+                    }
+                }
+            }
+        } else {
+            view! { ctx, }
+        })
+    }
+}
+
+#[component]
+fn Security<G: Html>(ctx: Scope) -> View<G> {
+    let status = create_signal(ctx, EncryptionStatus::default());
+    let passphrase = create_signal(ctx, "".to_string());
+    let confirm = create_signal(ctx, "".to_string());
+    let old_passphrase = create_signal(ctx, "".to_string());
+    let secret = create_signal(ctx, "".to_string());
+    let sealed = create_signal(ctx, "".to_string());
+    let message = create_signal(ctx, "".to_string());
+
+    let refresh = move || {
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            match call(ipc::GetEncryptionStatus {}).await {
+                Ok(s) => status.set(s),
+                Err(e) => message.set(e),
+            }
+        });
+    };
+    refresh();
+
+    let on_enable = move |_: web_sys::Event| {
+        if *passphrase.get_untracked() != *confirm.get_untracked() {
+            message.set("passphrases don't match".to_string());
+            return;
+        }
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            message.set("encrypting...".to_string());
+            match call(ipc::EnableEncryption { passphrase: passphrase.get_untracked().to_string() }).await {
+                Ok(()) => message.set("encryption enabled".to_string()),
+                Err(e) => message.set(e),
+            }
+            passphrase.set("".to_string());
+            confirm.set("".to_string());
+            refresh();
+        });
+    };
+
+    let on_rekey = move |_: web_sys::Event| {
+        if *passphrase.get_untracked() != *confirm.get_untracked() {
+            message.set("passphrases don't match".to_string());
+            return;
+        }
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            message.set("re-encrypting...".to_string());
+            let rekey = ipc::Rekey {
+                old_passphrase: old_passphrase.get_untracked().to_string(),
+                new_passphrase: passphrase.get_untracked().to_string(),
+            };
+            match call(rekey).await {
+                Ok(()) => message.set("passphrase changed".to_string()),
+                Err(e) => message.set(e),
+            }
+            old_passphrase.set("".to_string());
+            passphrase.set("".to_string());
+            confirm.set("".to_string());
+        });
+    };
+
+    let on_seal = move |_: web_sys::Event| {
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            match call(ipc::EncryptSecret { value: secret.get_untracked().to_string() }).await {
+                Ok(value) => sealed.set(value),
+                Err(e) => message.set(e),
+            }
+            secret.set("".to_string());
+        });
+    };
+
+    view! { ctx,
+        div(class="flex flex-col w-96 gap-2") {
+            (if status.get().enabled {
+                view! { ctx,
+                    h3(class="font-bold") { "Change passphrase" }
+                    input(class="input input-bordered input-sm", type="password",
+                        placeholder="current passphrase", bind:value=old_passphrase)
+                    input(class="input input-bordered input-sm", type="password",
+                        placeholder="new passphrase", bind:value=passphrase)
+                    input(class="input input-bordered input-sm", type="password",
+                        placeholder="repeat new passphrase", bind:value=confirm)
+                    button(class="btn btn-sm", on:click=on_rekey) { "re-encrypt" }
+
+                    h3(class="font-bold mt-4") { "Encrypt an api key" }
+                    label(class="text-xs") { "paste the result into api_keys of the config file" }
+                    input(class="input input-bordered input-sm", type="password",
+                        placeholder="sk-...", bind:value=secret)
+                    button(class="btn btn-sm", on:click=on_seal) { "encrypt" }
+                    input(class="input input-bordered input-sm", readonly=true, bind:value=sealed)
+                }
+            } else {
+                view! { ctx,
+                    h3(class="font-bold") { "Encrypt conversations" }
+                    label(class="text-xs") { "the passphrase will be asked for at every start and can't be recovered" }
+                    input(class="input input-bordered input-sm", type="password",
+                        placeholder="passphrase", bind:value=passphrase)
+                    input(class="input input-bordered input-sm", type="password",
+                        placeholder="repeat passphrase", bind:value=confirm)
+                    button(class="btn btn-sm", on:click=on_enable) { "enable encryption" }
+                }
+            })
+            label(class="text-sm") { (message.get()) }
+        }
+    }
+}
+
+#[component]
+fn Backup<G: Html>(ctx: Scope) -> View<G> {
+    let backup_path = create_signal(ctx, "".to_string());
+    let include_secrets = create_signal(ctx, false);
+    let restore_path = create_signal(ctx, "".to_string());
+    let snapshots = create_signal(ctx, Vec::<SnapshotInfo>::new());
+    let message = create_signal(ctx, "".to_string());
+
+    let refresh = move || {
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            match call(ipc::ListSnapshots {}).await {
+                Ok(v) => snapshots.set(v),
+                Err(e) => message.set(e),
+            }
+        });
+    };
+    refresh();
+
+    let on_backup = move |_: web_sys::Event| {
+        if backup_path.get_untracked().is_empty() {
+            return;
+        }
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            let path = backup_path.get_untracked().to_string();
+            let backup = ipc::CreateBackup { path: path.clone(), include_secrets: *include_secrets.get_untracked() };
+            match call(backup).await {
+                Ok(()) => message.set(format!("saved to {}", path)),
+                Err(e) => message.set(e),
+            }
+        });
+    };
+
+    let restore = move |path: String| {
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            message.set("restoring...".to_string());
+            match call(ipc::RestoreBackup { path }).await {
+                Ok(summary) => message.set(format!(
+                    "restored {} conversations, {} prompts and {} files, restart to apply settings",
+                    summary.conversations, summary.prompts, summary.files
+                )),
+                Err(e) => message.set(e),
+            }
+            refresh();
+        });
+    };
+
+    let on_restore = move |_: web_sys::Event| {
+        if !restore_path.get_untracked().is_empty() {
+            restore(restore_path.get_untracked().to_string());
+        }
+    };
+
+    let on_snapshot = move |_: web_sys::Event| {
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            if let Err(e) = call(ipc::TakeSnapshot {}).await {
+                message.set(e);
+            }
+            refresh();
+        });
+    };
+
+    view! { ctx,
+        div(class="flex flex-col w-96 gap-2") {
+            h3(class="font-bold") { "Create backup" }
+            input(class="input input-bordered input-sm",
+                placeholder="archive path, e.g. ~/chatgpt-backup.json.gz", bind:value=backup_path)
+            label(class="label cursor-pointer") {
+                span(class="label-text text-xs") { "include api keys" }
+                input(type="checkbox", class="checkbox checkbox-xs", bind:checked=include_secrets)
+            }
+            button(class="btn btn-sm", on:click=on_backup) { "backup" }
+
+            h3(class="font-bold mt-4") { "Restore" }
+            input(class="input input-bordered input-sm",
+                placeholder="archive path", bind:value=restore_path)
+            button(class="btn btn-sm", on:click=on_restore) { "restore" }
+
+            h3(class="font-bold mt-4") { "Snapshots" }
+            button(class="btn btn-outline btn-xs", on:click=on_snapshot) { "snapshot now" }
+            ul(class="text-xs") {
+                Keyed(
+                    iterable=snapshots,
+                    view=move |ctx, snapshot| {
+                        let created = js_sys::Date::new(&JsValue::from_f64(snapshot.created_at as f64));
+                        let created = String::from(created.to_locale_string("default", &JsValue::UNDEFINED));
+                        let path = snapshot.path.clone();
+                        view! { ctx,
+                            li(class="flex flex-row justify-between") {
+                                span(title=snapshot.path.clone()) {
+                                    (format!("{} ({} KB)", created, snapshot.size / 1024))
+                                }
+                                button(class="btn btn-ghost btn-xs", on:click=move |_| restore(path.clone())) { "restore" }
+                            }
+                        }
+                    },
+                    key=|s| s.created_at,
+                )
+            }
+            label(class="text-sm") { (message.get()) }
+
+            Retention {}
+            StorageLocation {}
+        }
+    }
+}
+
+fn describe_policy(policy: &RetentionPolicy) -> String {
+    if !policy.is_enabled() {
+        return "conversations are kept forever, set `[retention]` in the settings to change it".to_string();
+    }
+
+    let mut rules = vec![];
+    if policy.max_age_days > 0 {
+        rules.push(format!("older than {} days", policy.max_age_days));
+    }
+    if policy.max_total_bytes > 0 {
+        rules.push(format!("beyond {} KB in total", policy.max_total_bytes / 1024));
+    }
+    let mut kept = vec![];
+    if policy.keep_pinned {
+        kept.push("pinned");
+    }
+    if policy.keep_tagged {
+        kept.push("tagged");
+    }
+
+    let action = match policy.action {
+        RetentionAction::Archive => "archived",
+        RetentionAction::Delete => "deleted",
+    };
+    let mut text = format!("conversations {} are {}", rules.join(" or "), action);
+    if !kept.is_empty() {
+        text.push_str(&format!(", {} ones are kept", kept.join(" and ")));
+        if policy.max_total_bytes > 0 {
+            text.push_str(" and left out of the total");
+        }
+    }
+    text
+}
+
+#[component]
+fn Retention<G: Html>(ctx: Scope) -> View<G> {
+    let policy = create_signal(ctx, "".to_string());
+    let total = create_signal(ctx, 0u64);
+    let candidates = create_signal(ctx, Vec::<PurgeCandidate>::new());
+    let message = create_signal(ctx, "".to_string());
+
+    let refresh = move || {
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            match call(ipc::GetRetentionPreview {}).await {
+                Ok(preview) => {
+                    policy.set(describe_policy(&preview.policy));
+                    total.set(preview.total_size);
+                    candidates.set(preview.candidates);
+                }
+                Err(e) => message.set(e),
+            }
+        });
+    };
+    refresh();
+
+    let on_purge = move |_: web_sys::Event| {
+        let n = candidates.get_untracked().len();
+        if n == 0 {
+            return;
+        }
+        let confirmed = window()
+            .and_then(|w| w.confirm_with_message(&format!("purge {} conversations now?", n)).ok())
+            .unwrap_or(false);
+        if !confirmed {
+            return;
+        }
+
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            match call(ipc::ApplyRetention {}).await {
+                Ok(summary) => message.set(match summary.archive {
+                    Some(path) => format!("purged {}, archived to {}", summary.purged, path),
+                    None => format!("purged {}", summary.purged),
+                }),
+                Err(e) => message.set(e),
+            }
+            refresh();
+        });
+    };
+
+    view! { ctx,
+        h3(class="font-bold mt-4") { "Retention" }
+        p(class="text-xs") { (policy.get()) }
+        p(class="text-xs") {
+            (format!("{} KB stored, {} conversations would be purged now", *total.get() / 1024, candidates.get().len()))
+        }
+        ul(class="text-xs max-h-48 overflow-y-auto") {
+            Keyed(
+                iterable=candidates,
+                view=move |ctx, candidate| {
+                    let updated = js_sys::Date::new(&JsValue::from_f64(candidate.updated_at as f64));
+                    let updated = String::from(updated.to_locale_string("default", &JsValue::UNDEFINED));
+                    let reason = match candidate.reason {
+                        PurgeReason::Age => "too old",
+                        PurgeReason::Size => "over size",
+                    };
+                    let name = candidate.title.clone().unwrap_or_else(|| candidate.id.0.to_string());
+                    view! { ctx,
+                        li(class="flex flex-row justify-between gap-2") {
+                            a(class="truncate", href=format!("/chats/{}", candidate.id.0)) { (name) }
+                            span(class="whitespace-nowrap") {
+                                (format!("{}, {} KB, {}", updated, candidate.size / 1024, reason))
+                            }
+                        }
+                    }
+                },
+                key=|c| c.id,
+            )
+        }
+        div(class="flex flex-row gap-2") {
+            button(class="btn btn-outline btn-xs", on:click=move |_| refresh()) { "preview" }
+            button(class="btn btn-error btn-xs", on:click=on_purge) { "purge now" }
+        }
+        label(class="text-sm") { (message.get()) }
+    }
+}
+
+fn describe_backend(backend: StorageBackend) -> &'static str {
+    match backend {
+        StorageBackend::Memory => "memory, gone when the app quits",
+        StorageBackend::Sled => "sled database in the config dir",
+    }
+}
+
+#[component]
+fn StorageLocation<G: Html>(ctx: Scope) -> View<G> {
+    let info = create_signal(ctx, None::<StorageInfo>);
+    let migration = create_signal(ctx, None::<MigrationProgress>);
+    let message = create_signal(ctx, "".to_string());
+
+    let refresh = move || {
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            match call(ipc::GetStorageInfo {}).await {
+                Ok(v) => info.set(Some(v)),
+                Err(e) => message.set(e),
+            }
+        });
+    };
+    refresh();
+
+    backend_listener(ctx, MIGRATION_EVENT, move |p: MigrationProgress| migration.set(Some(p)));
+
+    let migrate = move |from: StorageBackend, to: StorageBackend| {
+        if to == StorageBackend::Memory {
+            let confirmed = window()
+                .and_then(|w| w.confirm_with_message("conversations kept in memory are lost when the app quits, move anyway?").ok())
+                .unwrap_or(false);
+            if !confirmed {
+                return;
+            }
+        }
+
+        message.set("".to_string());
+        sycamore::futures::spawn_local_scoped(ctx, async move {
+            match call(ipc::MigrateStorage { from, to }).await {
+                Ok(moved) => message.set(format!("moved {} conversations", moved)),
+                Err(e) => message.set(e),
+            }
+            migration.set(None);
+            refresh();
+        });
+    };
+
+    let targets = create_memo(ctx, || {
+        info.get()
+            .as_ref()
+            .as_ref()
+            .map(|info| {
+                info.available
+                    .iter()
+                    .filter(|b| **b != info.current)
+                    .map(|to| (info.current, *to))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    });
+
+    view! { ctx,
+        h3(class="font-bold mt-4") { "Storage" }
+        p(class="text-xs") {
+            (info.get().as_ref().as_ref().map(|info| format!("conversations are kept in {}", describe_backend(info.current))).unwrap_or_default())
+        }
+        div(class="flex flex-row gap-2") {
+            Keyed(
+                iterable=targets,
+                view=move |ctx, (from, to)| view! { ctx,
+                    button(class="btn btn-outline btn-xs", disabled=migration.get().is_some(), on:click=move |_| migrate(from, to)) {
+                        (format!("move to {}", describe_backend(to)))
+                    }
+                },
+                key=|(_, to)| *to,
+            )
+        }
+        (match *migration.get() {
+            Some(p) => {
+                let percent = if p.total == 0 { 100 } else { p.done * 100 / p.total };
+                view! { ctx,
+                    progress(class="progress w-56", value=percent.to_string(), max="100")
+                    label(class="text-xs") { (format!("{} of {}", p.done, p.total)) }
+                }
+            }
+            None => view! { ctx, },
+        })
+        label(class="text-sm") { (message.get()) }
+    }
+}
+
 #[component]
 fn Header<G: Html>(ctx: Scope) -> View<G> {
     view! { ctx,
@@ -837,6 +3021,8 @@ fn Header<G: Html>(ctx: Scope) -> View<G> {
                     li{a(href="/codeassist"){"CodeAssist"}}
                     li{a(href="/imgen"){"Imagen"}}
                     li{a(href="/voice"){"Voice"}}
+                    li{a(href="/security"){"Security"}}
+                    li{a(href="/backup"){"Backup"}}
                     li{a(href="/about"){"About"}}
                 }
             }
@@ -869,6 +3055,44 @@ where
     on_cleanup(ctx, move || drop(closure));
 }
 
+// call `f` with every change made to storage while `ctx` is alive
+fn storage_listener<'a, F>(ctx: Scope<'a>, f: F)
+where
+    F: FnMut(StorageEvent) + 'a,
+{
+    backend_listener(ctx, STORAGE_EVENT, f)
+}
+
+// call `f` with the payload of every `name` event the backend emits while `ctx` is alive
+fn backend_listener<'a, T, F>(ctx: Scope<'a>, name: &'static str, f: F)
+where
+    T: serde::de::DeserializeOwned,
+    F: FnMut(T) + 'a,
+{
+    let mut f = f;
+    let boxed: Box<dyn FnMut(JsValue) + 'a> = Box::new(move |payload| {
+        match serde_wasm_bindgen::from_value::<T>(payload) {
+            Ok(event) => f(event),
+            Err(e) => wasm_log!("{}: {:?}", name, e),
+        }
+    });
+    let handler: Box<dyn FnMut(JsValue) + 'static> = unsafe { std::mem::transmute(boxed) };
+    let closure = create_ref(ctx, Closure::wrap(handler));
+
+    let unlisten = create_signal(ctx, None::<js_sys::Function>);
+    sycamore::futures::spawn_local_scoped(ctx, async move {
+        match listen_event(name, closure.as_ref()).await {
+            Ok(stop) => unlisten.set(Some(stop.unchecked_into())),
+            Err(e) => wasm_log!("listen {}: {:?}", name, e),
+        }
+    });
+    on_cleanup(ctx, move || {
+        if let Some(stop) = unlisten.get_untracked().as_ref() {
+            let _ = stop.call0(&JsValue::NULL);
+        }
+    });
+}
+
 #[component]
 fn App<G: Html>(ctx: Scope) -> View<G> {
     window_event_listener(ctx, "load", || {
@@ -883,6 +3107,7 @@ fn App<G: Html>(ctx: Scope) -> View<G> {
     view! { ctx,
         div(class="h-screen bg-base-100 flex flex-col overflow-hidden") {
             Header()
+            UnlockPrompt()
 
             div(class="flex-1 flex flex-row overflow-y-auto h-full") {
                 Router(integration=HistoryIntegration::new(),
@@ -893,13 +3118,16 @@ fn App<G: Html>(ctx: Scope) -> View<G> {
                             div(class="app flex-1 flex m-4") {
                                 (match route.get().as_ref() {
                                     AppRoutes::ChatApp {id} => {
-                                        view!{cx, ChatApp(id=id.first().map(|r|r.clone()).unwrap_or("".to_string()))}
+                                        view!{cx, ChatApp(id=id.first().map(|r|r.clone()).unwrap_or("".to_string()),
+                                            focus=id.get(1).map(|r|r.clone()).unwrap_or("".to_string()))}
                                     },
                                     AppRoutes::About => view!{cx, About},
                                     AppRoutes::Home => view!{cx, Home},
                                     AppRoutes::GenImage => view!{cx, ImageGen},
                                     AppRoutes::CodeAssist => view!{cx, NotFound},
                                     AppRoutes::Voice => view!{cx, Voice},
+                                    AppRoutes::Security => view!{cx, Security},
+                                    AppRoutes::Backup => view!{cx, Backup},
                                     AppRoutes::NotFound => view!{cx, NotFound},
                                 })
                             }
@@ -932,24 +3160,21 @@ fn main() {
 
 #[wasm_bindgen(module = "/api.js")]
 extern "C" {
-    #[wasm_bindgen(js_name = invokeCompletion, catch)]
-    async fn openai_completion(id: JsValue, messages: JsValue) -> Result<JsValue, JsValue>;
-    #[wasm_bindgen(js_name = invokeStartConversation, catch)]
-    async fn openai_start_conversation(hint: Option<String>) -> Result<JsValue, JsValue>;
-    #[wasm_bindgen(js_name = invokeGetConversations, catch)]
-    async fn openai_get_conversations() -> Result<JsValue, JsValue>;
-    #[wasm_bindgen(js_name = invokeGetConversation, catch)]
-    async fn openai_get_conversation(id: JsValue) -> Result<JsValue, JsValue>;
-    #[wasm_bindgen(js_name = invokeGetTitle, catch)]
-    async fn openai_get_title(id: JsValue) -> Result<JsValue, JsValue>;
-    #[wasm_bindgen(js_name = invokeSetTitle, catch)]
-    async fn openai_set_title(id: JsValue, title: JsValue) -> Result<JsValue, JsValue>;
-    #[wasm_bindgen(js_name = invokeSuggestTitle, catch)]
-    async fn openai_suggest_title(id: JsValue) -> Result<JsValue, JsValue>;
-    #[wasm_bindgen(js_name = invokeBundledPrompts, catch)]
-    async fn openai_bundled_prompts() -> Result<JsValue, JsValue>;
-    #[wasm_bindgen(js_name = invokeGenerateImage, catch)]
-    async fn openai_generate_image(req: JsValue) -> Result<JsValue, JsValue>;
+    #[wasm_bindgen(js_name = invokeCommand, catch)]
+    async fn invoke_command(name: &str, args: JsValue) -> Result<JsValue, JsValue>;
+    #[wasm_bindgen(js_name = listenEvent, catch)]
+    async fn listen_event(name: &str, callback: &JsValue) -> Result<JsValue, JsValue>;
+    #[wasm_bindgen(js_name = readClipboard)]
+    async fn read_clipboard() -> JsValue;
+}
+
+/// run `command` on the backend, errors are the ones the backend gave
+async fn call<C: ipc::Command>(command: C) -> Result<C::Output, String> {
+    let args = serde_wasm_bindgen::to_value(&command).map_err(|e| e.to_string())?;
+    match invoke_command(C::NAME, args).await {
+        Ok(output) => serde_wasm_bindgen::from_value(output).map_err(|e| format!("{}: {}", C::NAME, e)),
+        Err(e) => Err(e.as_string().unwrap_or_else(|| format!("{:?}", e))),
+    }
 }
 
 #[wasm_bindgen]